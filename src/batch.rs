@@ -0,0 +1,172 @@
+use crate::processor::json::JsonFields;
+use serde::Deserialize;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchSourceType {
+    Applenotes,
+    Simplenote,
+    Notion,
+    Email,
+    Evernote,
+    Googlekeep,
+    Json,
+}
+
+/// One source listed in a batch config file.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct BatchSource {
+    pub source_type: BatchSourceType,
+    pub source_path: PathBuf,
+    /// for a `json` source, where the parts of its notes are found
+    #[serde(default)]
+    pub fields: Option<JsonFields>,
+}
+
+/// Load the list of sources from a batch config file, which is a JSON array like
+/// `[{"source_type": "simplenote", "source_path": "notes.json"}]`. A `json` source can add
+/// `"fields": {"title": "name"}` to say where its notes' parts are, as the `json` subcommand's
+/// options do; other sources can't.
+///
+/// Relative source paths are resolved against the directory containing the config file.
+pub fn load_config(config_file: &Path) -> Result<Vec<BatchSource>, Error> {
+    let text = fs::read_to_string(config_file)?;
+    let sources: Vec<BatchSource> = match serde_json::from_str(&text) {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "config_file: '{}' is not a valid batch config: {}",
+                    config_file.to_string_lossy(),
+                    e
+                ),
+            ))
+        }
+    };
+
+    if let Some(source) = sources
+        .iter()
+        .find(|s| s.fields.is_some() && s.source_type != BatchSourceType::Json)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "config_file: '{}' gives fields for '{}', only json sources have them",
+                config_file.to_string_lossy(),
+                source.source_path.to_string_lossy()
+            ),
+        ));
+    }
+
+    let config_dir = config_file.parent().unwrap_or_else(|| Path::new(""));
+    Ok(sources
+        .into_iter()
+        .map(|source| BatchSource {
+            source_path: config_dir.join(source.source_path),
+            ..source
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config_resolves_relative_paths() {
+        let sources = load_config(Path::new("test_data/batch.json")).unwrap();
+        assert_eq!(
+            BatchSource {
+                source_type: BatchSourceType::Simplenote,
+                source_path: PathBuf::from("test_data/simplenote-single.json"),
+                fields: None,
+            },
+            sources[0]
+        );
+        assert_eq!(
+            BatchSource {
+                source_type: BatchSourceType::Applenotes,
+                source_path: PathBuf::from("test_data/dir_you_can_write"),
+                fields: None,
+            },
+            sources[3]
+        );
+    }
+
+    #[test]
+    fn load_config_keeps_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("batch.json");
+        fs::write(
+            &config_file,
+            r#"[{"source_type": "simplenote", "source_path": "/somewhere/notes.json"}]"#,
+        )
+        .unwrap();
+        let sources = load_config(&config_file).unwrap();
+        assert_eq!(
+            PathBuf::from("/somewhere/notes.json"),
+            sources[0].source_path
+        );
+    }
+
+    #[test]
+    fn load_config_reads_every_source_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("batch.json");
+        fs::write(
+            &config_file,
+            r#"[
+                {"source_type": "evernote", "source_path": "notes.enex"},
+                {"source_type": "googlekeep", "source_path": "Takeout/Keep"},
+                {"source_type": "json", "source_path": "export.json",
+                 "fields": {"notes": "data.items", "title": "name"}}
+            ]"#,
+        )
+        .unwrap();
+        let sources = load_config(&config_file).unwrap();
+        let types: Vec<&BatchSourceType> = sources.iter().map(|s| &s.source_type).collect();
+        assert_eq!(
+            vec![
+                &BatchSourceType::Evernote,
+                &BatchSourceType::Googlekeep,
+                &BatchSourceType::Json
+            ],
+            types
+        );
+        assert_eq!(
+            Some(JsonFields {
+                notes: Some(String::from("data.items")),
+                title: String::from("name"),
+                ..JsonFields::default()
+            }),
+            sources[2].fields
+        );
+
+        fs::write(
+            &config_file,
+            r#"[{"source_type": "evernote", "source_path": "notes.enex", "fields": {}}]"#,
+        )
+        .unwrap();
+        let error = load_config(&config_file).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(format!("{}", error).ends_with("only json sources have them"));
+    }
+
+    #[test]
+    fn load_config_fails_for_unknown_source_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("batch.json");
+        fs::write(
+            &config_file,
+            r#"[{"source_type": "papyrus", "source_path": "notes.json"}]"#,
+        )
+        .unwrap();
+        let error = load_config(&config_file).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(format!("{}", error).contains("unknown variant `papyrus`"));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::processor::markdown::{write_file, Markdown};
+use crate::seen::content_hash;
+use crate::ConversionOptions;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Converted notes kept between runs, so a note which hasn't changed since it was last
+/// converted is taken from here instead of being converted again, along with any
+/// `transform_cmd` it would be run through. Each note is a JSON file named by a hash of its id,
+/// holding its modified date and the options it was converted with; it only counts while both
+/// still match.
+#[derive(Debug, PartialEq)]
+pub struct ConversionCache {
+    cache_dir: PathBuf,
+    options_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CacheEntry {
+    modified: String,
+    trashed: bool,
+    options: String,
+    markdown: Markdown,
+}
+
+impl ConversionCache {
+    /// Open the cache in `cache_dir`, creating the directory if it doesn't exist yet. Entries
+    /// made with options other than `options`, or by another version, are treated as missing.
+    pub fn open(cache_dir: &Path, options: &ConversionOptions) -> Result<ConversionCache, Error> {
+        fs::create_dir_all(cache_dir)?;
+        let options = format!("{} {:?}", env!("CARGO_PKG_VERSION"), options);
+        Ok(ConversionCache {
+            cache_dir: cache_dir.to_path_buf(),
+            options_hash: format!("{:016x}", content_hash(options.as_bytes())),
+        })
+    }
+
+    /// The note converted from the note `id` last modified at `modified`, if it is cached. An
+    /// entry which can't be read is a miss, to be replaced once the note is converted again.
+    pub fn get(&self, id: &str, modified: &str, trashed: bool) -> Option<Markdown> {
+        let text = fs::read_to_string(self.entry_file(id)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+        match entry.modified == modified
+            && entry.trashed == trashed
+            && entry.options == self.options_hash
+        {
+            true => Some(entry.markdown),
+            false => None,
+        }
+    }
+
+    /// Keep `markdown`, converted from the note `id` last modified at `modified`, replacing
+    /// what was cached for it before.
+    pub fn put(
+        &self,
+        id: &str,
+        modified: &str,
+        trashed: bool,
+        markdown: &Markdown,
+        options: &ConversionOptions,
+    ) -> Result<(), Error> {
+        let entry = CacheEntry {
+            modified: modified.to_string(),
+            trashed,
+            options: self.options_hash.clone(),
+            markdown: markdown.clone(),
+        };
+        let text = serde_json::to_string(&entry)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("JSON ERROR: {}", e)))?;
+        write_file(&self.entry_file(id), text.as_bytes(), options)
+    }
+
+    fn entry_file(&self, id: &str) -> PathBuf {
+        self.cache_dir
+            .join(format!("{:016x}.json", content_hash(id.as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_hits_only_on_same_modified_and_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions::default();
+        let cache = ConversionCache::open(&dir.path().join("cache"), &options).unwrap();
+        let md = Markdown::new("A note", "body");
+        assert_eq!(None, cache.get("abc", "2022-01-14T07:36:50.656Z", false));
+
+        cache
+            .put("abc", "2022-01-14T07:36:50.656Z", false, &md, &options)
+            .unwrap();
+        assert_eq!(
+            Some(md),
+            cache.get("abc", "2022-01-14T07:36:50.656Z", false)
+        );
+        assert_eq!(None, cache.get("abc", "2022-02-01T00:00:00.000Z", false));
+        assert_eq!(None, cache.get("abc", "2022-01-14T07:36:50.656Z", true));
+        assert_eq!(None, cache.get("def", "2022-01-14T07:36:50.656Z", false));
+
+        let other_options = ConversionOptions {
+            strip_title_line: true,
+            ..Default::default()
+        };
+        let reopened = ConversionCache::open(&dir.path().join("cache"), &other_options).unwrap();
+        assert_eq!(None, reopened.get("abc", "2022-01-14T07:36:50.656Z", false));
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time, so anything which depends on "now" can be tested with a fixed
+/// time instead.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real system time, used for conversions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock which is always at the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_clock_does_not_move() {
+        let time = DateTime::parse_from_rfc3339("2022-01-13T22:36:18.906Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = FixedClock(time);
+        assert_eq!(time, clock.now());
+        assert_eq!(clock.now(), clock.now());
+    }
+
+    #[test]
+    fn system_clock_is_now() {
+        let before = Utc::now();
+        let now = SystemClock.now();
+        assert!(before <= now && now <= Utc::now());
+    }
+}
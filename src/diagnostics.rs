@@ -0,0 +1,64 @@
+use crate::clock::Clock;
+use crate::ConversionOptions;
+use chrono::SecondsFormat;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Report a warning on stderr, and append it to the error log if one is set, at the time of
+/// `clock`. `note_id` identifies the note concerned in the log, for messages which don't already
+/// name it.
+pub fn warning(
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+    note_id: Option<&str>,
+    message: &str,
+) {
+    eprintln!("WARNING: {}", message);
+    append_to_log(options, clock, "WARNING", note_id, message);
+}
+
+/// Report an error which didn't stop the run on stdout, and append it to the error log if one
+/// is set. `clock` and `note_id` are as for `warning`. Errors for a note aren't printed when
+/// failures are summarized at the end of the run instead.
+pub fn error(options: &ConversionOptions, clock: &dyn Clock, note_id: Option<&str>, message: &str) {
+    if !(options.summary_failures && note_id.is_some()) {
+        println!("{}", message);
+    }
+    append_to_log(options, clock, "ERROR", note_id, message);
+}
+
+fn append_to_log(
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+    level: &str,
+    note_id: Option<&str>,
+    message: &str,
+) {
+    let log_file = match &options.error_log {
+        Some(f) => f,
+        None => return,
+    };
+    let note = match note_id {
+        Some(id) => format!(" note: '{}'", id),
+        None => String::new(),
+    };
+    let entry = format!(
+        "{} {}{}: {}",
+        clock.now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        level,
+        note,
+        message
+    );
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .and_then(|mut f| writeln!(f, "{}", entry));
+    if let Err(e) = written {
+        eprintln!(
+            "WARNING: error_log: '{}' could not be written: {}",
+            log_file.to_string_lossy(),
+            e
+        );
+    }
+}
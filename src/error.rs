@@ -0,0 +1,158 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+/// Why a conversion stopped, as returned by the crate's public functions. Each variant has a
+/// stable process exit code, see `exit_code`.
+///
+/// Within the processors errors are still `std::io::Error`, so they pass through `?` on file
+/// IO; a specific failure is carried inside one with `into_io` and recovered by `From`.
+#[derive(Debug)]
+pub enum Notes2mdError {
+    /// the source file or directory doesn't exist
+    SourceNotFound(String),
+    /// dest_dir doesn't exist or can't be written to
+    DestNotWritable(String),
+    /// the source isn't JSON in the shape of its export
+    InvalidJson(String),
+    /// the source isn't text in its encoding
+    NotUtf8(String),
+    /// a title can't be made into a file name
+    InvalidTitle(String),
+    /// an option, config file or path given can't be used
+    InvalidInput(String),
+    /// any other source data which can't be converted
+    InvalidData(String),
+    /// reading or writing failed for some other reason
+    Io(io::Error),
+}
+
+impl Notes2mdError {
+    /// The code notes2md exits with for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Notes2mdError::InvalidJson(_)
+            | Notes2mdError::NotUtf8(_)
+            | Notes2mdError::InvalidTitle(_)
+            | Notes2mdError::InvalidData(_) => 1,
+            Notes2mdError::InvalidInput(_) => 2,
+            Notes2mdError::SourceNotFound(_) => 3,
+            Notes2mdError::DestNotWritable(_) => 4,
+            Notes2mdError::Io(e) => match e.kind() {
+                ErrorKind::NotFound => 3,
+                ErrorKind::PermissionDenied => 4,
+                _ => 5,
+            },
+        }
+    }
+
+    /// This error carried inside an `io::Error` of `kind`, for code which returns those.
+    pub(crate) fn into_io(self, kind: ErrorKind) -> io::Error {
+        io::Error::new(kind, self)
+    }
+}
+
+impl fmt::Display for Notes2mdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notes2mdError::SourceNotFound(message)
+            | Notes2mdError::DestNotWritable(message)
+            | Notes2mdError::InvalidJson(message)
+            | Notes2mdError::NotUtf8(message)
+            | Notes2mdError::InvalidTitle(message)
+            | Notes2mdError::InvalidInput(message)
+            | Notes2mdError::InvalidData(message) => write!(f, "{}", message),
+            Notes2mdError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Notes2mdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Notes2mdError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Notes2mdError {
+    fn from(e: io::Error) -> Notes2mdError {
+        if !e.get_ref().is_some_and(|inner| inner.is::<Notes2mdError>()) {
+            return match e.kind() {
+                ErrorKind::InvalidData => Notes2mdError::InvalidData(e.to_string()),
+                ErrorKind::InvalidInput => Notes2mdError::InvalidInput(e.to_string()),
+                _ => Notes2mdError::Io(e),
+            };
+        }
+        let message = e.to_string();
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<Notes2mdError>())
+        {
+            Some(Ok(error)) => *error,
+            _ => Notes2mdError::InvalidData(message),
+        }
+    }
+}
+
+impl From<Notes2mdError> for io::Error {
+    /// Carry `e` back into the processors, as an `io::Error` of the kind its variant stands for.
+    fn from(e: Notes2mdError) -> io::Error {
+        let kind = match e {
+            Notes2mdError::Io(e) => return e,
+            Notes2mdError::SourceNotFound(_) => ErrorKind::NotFound,
+            Notes2mdError::DestNotWritable(_) => ErrorKind::PermissionDenied,
+            Notes2mdError::InvalidInput(_) => ErrorKind::InvalidInput,
+            Notes2mdError::InvalidJson(_)
+            | Notes2mdError::NotUtf8(_)
+            | Notes2mdError::InvalidTitle(_)
+            | Notes2mdError::InvalidData(_) => ErrorKind::InvalidData,
+        };
+        e.into_io(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carried_error_is_recovered() {
+        let carried = Notes2mdError::InvalidTitle(String::from("title: '' is not valid"))
+            .into_io(ErrorKind::InvalidData);
+        assert_eq!(ErrorKind::InvalidData, carried.kind());
+        assert_eq!("title: '' is not valid", carried.to_string());
+
+        let error = Notes2mdError::from(carried);
+        assert!(matches!(error, Notes2mdError::InvalidTitle(_)));
+        assert_eq!(1, error.exit_code());
+    }
+
+    #[test]
+    fn plain_io_errors_are_classified_by_kind() {
+        let error = Notes2mdError::from(io::Error::new(ErrorKind::InvalidInput, "bad option"));
+        assert!(matches!(error, Notes2mdError::InvalidInput(_)));
+        assert_eq!(2, error.exit_code());
+
+        let error = Notes2mdError::from(io::Error::from(ErrorKind::PermissionDenied));
+        assert!(matches!(error, Notes2mdError::Io(_)));
+        assert_eq!(4, error.exit_code());
+
+        let error = Notes2mdError::from(io::Error::from(ErrorKind::Interrupted));
+        assert_eq!(5, error.exit_code());
+    }
+
+    #[test]
+    fn error_survives_a_round_trip_through_io() {
+        let carried = io::Error::from(Notes2mdError::DestNotWritable(String::from("read only")));
+        assert_eq!(ErrorKind::PermissionDenied, carried.kind());
+        assert!(matches!(
+            Notes2mdError::from(carried),
+            Notes2mdError::DestNotWritable(_)
+        ));
+
+        let carried = io::Error::from(Notes2mdError::Io(io::Error::from(ErrorKind::Interrupted)));
+        assert_eq!(ErrorKind::Interrupted, carried.kind());
+        assert!(carried.get_ref().is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::processor::datetime::parse_note_timestamp;
+use crate::ConversionOptions;
+use chrono::{Duration, Utc};
+use std::io::{Error, ErrorKind};
+
+/// A parsed `--where` expression deciding which notes are converted, like
+/// `pinned && tag:work && !trashed`.
+///
+/// Terms are `pinned`, `trashed`, `markdown`, `tag:NAME` (a tag, ignoring case) and
+/// `text:WORD` (the content contains WORD, ignoring case). They combine with `!`, `&&`, `||`
+/// and parentheses, with the usual precedence.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    Pinned,
+    Trashed,
+    Markdown,
+    Tag(String),
+    Text(String),
+    Not(Box<FilterExpr>),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+}
+
+/// The parts of a note a `FilterExpr` can test.
+#[derive(Debug, Default)]
+pub struct NoteFields<'a> {
+    pub pinned: bool,
+    pub trashed: bool,
+    pub markdown: bool,
+    pub tags: &'a [String],
+    pub content: &'a str,
+}
+
+impl FilterExpr {
+    pub fn parse(expr: &str) -> Result<FilterExpr, Error> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            expr,
+            tokens: &tokens,
+            pos: 0,
+        };
+        let parsed = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(parsed),
+            Some(token) => Err(parser.error(&format!("unexpected '{}'", token))),
+        }
+    }
+
+    pub fn matches(&self, note: &NoteFields) -> bool {
+        match self {
+            FilterExpr::Pinned => note.pinned,
+            FilterExpr::Trashed => note.trashed,
+            FilterExpr::Markdown => note.markdown,
+            FilterExpr::Tag(tag) => note.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            FilterExpr::Text(word) => note.content.to_lowercase().contains(&word.to_lowercase()),
+            FilterExpr::Not(e) => !e.matches(note),
+            FilterExpr::And(a, b) => a.matches(note) && b.matches(note),
+            FilterExpr::Or(a, b) => a.matches(note) || b.matches(note),
+        }
+    }
+}
+
+/// Whether a note last modified at `modified` is within `options.recent` days of the clock's
+/// now. Every note is when the option isn't given. The date may be RFC 3339, or in `date_format`
+/// as a converted note's is. A note whose date can't be read is kept, with a warning; a missing
+/// date is left to the processor, which already warns about it.
+pub fn is_recent(
+    modified: Option<&str>,
+    note_id: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> bool {
+    let (days, modified) = match (options.recent, modified) {
+        (Some(days), Some(modified)) => (days, modified),
+        _ => return true,
+    };
+    match parse_note_timestamp(modified, options.date_format.as_deref()) {
+        Some(modified) => clock.now() - modified.with_timezone(&Utc) <= Duration::days(days.into()),
+        None => {
+            diagnostics::warning(
+                options,
+                clock,
+                None,
+                &format!(
+                    "note: '{}' has an unreadable modified date '{}', converting it anyway",
+                    note_id, modified
+                ),
+            );
+            true
+        }
+    }
+}
+
+/// Whether a note is small enough to convert: its content is at most `options.skip_large`
+/// bytes. A note over `options.warn_large` bytes is still converted, but named in a warning so
+/// it can be looked at.
+pub fn is_within_size(
+    title: &str,
+    content: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> bool {
+    let size = content.len();
+    let (limit, bytes, skipped) = match (options.skip_large, options.warn_large) {
+        (Some(skip), _) if size > skip => ("skip_large", skip, true),
+        (_, Some(warn)) if size > warn => ("warn_large", warn, false),
+        _ => return true,
+    };
+    diagnostics::warning(
+        options,
+        clock,
+        None,
+        &format!(
+            "note: '{}' is {} bytes, over the {} limit of {}{}",
+            title,
+            size,
+            limit,
+            bytes,
+            if skipped { ", skipping it" } else { "" }
+        ),
+    );
+    !skipped
+}
+
+/// Whether a note has the tags `options.require_tags` asks for. An untagged note is still
+/// converted, with a warning, unless `options.strict` is set; a note the processor gave
+/// `default_tag` is tagged.
+pub fn is_tagged(
+    note_id: &str,
+    tags: Option<&[String]>,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> bool {
+    if !options.require_tags || tags.is_some_and(|tags| !tags.is_empty()) {
+        return true;
+    }
+    if !options.strict {
+        diagnostics::warning(
+            options,
+            clock,
+            None,
+            &format!("note: '{}' has no tags, converting it anyway", note_id),
+        );
+    }
+    !options.strict
+}
+
+/// The error stopping a run at a note without tags, which `is_tagged` refused.
+pub fn untagged(note_id: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "note: '{}' has no tags, which require_tags requires; give untagged notes one with default_tag",
+            note_id
+        ),
+    )
+}
+
+fn tokenize(expr: &str) -> Result<Vec<String>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '!' | '(' | ')' => tokens.push(c.to_string()),
+            '&' | '|' => match chars.next() {
+                Some(next) if next == c => tokens.push(format!("{}{}", c, c)),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("where: '{}' has a single '{}', use '{}{}'", expr, c, c, c),
+                    ))
+                }
+            },
+            _ => {
+                let mut term = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "!()&|".contains(next) {
+                        break;
+                    }
+                    term.push(next);
+                    chars.next();
+                }
+                tokens.push(term);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    expr: &'a str,
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "where: '{}' is not a valid expression: {}",
+                self.expr, reason
+            ),
+        )
+    }
+
+    fn next_is(&self, token: &str) -> bool {
+        self.tokens.get(self.pos).map(String::as_str) == Some(token)
+    }
+
+    fn or(&mut self) -> Result<FilterExpr, Error> {
+        let mut expr = self.and()?;
+        while self.next_is("||") {
+            self.pos += 1;
+            expr = FilterExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<FilterExpr, Error> {
+        let mut expr = self.unary()?;
+        while self.next_is("&&") {
+            self.pos += 1;
+            expr = FilterExpr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<FilterExpr, Error> {
+        if self.next_is("!") {
+            self.pos += 1;
+            return Ok(FilterExpr::Not(Box::new(self.unary()?)));
+        }
+        if self.next_is("(") {
+            self.pos += 1;
+            let expr = self.or()?;
+            if !self.next_is(")") {
+                return Err(self.error("missing ')'"));
+            }
+            self.pos += 1;
+            return Ok(expr);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<FilterExpr, Error> {
+        let token = match self.tokens.get(self.pos) {
+            Some(t) => t,
+            None => return Err(self.error("it ends too soon")),
+        };
+        let term = match token.split_once(':') {
+            Some(("tag", tag)) if !tag.is_empty() => FilterExpr::Tag(tag.to_string()),
+            Some(("text", word)) if !word.is_empty() => FilterExpr::Text(word.to_string()),
+            None if token == "pinned" => FilterExpr::Pinned,
+            None if token == "trashed" => FilterExpr::Trashed,
+            None if token == "markdown" => FilterExpr::Markdown,
+            _ => return Err(self.error(&format!("unknown term '{}'", token))),
+        };
+        self.pos += 1;
+        Ok(term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use chrono::DateTime;
+
+    #[test]
+    fn is_recent_within_days_of_now() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = FixedClock(now);
+        let options = ConversionOptions {
+            recent: Some(7),
+            ..Default::default()
+        };
+        let recent = |modified| is_recent(modified, "id", &options, &clock);
+        assert!(recent(Some("2024-05-10T11:00:00Z")));
+        assert!(recent(Some("2024-05-03T12:00:00Z")));
+        assert!(!recent(Some("2024-05-03T11:59:59Z")));
+        assert!(!recent(Some("2023-01-01T00:00:00Z")));
+        assert!(recent(Some("last week")));
+        assert!(recent(None));
+        let day_first = ConversionOptions {
+            recent: Some(7),
+            date_format: Some(String::from("%d.%m.%Y %H:%M")),
+            ..Default::default()
+        };
+        assert!(is_recent(
+            Some("09.05.2024 08:00"),
+            "id",
+            &day_first,
+            &clock
+        ));
+        assert!(!is_recent(
+            Some("01.05.2024 08:00"),
+            "id",
+            &day_first,
+            &clock
+        ));
+        assert!(is_recent(
+            Some("2023-01-01T00:00:00Z"),
+            "id",
+            &ConversionOptions::default(),
+            &clock
+        ));
+    }
+
+    #[test]
+    fn is_within_size_warns_or_skips_large_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let error_log = dir.path().join("errors.log");
+        let warn = ConversionOptions {
+            warn_large: Some(10),
+            error_log: Some(error_log.clone()),
+            ..Default::default()
+        };
+        let clock = FixedClock(
+            DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        );
+        assert!(is_within_size("Small", "0123456789", &warn, &clock));
+        assert!(!error_log.exists());
+        assert!(is_within_size("Large", "0123456789a", &warn, &clock));
+        let logged = std::fs::read_to_string(&error_log).unwrap();
+        assert_eq!(
+            "2024-05-10T12:00:00Z WARNING: note: 'Large' is 11 bytes, over the warn_large limit of 10\n",
+            logged
+        );
+
+        let skip = ConversionOptions {
+            skip_large: Some(10),
+            ..Default::default()
+        };
+        assert!(is_within_size("Small", "0123456789", &skip, &clock));
+        assert!(!is_within_size("Large", "0123456789a", &skip, &clock));
+        assert!(is_within_size(
+            "Large",
+            "0123456789a",
+            &ConversionOptions::default(),
+            &clock
+        ));
+    }
+
+    #[test]
+    fn parse_respects_precedence() {
+        let expected = FilterExpr::Or(
+            Box::new(FilterExpr::And(
+                Box::new(FilterExpr::Pinned),
+                Box::new(FilterExpr::Not(Box::new(FilterExpr::Trashed))),
+            )),
+            Box::new(FilterExpr::Tag(String::from("work"))),
+        );
+        assert_eq!(
+            expected,
+            FilterExpr::parse("pinned && !trashed || tag:work").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_expressions() {
+        for (expr, reason) in [
+            ("pinned &&", "it ends too soon"),
+            ("(pinned || trashed", "missing ')'"),
+            ("pinned trashed", "unexpected 'trashed'"),
+            ("starred", "unknown term 'starred'"),
+            ("tag:", "unknown term 'tag:'"),
+        ] {
+            let error = FilterExpr::parse(expr).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, error.kind());
+            assert_eq!(
+                format!("where: '{}' is not a valid expression: {}", expr, reason),
+                format!("{}", error)
+            );
+        }
+        let error = FilterExpr::parse("pinned & trashed").unwrap_err();
+        assert_eq!(
+            "where: 'pinned & trashed' has a single '&', use '&&'",
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn expressions_select_notes() {
+        let work = vec![String::from("Work")];
+        let home = vec![String::from("home")];
+        let notes = [
+            NoteFields {
+                pinned: true,
+                tags: &work,
+                content: "Quarterly plan",
+                ..Default::default()
+            },
+            NoteFields {
+                pinned: true,
+                trashed: true,
+                tags: &work,
+                content: "Old plan",
+                ..Default::default()
+            },
+            NoteFields {
+                tags: &home,
+                content: "Groceries",
+                ..Default::default()
+            },
+            NoteFields {
+                content: "Plan the garden",
+                ..Default::default()
+            },
+        ];
+        let selected = |expr: &str| -> Vec<usize> {
+            let filter = FilterExpr::parse(expr).unwrap();
+            (0..notes.len())
+                .filter(|&i| filter.matches(&notes[i]))
+                .collect()
+        };
+
+        assert_eq!(vec![0], selected("pinned && tag:work && !trashed"));
+        assert_eq!(vec![0, 1, 3], selected("text:plan"));
+        assert_eq!(vec![2, 3], selected("!(tag:work || pinned)"));
+    }
+}
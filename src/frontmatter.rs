@@ -0,0 +1,140 @@
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::processor::markdown::{parse_markdown, MarkdownMeta};
+use crate::repair::find_markdown;
+use crate::ConversionOptions;
+use serde::Serialize;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// The front matter of one note, with the note's path relative to the directory it was found in.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NoteFrontMatter {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub meta: MarkdownMeta,
+}
+
+/// Read the front matter of the notes beneath `notes_dir`, in path order, leaving their bodies
+/// alone. Markdown files without front matter aren't notes and are left out; a note whose front
+/// matter can't be parsed is reported and left out.
+pub fn extract_front_matter(
+    notes_dir: &Path,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Vec<NoteFrontMatter>, Error> {
+    let mut note_files = Vec::new();
+    find_markdown(notes_dir, &mut note_files)?;
+    note_files.sort();
+
+    let mut extracted = Vec::new();
+    for note_file in note_files {
+        let text = fs::read_to_string(&note_file)?;
+        if !text.starts_with("---\n") {
+            continue;
+        }
+        let path = note_file
+            .strip_prefix(notes_dir)
+            .unwrap_or(&note_file)
+            .to_path_buf();
+        match parse_markdown(&text, options) {
+            Ok(markdown) => extracted.push(NoteFrontMatter {
+                path,
+                meta: markdown.meta,
+            }),
+            // on stderr, as stdout is the JSON the front matter is printed as
+            Err(e) => diagnostics::warning(
+                options,
+                clock,
+                None,
+                &format!(
+                    "file: '{}' has front matter which could not be read, leaving it out: {}",
+                    note_file.display(),
+                    e
+                ),
+            ),
+        }
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+
+    #[test]
+    fn extract_front_matter_of_two_notes() {
+        let notes_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            notes_dir.path().join("First.md"),
+            "---\ntitle: First\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-14T07:36:50.656Z\"\ntags:\n  - work\n---\nnot read\n",
+        )
+        .unwrap();
+        fs::create_dir(notes_dir.path().join("work")).unwrap();
+        fs::write(
+            notes_dir.path().join("work/Second.md"),
+            "---\ntitle: Second\ncreated: \"2022-01-15T10:00:00.000Z\"\nmodified: \"2022-01-15T10:00:00.000Z\"\npinned: true\n---\n",
+        )
+        .unwrap();
+        fs::write(notes_dir.path().join("README.md"), "# Not a note\n").unwrap();
+
+        let extracted = extract_front_matter(
+            notes_dir.path(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::json!([
+                {
+                    "path": "First.md",
+                    "title": "First",
+                    "created": "2022-01-13T22:36:18.906Z",
+                    "modified": "2022-01-14T07:36:50.656Z",
+                    "tags": ["work"]
+                },
+                {
+                    "path": "work/Second.md",
+                    "title": "Second",
+                    "created": "2022-01-15T10:00:00.000Z",
+                    "modified": "2022-01-15T10:00:00.000Z",
+                    "pinned": true
+                }
+            ]),
+            serde_json::to_value(&extracted).unwrap()
+        );
+    }
+
+    #[test]
+    fn extract_front_matter_leaves_out_unreadable_notes() {
+        let notes_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            notes_dir.path().join("Good.md"),
+            "---\ntitle: Good\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\n---\n",
+        )
+        .unwrap();
+        let broken = notes_dir.path().join("Broken.md");
+        fs::write(&broken, "---\ntitle: [unclosed\n---\n").unwrap();
+        let log_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            error_log: Some(log_dir.path().join("errors.log")),
+            ..Default::default()
+        };
+
+        let extracted = extract_front_matter(notes_dir.path(), &options, &SystemClock).unwrap();
+
+        assert_eq!(
+            vec![PathBuf::from("Good.md")],
+            extracted.into_iter().map(|n| n.path).collect::<Vec<_>>()
+        );
+        let log = fs::read_to_string(log_dir.path().join("errors.log")).unwrap();
+        assert!(log.contains(" WARNING: file: '"));
+        assert!(log.contains(&format!(
+            "{}' has front matter which could not be read",
+            broken.display()
+        )));
+    }
+}
@@ -1,10 +1,42 @@
+use std::fs;
 use std::io::{Error, ErrorKind};
-use std::{fs, path::PathBuf};
+use std::path::{Path, PathBuf};
 use tempfile::tempfile_in;
 
+pub mod batch;
+pub mod cache;
+pub mod clock;
+pub mod diagnostics;
+pub mod error;
+pub mod filter;
+pub mod frontmatter;
+pub mod links;
+pub mod manifest;
+pub mod options;
 pub mod processor;
+pub mod repair;
+pub mod scaffold;
+pub mod seen;
+pub mod space;
+pub mod stats;
+use batch::BatchSourceType;
+use clock::SystemClock;
+pub use error::Notes2mdError;
+pub use frontmatter::NoteFrontMatter;
+pub use options::{ConversionOptions, OutputFormat, TagCase};
 use processor::applenotes;
+use processor::email;
+use processor::evernote;
+use processor::googlekeep;
+use processor::json;
+pub use processor::json::JsonFields;
+use processor::markdown::{
+    validate_collision_format, validate_date_keys, validate_missing_segment,
+};
+use processor::notion;
 use processor::simplenote;
+pub use processor::sink::{dest_sink, FileSink, NoteSink};
+pub use stats::ConversionStats;
 
 #[derive(PartialEq)]
 enum SourceType {
@@ -12,87 +44,411 @@ enum SourceType {
     Directory,
 }
 
-pub fn process_applenotes(source_dir: PathBuf, dest_dir: PathBuf) -> Result<(), Error> {
-    let dv = verify_dest(&dest_dir);
-    if dv.is_err() {
-        dv
+pub fn process_applenotes(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_dir, SourceType::Directory)?;
+    if options.check_space {
+        verify_dest_space(&source_dir, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "applenotes", options)?;
+    applenotes::process(source_dir, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+pub fn process_email(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_dir, SourceType::Directory)?;
+    if options.check_space {
+        verify_dest_space(&source_dir, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "email", options)?;
+    email::process(source_dir, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Convert an Evernote `.enex` export, turning each note's ENML into markdown.
+pub fn process_evernote(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_file, SourceType::File)?;
+    if options.check_space {
+        verify_dest_space(&source_file, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "evernote", options)?;
+    evernote::process(source_file, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Convert a Google Takeout export of Keep notes, a directory of one JSON file per note.
+pub fn process_googlekeep(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_dir, SourceType::Directory)?;
+    if options.check_space {
+        verify_dest_space(&source_dir, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "googlekeep", options)?;
+    googlekeep::process(source_dir, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Convert a JSON file of notes in any shape, finding each part of a note where `fields` says.
+pub fn process_json(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    fields: &JsonFields,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_file, SourceType::File)?;
+    if options.check_space {
+        verify_dest_space(&source_file, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "json", options)?;
+    json::process(source_file, dest_dir, fields, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+pub fn process_notion(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_dir, SourceType::Directory)?;
+    if options.check_space {
+        verify_dest_space(&source_dir, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "notion", options)?;
+    notion::process(source_dir, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+pub fn process_simplenote(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_options(options)?;
+    verify_source(&source_file, SourceType::File)?;
+    if options.check_space {
+        verify_dest_space(&source_file, &dest_dir, options)?;
+    }
+    let dest_dir = source_dest_dir(dest_dir, "simplenote", options)?;
+    simplenote::process(source_file, dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Convert a Simplenote export, handing each note to `sink` instead of writing it into a
+/// directory, for embedding notes2md with notes kept elsewhere.
+pub fn process_simplenote_into(
+    source_file: PathBuf,
+    sink: &mut dyn NoteSink,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_options(options)?;
+    verify_source(&source_file, SourceType::File)?;
+    simplenote::process_into(source_file, sink, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Convert a Simplenote JSON export held in memory, for embedding notes2md without a
+/// filesystem. Returns each note's path relative to dest_dir with the text it would be written
+/// as; see `processor::simplenote::render` for what is left out.
+pub fn render_simplenote(
+    json: &str,
+    options: &ConversionOptions,
+) -> Result<Vec<(PathBuf, String)>, Notes2mdError> {
+    verify_options(options)?;
+    simplenote::render(json, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// The directory a processor should write into: `dest_dir` itself, or a subdirectory named
+/// for the source type when notes are kept apart by source.
+fn source_dest_dir(
+    dest_dir: PathBuf,
+    source_name: &str,
+    options: &ConversionOptions,
+) -> Result<PathBuf, Error> {
+    if options.subdir_by_source {
+        let subdir = dest_dir.join(source_name);
+        fs::create_dir_all(&subdir)?;
+        Ok(subdir)
     } else {
-        let sv = verify_source(&source_dir, SourceType::Directory);
-        if sv.is_err() {
-            sv
-        } else {
-            applenotes::process(source_dir, dest_dir)
+        Ok(dest_dir)
+    }
+}
+
+/// Process every source listed in a batch config file into the same `dest_dir`, so collisions
+/// between sources are resolved just as they are within one. A source which fails is reported
+/// and skipped rather than stopping the batch.
+pub fn process_batch(
+    config_file: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_source(&config_file, SourceType::File)?;
+    let sources = batch::load_config(&config_file)?;
+
+    let mut stats = ConversionStats::default();
+    let mut failed: usize = 0;
+    for source in &sources {
+        let source_path = source.source_path.clone();
+        let result = match source.source_type {
+            BatchSourceType::Applenotes => {
+                process_applenotes(source_path, dest_dir.clone(), options)
+            }
+            BatchSourceType::Simplenote => {
+                process_simplenote(source_path, dest_dir.clone(), options)
+            }
+            BatchSourceType::Notion => process_notion(source_path, dest_dir.clone(), options),
+            BatchSourceType::Email => process_email(source_path, dest_dir.clone(), options),
+            BatchSourceType::Evernote => process_evernote(source_path, dest_dir.clone(), options),
+            BatchSourceType::Googlekeep => {
+                process_googlekeep(source_path, dest_dir.clone(), options)
+            }
+            BatchSourceType::Json => process_json(
+                source_path,
+                dest_dir.clone(),
+                &source.fields.clone().unwrap_or_default(),
+                options,
+            ),
+        };
+        match result {
+            Ok(source_stats) => stats += source_stats,
+            Err(e) => {
+                failed += 1;
+                diagnostics::error(options, &SystemClock, None, &e.to_string());
+            }
         }
     }
+    println!(
+        "batch processed {} of {} sources from '{}'",
+        sources.len() - failed,
+        sources.len(),
+        config_file.to_string_lossy()
+    );
+    Ok(stats)
 }
 
-pub fn process_simplenote(source_file: PathBuf, dest_dir: PathBuf) -> Result<(), Error> {
-    let dv = verify_dest(&dest_dir);
-    if dv.is_err() {
-        dv
-    } else {
-        let sv = verify_source(&source_file, SourceType::File);
-        if sv.is_err() {
-            sv
-        } else {
-            simplenote::process(source_file, dest_dir)
+/// Rewrite the notes already in `dest_dir` as this version writes them; see
+/// `repair::repair_notes`.
+pub fn repair_notes(
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    repair::repair_notes(&dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// The front matter of the notes already in `dest_dir`, for indexing them; see
+/// `frontmatter::extract_front_matter`.
+pub fn front_matter(
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+) -> Result<Vec<NoteFrontMatter>, Notes2mdError> {
+    verify_source(&dest_dir, SourceType::Directory)?;
+    frontmatter::extract_front_matter(&dest_dir, options, &SystemClock).map_err(Notes2mdError::from)
+}
+
+/// Remove the notes listed in a `track_created` file, undoing the runs which wrote it. Only
+/// files inside `dest_dir` are removed; any other listed path is reported and left alone.
+pub fn undo_created(track_file: PathBuf, dest_dir: PathBuf) -> Result<(), Notes2mdError> {
+    verify_dest(&dest_dir)?;
+    verify_source(&track_file, SourceType::File)?;
+    let dest_dir = fs::canonicalize(&dest_dir)?;
+    let listed = fs::read_to_string(&track_file)?;
+
+    let mut paths = 0;
+    let mut removed = 0;
+    for line in listed.lines().filter(|l| !l.trim().is_empty()) {
+        paths += 1;
+        let path = Path::new(line);
+        match fs::canonicalize(path) {
+            Ok(p) if p.starts_with(&dest_dir) && p.is_dir() => match fs::remove_dir_all(&p) {
+                Ok(_) => removed += 1,
+                Err(e) => println!("file: '{}' could not be removed: {}", line, e),
+            },
+            Ok(p) if p.starts_with(&dest_dir) && p.is_file() => match fs::remove_file(&p) {
+                Ok(_) => removed += 1,
+                Err(e) => println!("file: '{}' could not be removed: {}", line, e),
+            },
+            Ok(_) => eprintln!(
+                "WARNING: file: '{}' is not a note in '{}', leaving it",
+                line,
+                dest_dir.to_string_lossy()
+            ),
+            Err(_) => eprintln!("WARNING: file: '{}' no longer exists", line),
         }
     }
+    println!(
+        "undo removed {} of {} files listed in '{}'",
+        removed,
+        paths,
+        track_file.to_string_lossy()
+    );
+    Ok(())
 }
 
-fn verify_dest(dest_dir: &PathBuf) -> Result<(), Error> {
-    let attr = fs::metadata(&dest_dir);
+/// Resolve `path` to an absolute form for use in error messages, so users see the path which
+/// actually failed rather than one relative to an unknown working directory.
+///
+/// A path which doesn't exist can't be canonicalized, so its parent is canonicalized instead
+/// and the file name re-attached. If that fails too, the path is shown as given.
+fn display_path(path: &Path) -> String {
+    let resolved = match fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(_) => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                match fs::canonicalize(parent) {
+                    Ok(p) => p.join(name),
+                    Err(_) => path.to_path_buf(),
+                }
+            }
+            _ => path.to_path_buf(),
+        },
+    };
+    resolved.to_string_lossy().to_string()
+}
+
+/// Check the options which aren't used until a note needs them, so a bad one stops the run
+/// before any note is converted instead of failing each note in turn.
+fn verify_options(options: &ConversionOptions) -> Result<(), Error> {
+    if let Some(segment) = &options.missing_segment {
+        validate_missing_segment(segment)?;
+    }
+    if let Some(format) = &options.collision_format {
+        validate_collision_format(format)?;
+    }
+    validate_date_keys(
+        options.created_key.as_deref(),
+        options.modified_key.as_deref(),
+    )?;
+    Ok(())
+}
+
+fn verify_dest(dest_dir: &Path) -> Result<(), Error> {
+    let attr = fs::metadata(dest_dir);
     match attr {
         Err(e) => match e.kind() {
-            ErrorKind::NotFound => Err(Error::new(
-                e.kind(),
-                format!("dest_dir: '{}' not found", dest_dir.to_string_lossy()),
-            )),
+            ErrorKind::NotFound => Err(Notes2mdError::DestNotWritable(format!(
+                "dest_dir: '{}' not found",
+                display_path(dest_dir)
+            ))
+            .into_io(e.kind())),
             _ => Err(e),
         },
         Ok(metadata) => match metadata.is_dir() {
-            true => match tempfile_in(&dest_dir) {
+            true => match tempfile_in(dest_dir) {
                 Err(e) => match e.kind() {
-                    ErrorKind::PermissionDenied => Err(Error::new(
-                        e.kind(),
-                        format!("dest_dir: '{}' not writable", dest_dir.to_string_lossy()),
-                    )),
+                    ErrorKind::PermissionDenied => Err(Notes2mdError::DestNotWritable(format!(
+                        "dest_dir: '{}' not writable",
+                        display_path(dest_dir)
+                    ))
+                    .into_io(e.kind())),
                     _ => Err(e),
                 },
                 Ok(_) => Ok(()),
             },
             false => Err(Error::new(
                 ErrorKind::InvalidInput,
-                format!(
-                    "dest_dir: '{}' must be a directory",
-                    dest_dir.to_string_lossy()
-                ),
+                format!("dest_dir: '{}' must be a directory", display_path(dest_dir)),
             )),
         },
     }
 }
 
-fn verify_source(source_path: &PathBuf, source_type: SourceType) -> Result<(), Error> {
-    let attr = fs::metadata(&source_path);
+/// Estimate the space needed for the converted notes from the size of the source, and check
+/// `dest_dir` has that much free. Too little space is a warning, or an error when `strict`.
+fn verify_dest_space(
+    source_path: &Path,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+) -> Result<(), Error> {
+    let available = fs2::available_space(dest_dir)?;
+    check_space(source_size(source_path), available, dest_dir, options)
+}
+
+fn check_space(
+    required: u64,
+    available: u64,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+) -> Result<(), Error> {
+    if required <= available {
+        Ok(())
+    } else {
+        let message = format!(
+            "dest_dir: '{}' has {} bytes free but conversion needs about {} bytes",
+            display_path(dest_dir),
+            available,
+            required
+        );
+        if options.strict {
+            Err(Error::new(ErrorKind::InvalidInput, message))
+        } else {
+            diagnostics::warning(options, &SystemClock, None, &message);
+            Ok(())
+        }
+    }
+}
+
+/// Total size in bytes of a file, or of all files beneath a directory.
+fn source_size(source_path: &Path) -> u64 {
+    match fs::metadata(source_path) {
+        Ok(metadata) if metadata.is_dir() => match fs::read_dir(source_path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| source_size(&entry.path()))
+                .sum(),
+            Err(_) => 0,
+        },
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+fn verify_source(source_path: &Path, source_type: SourceType) -> Result<(), Error> {
+    let attr = fs::metadata(source_path);
     match attr {
         Err(e) => match e.kind() {
-            ErrorKind::NotFound => Err(Error::new(
-                e.kind(),
-                format!("source_path: '{}' not found", source_path.to_string_lossy()),
-            )),
+            ErrorKind::NotFound => Err(Notes2mdError::SourceNotFound(format!(
+                "source_path: '{}' not found",
+                display_path(source_path)
+            ))
+            .into_io(e.kind())),
             _ => Err(e),
         },
         Ok(metadata) => {
             if metadata.is_dir() {
                 if SourceType::Directory == source_type {
                     // read the directory to ensure it is permitted
-                    match fs::read_dir(&source_path) {
+                    match fs::read_dir(source_path) {
                         Err(e) => match e.kind() {
                             ErrorKind::PermissionDenied => Err(Error::new(
                                 e.kind(),
                                 format!(
                                     "source_path: '{}' directory access denied",
-                                    source_path.to_string_lossy()
+                                    display_path(source_path)
                                 ),
                             )),
                             _ => Err(e),
@@ -104,21 +460,21 @@ fn verify_source(source_path: &PathBuf, source_type: SourceType) -> Result<(), E
                         ErrorKind::InvalidInput,
                         format!(
                             "source_path: '{}' is a directory but file was required",
-                            source_path.to_string_lossy()
+                            display_path(source_path)
                         ),
                     ))
                 }
             } else if metadata.is_file() {
                 if SourceType::File == source_type {
                     // open file to ensure it is permitted
-                    let file = fs::File::open(&source_path);
+                    let file = fs::File::open(source_path);
                     match file {
                         Err(e) => match e.kind() {
                             ErrorKind::PermissionDenied => Err(Error::new(
                                 e.kind(),
                                 format!(
                                     "source_path: '{}' file access denied",
-                                    source_path.to_string_lossy()
+                                    display_path(source_path)
                                 ),
                             )),
                             _ => Err(e),
@@ -130,7 +486,7 @@ fn verify_source(source_path: &PathBuf, source_type: SourceType) -> Result<(), E
                         ErrorKind::InvalidInput,
                         format!(
                             "source_path: '{}' is a file but directory was required",
-                            source_path.to_string_lossy()
+                            display_path(source_path)
                         ),
                     ))
                 }
@@ -140,7 +496,7 @@ fn verify_source(source_path: &PathBuf, source_type: SourceType) -> Result<(), E
                     ErrorKind::InvalidInput,
                     format!(
                         "source_path: '{}' is not a file or directory",
-                        source_path.to_string_lossy()
+                        display_path(source_path)
                     ),
                 ))
             }
@@ -158,7 +514,7 @@ mod tests {
         let error = verify_dest(&non_existent_path).unwrap_err();
         assert_eq!(ErrorKind::NotFound, error.kind());
         assert_eq!(
-            "dest_dir: 'test_data/filename_which_does_not_exist' not found",
+            format!("dest_dir: '{}' not found", display_path(&non_existent_path)),
             format!("{}", error)
         );
     }
@@ -169,7 +525,10 @@ mod tests {
         let error = verify_dest(&non_existent_path).unwrap_err();
         assert_eq!(ErrorKind::InvalidInput, error.kind());
         assert_eq!(
-            "dest_dir: 'test_data/not_a_dir.txt' must be a directory",
+            format!(
+                "dest_dir: '{}' must be a directory",
+                display_path(&non_existent_path)
+            ),
             format!("{}", error)
         );
     }
@@ -180,7 +539,10 @@ mod tests {
         let error = verify_dest(&restricted_path).unwrap_err();
         assert_eq!(ErrorKind::PermissionDenied, error.kind());
         assert_eq!(
-            "dest_dir: 'test_data/dir_you_cant_write' not writable",
+            format!(
+                "dest_dir: '{}' not writable",
+                display_path(&restricted_path)
+            ),
             format!("{}", error)
         );
     }
@@ -191,10 +553,7 @@ mod tests {
         let error = verify_source(&path, SourceType::File).unwrap_err();
         assert_eq!(ErrorKind::NotFound, error.kind());
         assert_eq!(
-            format!(
-                "source_path: '{}' not found",
-                String::from(path.to_string_lossy())
-            ),
+            format!("source_path: '{}' not found", display_path(&path)),
             format!("{}", error)
         );
     }
@@ -207,7 +566,7 @@ mod tests {
         assert_eq!(
             format!(
                 "source_path: '{}' is not a file or directory",
-                String::from(path.to_string_lossy())
+                display_path(&path)
             ),
             format!("{}", error)
         );
@@ -221,7 +580,7 @@ mod tests {
         assert_eq!(
             format!(
                 "source_path: '{}' directory access denied",
-                String::from(path.to_string_lossy())
+                display_path(&path)
             ),
             format!("{}", error)
         );
@@ -233,10 +592,7 @@ mod tests {
         let error = verify_source(&path, SourceType::File).unwrap_err();
         assert_eq!(ErrorKind::PermissionDenied, error.kind());
         assert_eq!(
-            format!(
-                "source_path: '{}' file access denied",
-                String::from(path.to_string_lossy())
-            ),
+            format!("source_path: '{}' file access denied", display_path(&path)),
             format!("{}", error)
         );
     }
@@ -248,7 +604,7 @@ mod tests {
         assert_eq!(
             format!(
                 "source_path: '{}' is a directory but file was required",
-                String::from(path.to_string_lossy())
+                display_path(&path)
             ),
             format!("{}", error)
         );
@@ -262,9 +618,312 @@ mod tests {
         assert_eq!(
             format!(
                 "source_path: '{}' is a file but directory was required",
-                String::from(path.to_string_lossy())
+                display_path(&path)
             ),
             format!("{}", error)
         );
     }
+
+    #[test]
+    fn display_path_is_absolute_for_relative_missing_path() {
+        let path = PathBuf::from("./test_data/filename_which_does_not_exist");
+        let expected = fs::canonicalize("test_data")
+            .unwrap()
+            .join("filename_which_does_not_exist");
+        assert_eq!(expected.to_string_lossy(), display_path(&path));
+        assert!(Path::new(&display_path(&path)).is_absolute());
+    }
+
+    #[test]
+    fn display_path_falls_back_to_raw_path() {
+        let path = PathBuf::from("no_such_dir/no_such_file");
+        assert_eq!("no_such_dir/no_such_file", display_path(&path));
+    }
+
+    #[test]
+    fn verify_source_not_found_message_shows_absolute_path() {
+        let path = PathBuf::from("./test_data/filename_which_does_not_exist");
+        let error = verify_source(&path, SourceType::File).unwrap_err();
+        let message = format!("{}", error);
+        let expected = fs::canonicalize("test_data")
+            .unwrap()
+            .join("filename_which_does_not_exist");
+        assert_eq!(
+            format!("source_path: '{}' not found", expected.to_string_lossy()),
+            message
+        );
+        assert!(!message.contains("./test_data"));
+    }
+
+    #[test]
+    fn source_size_of_file_and_directory() {
+        assert_eq!(22, source_size(&PathBuf::from("test_data/happy.txt")));
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "12345").unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested/b.txt"), "123").unwrap();
+        assert_eq!(8, source_size(dir.path()));
+    }
+
+    #[test]
+    fn check_space_passes_when_enough_available() {
+        let path = PathBuf::from("test_data/out");
+        let strict = ConversionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        check_space(100, 100, &path, &strict).unwrap();
+    }
+
+    #[test]
+    fn check_space_warns_or_fails_when_not_enough_available() {
+        let path = PathBuf::from("test_data/out");
+        // only a warning unless strict
+        check_space(100, 10, &path, &ConversionOptions::default()).unwrap();
+
+        let strict = ConversionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = check_space(100, 10, &path, &strict).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        assert_eq!(
+            format!(
+                "dest_dir: '{}' has 10 bytes free but conversion needs about 100 bytes",
+                display_path(&path)
+            ),
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn process_rejects_unusable_options_before_writing() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let escaping = ConversionOptions {
+            missing_segment: Some(String::from("../../escaped")),
+            ..Default::default()
+        };
+        // without a placeholder every collision would get the same name
+        let unnumbered = ConversionOptions {
+            collision_format: Some(String::from("-copy")),
+            ..Default::default()
+        };
+        let clashing = ConversionOptions {
+            modified_key: Some(String::from("title")),
+            ..Default::default()
+        };
+        for options in [escaping, unnumbered, clashing] {
+            let error = process_simplenote(
+                PathBuf::from("test_data/simplenote-single.json"),
+                dest_dir.path().to_path_buf(),
+                &options,
+            )
+            .unwrap_err();
+            assert!(matches!(error, Notes2mdError::InvalidInput(_)));
+            assert_eq!(0, fs::read_dir(dest_dir.path()).unwrap().count());
+        }
+    }
+
+    #[test]
+    fn process_batch_writes_notes_from_every_source() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        process_batch(
+            PathBuf::from("test_data/batch.json"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+        assert!(dest_dir.path().join("Sample Document.md").exists());
+        assert!(dest_dir.path().join("process-active.md").exists());
+        assert!(dest_dir.path().join("process-trashed.md").exists());
+        // the same note listed twice collides across sources
+        assert!(dest_dir.path().join("Sample Document (1).md").exists());
+    }
+
+    #[test]
+    fn process_batch_writes_notes_from_newer_sources() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let keep_dir = source_dir.path().join("Keep");
+        fs::create_dir(&keep_dir).unwrap();
+        fs::write(
+            keep_dir.join("Jobs.json"),
+            r#"{"title": "Weekend jobs", "textContent": "mow the lawn", "userEditedTimestampUsec": 1642145810656000}"#,
+        )
+        .unwrap();
+        fs::write(
+            source_dir.path().join("export.json"),
+            r#"{"items": [{"name": "Named", "body": "text"}]}"#,
+        )
+        .unwrap();
+        let config_file = source_dir.path().join("batch.json");
+        fs::write(
+            &config_file,
+            format!(
+                r#"[
+                    {{"source_type": "evernote", "source_path": "{}"}},
+                    {{"source_type": "googlekeep", "source_path": "Keep"}},
+                    {{"source_type": "json", "source_path": "export.json",
+                      "fields": {{"notes": "items", "title": "name", "body": "body"}}}}
+                ]"#,
+                fs::canonicalize("test_data/evernote.enex")
+                    .unwrap()
+                    .display()
+            ),
+        )
+        .unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process_batch(
+            config_file,
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(0, stats.failed);
+        assert!(dest_dir.path().join("Groceries & errands.md").exists());
+        assert!(dest_dir.path().join("Weekend jobs.md").exists());
+        assert!(dest_dir.path().join("Named.md").exists());
+    }
+
+    #[test]
+    fn process_simplenote_with_subdir_by_source() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            subdir_by_source: true,
+            ..Default::default()
+        };
+        process_simplenote(
+            PathBuf::from("test_data/simplenote-single.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+        )
+        .unwrap();
+        assert!(dest_dir
+            .path()
+            .join("simplenote/Sample Document.md")
+            .exists());
+        assert!(!dest_dir.path().join("Sample Document.md").exists());
+    }
+
+    #[test]
+    fn process_simplenote_errors_say_what_failed() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let error = process_simplenote(
+            PathBuf::from("test_data/filename_which_does_not_exist"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Notes2mdError::SourceNotFound(_)));
+        assert_eq!(3, error.exit_code());
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(&source_file, "{\"activeNotes\": [").unwrap();
+        let error = process_simplenote(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Notes2mdError::InvalidJson(_)));
+        assert_eq!(1, error.exit_code());
+    }
+
+    #[test]
+    fn track_created_lists_written_notes_for_undo() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let track_dir = tempfile::tempdir().unwrap();
+        let track_file = track_dir.path().join("created.txt");
+        let pre_existing = dest_dir.path().join("process-active.md");
+        fs::write(&pre_existing, "not created by notes2md").unwrap();
+        let options = ConversionOptions {
+            track_created: Some(track_file.clone()),
+            ..Default::default()
+        };
+        process_simplenote(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+        )
+        .unwrap();
+
+        let mut written: Vec<PathBuf> = fs::read_dir(dest_dir.path())
+            .unwrap()
+            .map(|e| fs::canonicalize(e.unwrap().path()).unwrap())
+            .filter(|p| *p != fs::canonicalize(&pre_existing).unwrap())
+            .collect();
+        written.sort();
+        let mut listed: Vec<PathBuf> = fs::read_to_string(&track_file)
+            .unwrap()
+            .lines()
+            .map(PathBuf::from)
+            .collect();
+        listed.sort();
+        assert_eq!(written, listed);
+        assert_eq!(
+            "not created by notes2md",
+            fs::read_to_string(&pre_existing).unwrap()
+        );
+
+        undo_created(track_file, dest_dir.path().to_path_buf()).unwrap();
+        let remaining: Vec<PathBuf> = fs::read_dir(dest_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(vec![pre_existing], remaining);
+    }
+
+    #[test]
+    fn error_log_collects_warnings_and_note_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_file = dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [{"id": "untitled", "content": "", "lastModified": "2022-01-14T07:36:50.656Z"}]}"#,
+        )
+        .unwrap();
+        let dest_dir = dir.path().join("out");
+        fs::create_dir(&dest_dir).unwrap();
+        let error_log = dir.path().join("errors.log");
+        let options = ConversionOptions {
+            error_log: Some(error_log.clone()),
+            ..Default::default()
+        };
+        process_simplenote(source_file, dest_dir, &options).unwrap();
+
+        let logged = fs::read_to_string(&error_log).unwrap();
+        let entries: Vec<&str> = logged.lines().collect();
+        assert_eq!(2, entries.len());
+        assert!(entries[0].ends_with(
+            " WARNING: note: 'untitled' is missing a date, using created: '2022-01-14T07:36:50.656Z' modified: '2022-01-14T07:36:50.656Z'"
+        ));
+        assert!(
+            entries[1].ends_with(" ERROR note: 'untitled': title: '' is not valid for a filename")
+        );
+    }
+
+    #[test]
+    fn stats_json_counts_a_run() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process_batch(
+            PathBuf::from("test_data/batch.json"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        let json = stats.to_json(std::time::Duration::from_millis(1234), dest_dir.path());
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(4, parsed["converted"]);
+        assert_eq!(0, parsed["skipped"]);
+        assert_eq!(0, parsed["failed"]);
+        // the same note listed twice collides across sources
+        assert_eq!(1, parsed["collisions"]);
+        assert_eq!(1234, parsed["duration_ms"]);
+        assert_eq!(
+            dest_dir.path().to_string_lossy(),
+            parsed["dest_dir"].as_str().unwrap()
+        );
+    }
 }
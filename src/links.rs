@@ -0,0 +1,275 @@
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::ConversionOptions;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// A problem with a link in a note's body.
+#[derive(Debug, PartialEq)]
+pub enum LinkProblem {
+    /// a link whose brackets or parentheses don't balance, with the rest of its line
+    Malformed(String),
+    /// a link to a local file which doesn't exist, with the file's path
+    Missing(String),
+}
+
+/// Check the links in the body of each written note, warning about any which are malformed or
+/// point to local files which don't exist. Notes written to the same file are checked once, and
+/// a JSON array is not checked.
+pub fn report_broken_links(files: &[PathBuf], options: &ConversionOptions, clock: &dyn Clock) {
+    let files: BTreeSet<&PathBuf> = files.iter().collect();
+    for file_path in files {
+        let problems = match note_body(file_path) {
+            Ok(Some((body, dir))) => link_problems(&body, &dir),
+            Ok(None) => continue,
+            Err(e) => {
+                diagnostics::warning(
+                    options,
+                    clock,
+                    None,
+                    &format!(
+                        "file: '{}' could not be read to check its links: {}",
+                        file_path.display(),
+                        e
+                    ),
+                );
+                continue;
+            }
+        };
+        for problem in problems {
+            let message = match problem {
+                LinkProblem::Malformed(link) => format!("a malformed link '{}'", link),
+                LinkProblem::Missing(target) => format!("a link to missing file '{}'", target),
+            };
+            diagnostics::warning(
+                options,
+                clock,
+                None,
+                &format!("file: '{}' has {}", file_path.display(), message),
+            );
+        }
+    }
+}
+
+/// The body of the note written at `file_path` without its front matter, and the directory its
+/// links are relative to; `None` for a JSON array.
+fn note_body(file_path: &Path) -> Result<Option<(String, PathBuf)>, Error> {
+    let (text, dir) = match file_path {
+        p if p.extension().is_some_and(|e| e == "json") => return Ok(None),
+        // a textbundle's links are relative to the bundle
+        p if p.is_dir() => (fs::read_to_string(p.join("text.md"))?, p.to_path_buf()),
+        p => (
+            fs::read_to_string(p)?,
+            p.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+        ),
+    };
+    let body = match text.strip_prefix("---\n") {
+        Some(rest) => match rest.split_once("\n---\n") {
+            Some((_front_matter, body)) => body.to_string(),
+            None => text,
+        },
+        None => text,
+    };
+    Ok(Some((body, dir)))
+}
+
+/// The problems with the inline links and images in `content`, whose relative links point
+/// into `dir`. Links in code are ignored, as are links with a scheme, like `https:` or
+/// `mailto:`, and links within the note.
+pub fn link_problems(content: &str, dir: &Path) -> Vec<LinkProblem> {
+    let mut problems = Vec::new();
+    let mut in_fence = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence || line.starts_with("    ") || line.starts_with('\t') {
+            continue;
+        }
+        let line = without_code_spans(line);
+        let mut search = 0;
+        while let Some(found) = line[search..].find("](") {
+            let text_end = search + found;
+            let target_start = text_end + 2;
+            search = target_start;
+            let target = match (
+                has_link_text(&line[..text_end]),
+                link_target(&line[target_start..]),
+            ) {
+                (true, Some(target)) => target,
+                _ => {
+                    problems.push(LinkProblem::Malformed(line.trim().to_string()));
+                    break;
+                }
+            };
+            search += target.len() + 1;
+            if let Some(path) = local_path(target) {
+                if !dir.join(&path).exists() {
+                    problems.push(LinkProblem::Missing(path));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Whether `before`, the line up to a link's `]`, holds the `[` which opens its text.
+fn has_link_text(before: &str) -> bool {
+    let mut depth = 1;
+    for c in before.chars().rev() {
+        match c {
+            ']' => depth += 1,
+            '[' => depth -= 1,
+            _ => (),
+        }
+        if depth == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// The target of a link from just after its `(` up to the `)` which closes it, including any
+/// title, or `None` when the parentheses don't balance.
+fn link_target(after: &str) -> Option<&str> {
+    let mut depth = 1;
+    for (i, c) in after.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => (),
+        }
+        if depth == 0 {
+            return Some(&after[..i]);
+        }
+    }
+    None
+}
+
+/// The path of the file a link target points to, decoded, or `None` for targets which aren't local files.
+fn local_path(target: &str) -> Option<String> {
+    let target = target.trim();
+    // drop a title, as in `(file.md "Title")`
+    let target = match target.strip_prefix('<') {
+        Some(rest) => rest.split('>').next().unwrap_or_default(),
+        None => target.split_whitespace().next().unwrap_or_default(),
+    };
+    let path = target.split(['#', '?']).next().unwrap_or_default();
+    let has_scheme = target.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    match path.is_empty() || has_scheme {
+        true => None,
+        false => Some(percent_decode(path)),
+    }
+}
+
+/// Decode `%XX` escapes, such as the `%20` links between converted Notion pages use.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// `line` with the contents of its code spans blanked out, so they aren't taken for links.
+fn without_code_spans(line: &str) -> String {
+    let mut in_code = false;
+    line.chars()
+        .map(|c| match c {
+            '`' => {
+                in_code = !in_code;
+                c
+            }
+            _ if in_code => ' ',
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+
+    #[test]
+    fn link_problems_reports_only_broken_links() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Other Note.md"), "").unwrap();
+        let content = "See [the other note](Other%20Note.md) and [a lost one](Lost.md \"Lost\").\n\
+            [web](https://example.com/x.md), [mail](mailto:a@example.com), [here](#top)\n\
+            `[code](Missing.md)`\n\
+            ```\n[fenced](Missing.md)\n```\n";
+        assert_eq!(
+            vec![LinkProblem::Missing(String::from("Lost.md"))],
+            link_problems(content, dir.path())
+        );
+    }
+
+    #[test]
+    fn link_problems_reports_unbalanced_links() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a (1).md"), "").unwrap();
+        assert_eq!(
+            Vec::<LinkProblem>::new(),
+            link_problems("[balanced](a%20(1).md)", dir.path())
+        );
+        assert_eq!(
+            vec![LinkProblem::Malformed(String::from("[open](a.md"))],
+            link_problems("[open](a.md\n", dir.path())
+        );
+        assert_eq!(
+            vec![LinkProblem::Malformed(String::from("no opening](a.md)"))],
+            link_problems("no opening](a.md)\n", dir.path())
+        );
+    }
+
+    #[test]
+    fn report_broken_links_checks_written_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = dir.path().join("Note.md");
+        fs::write(
+            &note,
+            "---\ntitle: Note\n---\n[self](Note.md) [gone](Gone.md)\n",
+        )
+        .unwrap();
+        let error_log = dir.path().join("errors.log");
+        let options = ConversionOptions {
+            error_log: Some(error_log.clone()),
+            ..ConversionOptions::default()
+        };
+        report_broken_links(&[note.clone(), note.clone()], &options, &SystemClock);
+        let logged = fs::read_to_string(&error_log).unwrap();
+        assert_eq!(1, logged.lines().count());
+        assert!(
+            logged.ends_with("has a link to missing file 'Gone.md'\n"),
+            "{}",
+            logged
+        );
+    }
+}
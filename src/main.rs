@@ -1,6 +1,17 @@
-use clap::{AppSettings, Parser, Subcommand};
-use std::io::ErrorKind;
+use clap::{AppSettings, ArgEnum, Parser, Subcommand};
+use notes2md::clock::SystemClock;
+use notes2md::filter::FilterExpr;
+use notes2md::processor::datetime::validate_date_format;
+use notes2md::processor::markdown::{
+    validate_collision_format, validate_date_keys, validate_missing_segment,
+};
+use notes2md::processor::simplenote::TitleStripChars;
+use notes2md::scaffold::{self, Editor};
+use notes2md::{
+    ConversionOptions, ConversionStats, JsonFields, Notes2mdError, OutputFormat, TagCase,
+};
 use std::path::PathBuf;
+use std::time::Instant;
 
 /// A simple program to convert notes from either Apple Notes or Simplenote to markdown which can be used with Notable or other editors.
 #[derive(Parser, Debug)]
@@ -14,6 +25,335 @@ struct Cli {
     /// directory where converted notes will be written
     #[clap(short, long)]
     dest_dir: String,
+
+    /// treat warnings as errors
+    #[clap(long)]
+    strict: bool,
+
+    /// escape markdown characters in notes marked as plain text so they render verbatim
+    #[clap(long)]
+    escape_plaintext: bool,
+
+    /// read back every written note and fail if its front matter doesn't parse
+    #[clap(long)]
+    verify_output: bool,
+
+    /// rewrite reference-style links in note bodies as inline links
+    #[clap(long)]
+    inline_links: bool,
+
+    /// check dest_dir has enough free space for the converted notes before starting
+    #[clap(long)]
+    check_space: bool,
+
+    /// split tags which hold several values on this character, e.g. ','
+    #[clap(long)]
+    tag_separator: Option<char>,
+
+    /// decode the source from this encoding (e.g. windows-1252, latin1) instead of UTF8
+    #[clap(long)]
+    source_encoding: Option<String>,
+
+    /// truncate note bodies to this many characters, ending them with '…'
+    #[clap(long)]
+    content_max_length: Option<usize>,
+
+    /// add a 'type' front matter field with this value to every note, e.g. 'note'
+    #[clap(long)]
+    emit_type: Option<String>,
+
+    /// append this text to the file names of trashed notes, e.g. ' (trashed)'
+    #[clap(long)]
+    trashed_suffix: Option<String>,
+
+    /// title single-line notes with just their first sentence
+    #[clap(long)]
+    title_sentence: bool,
+
+    /// add an 'age' field like '2y 3mo' computed from the created date (a snapshot at conversion
+    /// time)
+    #[clap(long)]
+    emit_age: bool,
+
+    /// write notes into a subdirectory of dest_dir named for their source type, e.g. 'simplenote'
+    #[clap(long)]
+    subdir_by_source: bool,
+
+    /// report notes which would share a title, with their ids, instead of writing any notes
+    #[clap(long)]
+    report_title_collisions: bool,
+
+    /// write note bodies exactly as exported, without normalizing line endings or any other
+    /// content option
+    #[clap(long)]
+    raw_content: bool,
+
+    /// pipe each note body through this shell command, using its output as the body; a failing
+    /// command leaves the body unchanged, or with --strict skips the note
+    #[clap(long)]
+    transform_cmd: Option<String>,
+
+    /// file each note under this path in dest_dir, e.g. '{created_year}/{first_tag}'; also
+    /// accepts created_month, created_day, the modified_ equivalents and type
+    #[clap(long)]
+    path_template: Option<String>,
+
+    /// path segment for --path-template placeholders a note has no value for, and the directory
+    /// for --journal notes without a date, as a single directory name; an empty <NAME> leaves
+    /// the segment out [default: unknown]
+    #[clap(long, value_name = "NAME", alias = "path-template-default")]
+    missing_segment: Option<String>,
+
+    /// append the path of every note written to this file, so the run can be reverted with the
+    /// undo subcommand
+    #[clap(long)]
+    track_created: Option<String>,
+
+    /// add a comment recording the notes2md version, source type and note id to the front
+    /// matter, just below its opening '---'
+    #[clap(long)]
+    provenance_comment: bool,
+
+    /// write each note as a markdown file, as a textbundle directory for Bear or Ulysses, or
+    /// as an object in one JSON array in dest_dir/notes.json
+    #[clap(long, arg_enum, default_value = "markdown")]
+    format: Format,
+
+    /// recase tags, merging tags which then match; 'title' capitalizes each word
+    #[clap(long, arg_enum, default_value = "preserve")]
+    tag_case: Case,
+
+    /// also append every warning and note error to this file, with timestamps
+    #[clap(long)]
+    error_log: Option<String>,
+
+    /// when finished, print a JSON summary of converted, skipped and failed notes, notes renamed
+    /// on a collision, the duration and dest_dir on stderr
+    #[clap(long)]
+    stats_json: bool,
+
+    /// split notes at headings of this level (2 for '##'), writing each section as a note titled
+    /// with its heading and sharing the original's tags and dates
+    #[clap(long)]
+    split_on_headings: Option<usize>,
+
+    /// write notes into daily files at 'YYYY/MM/YYYY-MM-DD.md' by creation date, one '## HH:MM'
+    /// section per note; notes with unreadable dates go to 'unknown'
+    #[clap(long)]
+    journal: bool,
+
+    /// when finished, add the configuration the editor expects to dest_dir, keeping any which
+    /// already exists
+    #[clap(long, arg_enum)]
+    scaffold: Option<ScaffoldEditor>,
+
+    /// keep a JSON manifest of where each note id was written; on later runs notes found in it
+    /// overwrite their earlier file instead of being numbered, and only new notes get new files
+    #[clap(long)]
+    update_from_manifest: Option<String>,
+
+    /// before writing anything, check every note's dates and stop with a list of the notes
+    /// whose dates can't be read
+    #[clap(long)]
+    validate_dates: bool,
+
+    /// only convert notes matching an expression like 'pinned && tag:work && !trashed'; terms
+    /// are pinned, trashed, markdown, tag:NAME and text:WORD, combined with !, &&, || and ()
+    #[clap(long = "where")]
+    where_expr: Option<String>,
+
+    /// title a note whose first line is a bare URL as just its host and last path segment,
+    /// keeping the full URL in an aliases field
+    #[clap(long)]
+    shorten_url_titles: bool,
+
+    /// suffix given to a note whose file name is taken, with the index where it has n in braces,
+    /// or {n:03} for an index zero-padded to 3 digits [default: a space and the index in
+    /// parentheses]
+    #[clap(long)]
+    collision_format: Option<String>,
+
+    /// index of the first suffix given to a note whose file name is taken [default: 1]
+    #[clap(long)]
+    collision_start: Option<usize>,
+
+    /// after converting, print to stderr how many notes had each tag, most common first
+    #[clap(long)]
+    tag_stats: bool,
+
+    /// only convert notes modified within the last <DAYS> days; notes whose modified date
+    /// can't be read are converted with a warning
+    #[clap(long, value_name = "DAYS")]
+    recent: Option<u32>,
+
+    /// list the notes which failed, and why, together at the end of the run grouped by the kind
+    /// of error, instead of as each one fails; a run stopped by an error lists them before it
+    #[clap(long)]
+    summary_failures: bool,
+
+    /// skip notes whose content is already recorded in this file by an earlier run, and record
+    /// the notes converted now, so repeated imports only add new notes
+    #[clap(long)]
+    seen_file: Option<String>,
+
+    /// characters to strip from titles derived from note content, as the inside of a regex
+    /// character class, replacing the default set; stripping too much can leave titles empty,
+    /// and those notes then fail [default: '"`#()!~>_\[\]\*]
+    #[clap(long, value_name = "SET")]
+    title_strip_chars: Option<String>,
+
+    /// treat every note as markdown whatever its source says, adding 'markdown: true' to the
+    /// front matter
+    #[clap(long, conflicts_with = "assume-plaintext")]
+    assume_markdown: bool,
+
+    /// treat every note as plain text whatever its source says, adding 'markdown: false' to the
+    /// front matter
+    #[clap(long)]
+    assume_plaintext: bool,
+
+    /// when a note's file name is taken, suffix it with the note's modified date instead of a
+    /// number, falling back to numbers if that is taken too
+    #[clap(long)]
+    rename_collisions_by_date_suffix: bool,
+
+    /// hard-wrap paragraphs and list items in note bodies at <WIDTH> characters, leaving code,
+    /// headings, tables and quotes as they are
+    #[clap(long, value_name = "WIDTH")]
+    wrap: Option<usize>,
+
+    /// write the creation date in front matter as <NAME> instead of 'created', e.g. 'date'
+    #[clap(long, value_name = "NAME")]
+    created_key: Option<String>,
+
+    /// write the modification date in front matter as <NAME> instead of 'modified', e.g.
+    /// 'updated'
+    #[clap(long, value_name = "NAME")]
+    modified_key: Option<String>,
+
+    /// warn, naming the note, about each note whose content is over <BYTES>
+    #[clap(long, value_name = "BYTES")]
+    warn_large: Option<usize>,
+
+    /// skip each note whose content is over <BYTES>, with a warning
+    #[clap(long, value_name = "BYTES")]
+    skip_large: Option<usize>,
+
+    /// after converting, warn about links in the notes which are malformed or point to local
+    /// files which don't exist; the notes are left as they are
+    #[clap(long)]
+    check_links: bool,
+
+    /// start each Simplenote file name with the note's position in the export, e.g.
+    /// '0003 My Note.md', so files sort in the export's order
+    #[clap(long)]
+    order_prefix: bool,
+
+    /// leave the line a Simplenote note's title is taken from out of its body, so a note of
+    /// only a title is written with an empty body
+    #[clap(long)]
+    strip_title_line: bool,
+
+    /// stop with an error before writing a note would leave less than <BYTES> free on
+    /// dest_dir's disk
+    #[clap(long, value_name = "BYTES")]
+    reserve_space: Option<u64>,
+
+    /// warn about each field in a Simplenote export which notes2md doesn't convert, so its data
+    /// would be lost
+    #[clap(long)]
+    warn_unknown_fields: bool,
+
+    /// cut titles taken from a note's first line to at most <CHARS> characters [default: 200]
+    #[clap(long, value_name = "CHARS")]
+    max_title_len: Option<usize>,
+
+    /// warn about each note without tags, or with --strict stop at the first one
+    #[clap(long)]
+    require_tags: bool,
+
+    /// tag notes which have no tags with <TAG>
+    #[clap(long, value_name = "TAG")]
+    default_tag: Option<String>,
+
+    /// name files with a slug of the title like 'hello-world.md', for static site generators;
+    /// the front matter title is unchanged
+    #[clap(long)]
+    slug: bool,
+
+    /// wait for each note and the manifest to reach the disk after writing it, for durability
+    /// on unreliable storage at the cost of speed
+    #[clap(long)]
+    fsync: bool,
+
+    /// put TEXT before each note's title and file name, e.g. "[SN] " to mark imported notes
+    #[clap(long, value_name = "TEXT")]
+    title_prefix: Option<String>,
+
+    /// put TEXT after each note's title and file name
+    #[clap(long, value_name = "TEXT")]
+    title_suffix: Option<String>,
+
+    /// keep converted Simplenote notes in DIR, and reuse them on later runs for notes whose id
+    /// and modified date are unchanged, saving slow steps like --transform-cmd; any change of
+    /// options converts every note again
+    #[clap(long, value_name = "DIR")]
+    cache: Option<String>,
+
+    /// write note dates in this strftime PATTERN, e.g. '%Y-%m-%d', instead of as RFC 3339
+    /// timestamps in UTC; dates are always read and checked first
+    #[clap(long, value_name = "PATTERN")]
+    date_format: Option<String>,
+
+    /// write a copy of each note into a folder named for every one of its tags, e.g.
+    /// 'DEST_DIR/work/Title.md' and 'DEST_DIR/ideas/Title.md'; untagged notes go into
+    /// 'untagged', as with --group-by-tag
+    #[clap(long)]
+    duplicate_into_tag_dirs: bool,
+
+    /// with --duplicate-into-tag-dirs, hard link the copies to the first one to save space
+    #[clap(long, requires = "duplicate-into-tag-dirs")]
+    link: bool,
+
+    /// set each note file's modification time to the note's modified date, so file managers
+    /// sort notes by when they were last edited rather than when they were converted
+    #[clap(long)]
+    preserve_mtime: bool,
+
+    /// write each note into a folder of DEST_DIR named for its first tag, and untagged notes
+    /// into 'untagged'
+    #[clap(
+        long,
+        conflicts_with_all = &["path-template", "duplicate-into-tag-dirs"]
+    )]
+    group_by_tag: bool,
+
+    /// replace files already in DEST_DIR with the notes of the same name, so a re-run doesn't
+    /// leave numbered duplicates; notes of one run which share a name are still numbered, and
+    /// --journal and '--format json-array' still add later notes to the files the run wrote
+    #[clap(long)]
+    overwrite: bool,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum Format {
+    Markdown,
+    Textbundle,
+    JsonArray,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum ScaffoldEditor {
+    Obsidian,
+    Notable,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum Case {
+    Lower,
+    Upper,
+    Title,
+    Preserve,
 }
 
 #[derive(Subcommand, Debug)]
@@ -22,10 +362,167 @@ enum SourceTypes {
     Applenotes { source_dir: String },
     /// process a JSON file export of Simplenote data <SOURCE_FILE>
     Simplenote { source_file: String },
+    /// process a directory of Notion markdown export pages <SOURCE_DIR>
+    Notion { source_dir: String },
+    /// process a directory of .eml email files <SOURCE_DIR>
+    Email { source_dir: String },
+    /// process an Evernote .enex export file <SOURCE_FILE>
+    Evernote { source_file: String },
+    /// process a Google Takeout directory of Keep notes <SOURCE_DIR>
+    Googlekeep { source_dir: String },
+    /// process a JSON file of notes, with options saying where each field is <SOURCE_FILE>
+    Json {
+        source_file: String,
+        /// dotted path to the array of notes, when it isn't the whole document
+        #[clap(long, value_name = "PATH")]
+        notes_field: Option<String>,
+        /// dotted path to each note's title; notes without one are titled from the body
+        #[clap(long, value_name = "PATH", default_value = "title")]
+        title_field: String,
+        /// dotted path to each note's body
+        #[clap(long, value_name = "PATH", default_value = "content")]
+        body_field: String,
+        /// dotted path to each note's creation date, RFC 3339 or Unix seconds
+        #[clap(long, value_name = "PATH", default_value = "created")]
+        created_field: String,
+        /// dotted path to each note's modification date, RFC 3339 or Unix seconds
+        #[clap(long, value_name = "PATH", default_value = "modified")]
+        modified_field: String,
+        /// dotted path to each note's tags, an array or a comma separated string
+        #[clap(long, value_name = "PATH", default_value = "tags")]
+        tags_field: String,
+    },
+    /// process every source listed in a JSON batch config file <CONFIG_FILE>
+    Batch { config_file: String },
+    /// remove the notes in dest_dir listed in a --track-created file <TRACK_FILE>
+    Undo { track_file: String },
+    /// rewrite the notes already in dest_dir as this version would, fixing their front matter
+    Repair,
+    /// print the front matter of the notes already in dest_dir as a JSON array
+    Frontmatter,
 }
 
 fn main() {
+    let started = Instant::now();
     let cli = Cli::parse();
+    let filter = match cli.where_expr.as_deref().map(FilterExpr::parse).transpose() {
+        Ok(filter) => filter,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(Err(e)) = cli
+        .collision_format
+        .as_deref()
+        .map(validate_collision_format)
+    {
+        println!("{}", e);
+        std::process::exit(2);
+    }
+    if let Some(Err(e)) = cli.missing_segment.as_deref().map(validate_missing_segment) {
+        println!("{}", e);
+        std::process::exit(2);
+    }
+    if let Some(Err(e)) = cli.date_format.as_deref().map(validate_date_format) {
+        println!("{}", e);
+        std::process::exit(2);
+    }
+    if let Err(e) = validate_date_keys(cli.created_key.as_deref(), cli.modified_key.as_deref()) {
+        println!("{}", e);
+        std::process::exit(2);
+    }
+    let title_strip_chars = match cli
+        .title_strip_chars
+        .as_deref()
+        .map(TitleStripChars::new)
+        .transpose()
+    {
+        Ok(title_strip_chars) => title_strip_chars,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.max_title_len == Some(0) {
+        println!("max_title_len: '0' leaves no room for a title");
+        std::process::exit(2);
+    }
+    let options = ConversionOptions {
+        strict: cli.strict,
+        escape_plaintext: cli.escape_plaintext,
+        verify_output: cli.verify_output,
+        inline_links: cli.inline_links,
+        check_space: cli.check_space,
+        tag_separator: cli.tag_separator,
+        source_encoding: cli.source_encoding,
+        content_max_length: cli.content_max_length,
+        emit_type: cli.emit_type,
+        trashed_suffix: cli.trashed_suffix,
+        title_sentence: cli.title_sentence,
+        emit_age: cli.emit_age,
+        subdir_by_source: cli.subdir_by_source,
+        report_title_collisions: cli.report_title_collisions,
+        raw_content: cli.raw_content,
+        transform_cmd: cli.transform_cmd,
+        path_template: cli.path_template,
+        missing_segment: cli.missing_segment,
+        track_created: cli.track_created.map(PathBuf::from),
+        provenance_comment: cli.provenance_comment,
+        format: match cli.format {
+            Format::Markdown => OutputFormat::Markdown,
+            Format::Textbundle => OutputFormat::Textbundle,
+            Format::JsonArray => OutputFormat::JsonArray,
+        },
+        tag_case: match cli.tag_case {
+            Case::Lower => TagCase::Lower,
+            Case::Upper => TagCase::Upper,
+            Case::Title => TagCase::Title,
+            Case::Preserve => TagCase::Preserve,
+        },
+        error_log: cli.error_log.map(PathBuf::from),
+        split_on_headings: cli.split_on_headings,
+        journal: cli.journal,
+        update_from_manifest: cli.update_from_manifest.map(PathBuf::from),
+        validate_dates: cli.validate_dates,
+        filter,
+        shorten_url_titles: cli.shorten_url_titles,
+        collision_format: cli.collision_format,
+        collision_start: cli.collision_start,
+        recent: cli.recent,
+        summary_failures: cli.summary_failures,
+        seen_file: cli.seen_file.map(PathBuf::from),
+        title_strip_chars,
+        rename_collisions_by_date_suffix: cli.rename_collisions_by_date_suffix,
+        wrap: cli.wrap,
+        created_key: cli.created_key,
+        modified_key: cli.modified_key,
+        warn_large: cli.warn_large,
+        skip_large: cli.skip_large,
+        order_prefix: cli.order_prefix,
+        strip_title_line: cli.strip_title_line,
+        reserve_space: cli.reserve_space,
+        warn_unknown_fields: cli.warn_unknown_fields,
+        max_title_len: cli.max_title_len,
+        require_tags: cli.require_tags,
+        default_tag: cli.default_tag,
+        slug: cli.slug,
+        fsync: cli.fsync,
+        title_prefix: cli.title_prefix,
+        title_suffix: cli.title_suffix,
+        cache_dir: cli.cache.map(PathBuf::from),
+        date_format: cli.date_format,
+        duplicate_into_tag_dirs: cli.duplicate_into_tag_dirs,
+        link_tag_copies: cli.link,
+        preserve_mtime: cli.preserve_mtime,
+        group_by_tag: cli.group_by_tag,
+        overwrite: cli.overwrite,
+        assume_markdown: match (cli.assume_markdown, cli.assume_plaintext) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+    };
 
     let results = match &cli.source_type {
         SourceTypes::Applenotes { source_dir } => {
@@ -33,37 +530,167 @@ fn main() {
                 "notes2md will read applenotes from source '{}' and write to '{}'",
                 source_dir, &cli.dest_dir
             );
-            notes2md::process_applenotes(PathBuf::from(source_dir), PathBuf::from(cli.dest_dir))
+            notes2md::process_applenotes(
+                PathBuf::from(source_dir),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
         }
         SourceTypes::Simplenote { source_file } => {
             println!(
                 "notes2md will read simplenote from source '{}' and write to '{}'",
                 source_file, &cli.dest_dir
             );
-            notes2md::process_simplenote(PathBuf::from(source_file), PathBuf::from(cli.dest_dir))
+            notes2md::process_simplenote(
+                PathBuf::from(source_file),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
         }
+        SourceTypes::Notion { source_dir } => {
+            println!(
+                "notes2md will read notion from source '{}' and write to '{}'",
+                source_dir, &cli.dest_dir
+            );
+            notes2md::process_notion(
+                PathBuf::from(source_dir),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
+        }
+        SourceTypes::Email { source_dir } => {
+            println!(
+                "notes2md will read email from source '{}' and write to '{}'",
+                source_dir, &cli.dest_dir
+            );
+            notes2md::process_email(
+                PathBuf::from(source_dir),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
+        }
+        SourceTypes::Evernote { source_file } => {
+            println!(
+                "notes2md will read evernote from source '{}' and write to '{}'",
+                source_file, &cli.dest_dir
+            );
+            notes2md::process_evernote(
+                PathBuf::from(source_file),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
+        }
+        SourceTypes::Googlekeep { source_dir } => {
+            println!(
+                "notes2md will read googlekeep from source '{}' and write to '{}'",
+                source_dir, &cli.dest_dir
+            );
+            notes2md::process_googlekeep(
+                PathBuf::from(source_dir),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
+        }
+        SourceTypes::Json {
+            source_file,
+            notes_field,
+            title_field,
+            body_field,
+            created_field,
+            modified_field,
+            tags_field,
+        } => {
+            println!(
+                "notes2md will read json from source '{}' and write to '{}'",
+                source_file, &cli.dest_dir
+            );
+            let fields = JsonFields {
+                notes: notes_field.clone(),
+                title: title_field.clone(),
+                body: body_field.clone(),
+                created: created_field.clone(),
+                modified: modified_field.clone(),
+                tags: tags_field.clone(),
+            };
+            notes2md::process_json(
+                PathBuf::from(source_file),
+                PathBuf::from(&cli.dest_dir),
+                &fields,
+                &options,
+            )
+        }
+        SourceTypes::Batch { config_file } => {
+            println!(
+                "notes2md will read batch sources from config '{}' and write to '{}'",
+                config_file, &cli.dest_dir
+            );
+            notes2md::process_batch(
+                PathBuf::from(config_file),
+                PathBuf::from(&cli.dest_dir),
+                &options,
+            )
+        }
+        SourceTypes::Repair => {
+            println!("notes2md will repair the notes in '{}'", &cli.dest_dir);
+            notes2md::repair_notes(PathBuf::from(&cli.dest_dir), &options)
+        }
+        SourceTypes::Frontmatter => notes2md::front_matter(PathBuf::from(&cli.dest_dir), &options)
+            .and_then(|notes| {
+                serde_json::to_string_pretty(&notes)
+                    .map_err(|e| Notes2mdError::InvalidData(format!("JSON ERROR: {}", e)))
+            })
+            .map(|json| {
+                println!("{}", json);
+                ConversionStats::default()
+            }),
+        SourceTypes::Undo { track_file } => {
+            println!(
+                "notes2md will remove notes listed in '{}' from '{}'",
+                track_file, &cli.dest_dir
+            );
+            notes2md::undo_created(PathBuf::from(track_file), PathBuf::from(&cli.dest_dir))
+                .map(|_| ConversionStats::default())
+        }
+    };
+
+    let results = match (&cli.scaffold, &cli.source_type) {
+        (_, SourceTypes::Undo { .. } | SourceTypes::Repair | SourceTypes::Frontmatter)
+        | (None, _) => results,
+        (Some(editor), _) => results.and_then(|stats| {
+            let editor = match editor {
+                ScaffoldEditor::Obsidian => Editor::Obsidian,
+                ScaffoldEditor::Notable => Editor::Notable,
+            };
+            scaffold::write_scaffold(&PathBuf::from(&cli.dest_dir), editor)?;
+            Ok(stats)
+        }),
     };
 
+    if let (true, Ok(stats)) = (cli.check_links, &results) {
+        notes2md::links::report_broken_links(&stats.written, &options, &SystemClock);
+    }
+
+    // a run which stopped with an error has already printed its summary
+    if let (true, Ok(stats)) = (cli.summary_failures, &results) {
+        print!("{}", stats.failure_summary());
+    }
+
+    if let (true, Ok(stats)) = (cli.tag_stats, &results) {
+        eprint!("{}", stats.tag_table());
+    }
+
+    if let (true, Ok(stats)) = (cli.stats_json, &results) {
+        eprintln!(
+            "{}",
+            stats.to_json(started.elapsed(), &PathBuf::from(&cli.dest_dir))
+        );
+    }
+
     std::process::exit(match results {
-        Err(e) => match e.kind() {
-            ErrorKind::InvalidData => {
-                println!("{}", e);
-                1
-            }
-            ErrorKind::InvalidInput => {
-                println!("{}", e);
-                2
-            }
-            ErrorKind::NotFound => {
-                println!("{}", e);
-                3
-            }
-            ErrorKind::PermissionDenied => {
-                println!("{}", e);
-                4
-            }
-            _ => panic!("Unhandled error {:?}", e),
-        },
+        Err(e) => {
+            println!("{}", e);
+            e.exit_code()
+        }
         Ok(_) => 0,
     })
 }
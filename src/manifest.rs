@@ -0,0 +1,88 @@
+use crate::processor::markdown::write_file;
+use crate::ConversionOptions;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// A record of which file each source note was written to, kept between runs so a re-import can
+/// update those files in place. It is stored as a JSON object of note id to absolute path.
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    manifest_file: PathBuf,
+    notes: BTreeMap<String, PathBuf>,
+}
+
+impl Manifest {
+    /// Load the manifest from `manifest_file`, or start an empty one if it doesn't exist yet.
+    pub fn load(manifest_file: &Path) -> Result<Manifest, Error> {
+        let notes = match fs::read_to_string(manifest_file) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(notes) => notes,
+                Err(e) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "manifest: '{}' is not a valid manifest: {}",
+                            manifest_file.to_string_lossy(),
+                            e
+                        ),
+                    ))
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Manifest {
+            manifest_file: manifest_file.to_path_buf(),
+            notes,
+        })
+    }
+
+    /// The file a previous run wrote the note `id` to, if it is still there.
+    pub fn existing_path(&self, id: &str) -> Option<&Path> {
+        self.notes
+            .get(id)
+            .map(PathBuf::as_path)
+            .filter(|path| path.is_file())
+    }
+
+    /// Record that the note `id` is now in `file_path`.
+    pub fn record(&mut self, id: &str, file_path: &Path) -> Result<(), Error> {
+        self.notes
+            .insert(id.to_string(), fs::canonicalize(file_path)?);
+        Ok(())
+    }
+
+    pub fn save(&self, options: &ConversionOptions) -> Result<(), Error> {
+        let text = serde_json::to_string_pretty(&self.notes)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("JSON ERROR: {}", e)))?;
+        write_file(&self.manifest_file, text.as_bytes(), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_round_trips_and_ignores_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_file = dir.path().join("manifest.json");
+        let note_file = dir.path().join("A note.md");
+        fs::write(&note_file, "").unwrap();
+
+        let mut manifest = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(None, manifest.existing_path("someid"));
+        manifest.record("someid", &note_file).unwrap();
+        manifest.save(&ConversionOptions::default()).unwrap();
+
+        let reloaded = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(manifest, reloaded);
+        let expected = fs::canonicalize(&note_file).unwrap();
+        assert_eq!(Some(expected.as_path()), reloaded.existing_path("someid"));
+
+        fs::remove_file(&note_file).unwrap();
+        assert_eq!(None, reloaded.existing_path("someid"));
+    }
+}
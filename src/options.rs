@@ -0,0 +1,163 @@
+use crate::filter::FilterExpr;
+use crate::processor::simplenote::TitleStripChars;
+use std::path::PathBuf;
+
+/// The form in which each converted note is written.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// a markdown file with YAML front matter
+    #[default]
+    Markdown,
+    /// a `.textbundle` directory holding the markdown file as `text.md` and an `info.json`
+    Textbundle,
+    /// an object in a JSON array of all notes, in `notes.json` in dest_dir
+    JsonArray,
+}
+
+/// How tag capitalization is normalized.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TagCase {
+    /// keep tags as the source has them
+    #[default]
+    Preserve,
+    Lower,
+    Upper,
+    /// capitalize the first letter of each word, lowercasing the rest
+    Title,
+}
+
+/// Options which control how notes are converted and written, shared by all processors.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConversionOptions {
+    /// treat problems which are otherwise only warnings as errors
+    pub strict: bool,
+    /// escape markdown-significant characters in notes which the source marks as plain text
+    pub escape_plaintext: bool,
+    /// read each written note back and fail the run if its front matter doesn't parse
+    pub verify_output: bool,
+    /// rewrite reference-style links into inline links, dropping their definitions
+    pub inline_links: bool,
+    /// check dest_dir has room for the converted notes before starting
+    pub check_space: bool,
+    /// split each source tag on this character into separate tags
+    pub tag_separator: Option<char>,
+    /// encoding label (e.g. `windows-1252`) to decode the source from, rather than strict UTF8
+    pub source_encoding: Option<String>,
+    /// cap note bodies at this many characters, marking truncation with `…`
+    pub content_max_length: Option<usize>,
+    /// value for a `type` front matter field added to every note
+    pub emit_type: Option<String>,
+    /// text appended to the file names of trashed notes
+    pub trashed_suffix: Option<String>,
+    /// for notes which are a single line, take only the first sentence as the title
+    pub title_sentence: bool,
+    /// add an `age` field computed from the creation date at conversion time
+    pub emit_age: bool,
+    /// write each source type's notes into a subdirectory of dest_dir named for it
+    pub subdir_by_source: bool,
+    /// only report groups of notes which would share a title, writing nothing
+    pub report_title_collisions: bool,
+    /// write note bodies exactly as exported, ignoring all other content options
+    pub raw_content: bool,
+    /// shell command each note body is piped through, its stdout becoming the new body
+    pub transform_cmd: Option<String>,
+    /// directory under dest_dir for each note, built from placeholders like `{created_year}`
+    pub path_template: Option<String>,
+    /// path segment used where one can't be worked out for a note, like a template placeholder
+    /// it has no value for or a journal note without a date; default `unknown`, and an empty
+    /// segment is left out
+    pub missing_segment: Option<String>,
+    /// file to which the path of every note written is appended, so a run can be undone
+    pub track_created: Option<PathBuf>,
+    /// add a YAML comment naming the notes2md version, source and note id to the front matter
+    pub provenance_comment: bool,
+    /// write each note as a markdown file or as a textbundle
+    pub format: OutputFormat,
+    /// recase tags, so tags differing only in case are merged
+    pub tag_case: TagCase,
+    /// file to which every warning and note error is also appended, with a timestamp
+    pub error_log: Option<PathBuf>,
+    /// write each section under a heading of this level as a note of its own
+    pub split_on_headings: Option<usize>,
+    /// collect notes into one `YYYY/MM/YYYY-MM-DD.md` file per creation day
+    pub journal: bool,
+    /// manifest of the file each note id was written to, updated in place on later runs
+    pub update_from_manifest: Option<PathBuf>,
+    /// check all note dates parse before converting any, failing with a list of those which don't
+    pub validate_dates: bool,
+    /// only notes matching this expression are converted
+    pub filter: Option<FilterExpr>,
+    /// title notes which are only a URL `host/last-segment`, keeping the URL as an alias
+    pub shorten_url_titles: bool,
+    /// suffix for notes whose file name is taken, with `{n}` or zero-padded `{n:03}` for the
+    /// index; default ` ({n})`
+    pub collision_format: Option<String>,
+    /// first index used for a taken file name, default 1
+    pub collision_start: Option<usize>,
+    /// only notes modified within this many days of now are converted
+    pub recent: Option<u32>,
+    /// print failed notes together at the end of the run, rather than as each one fails
+    pub summary_failures: bool,
+    /// file of the content hashes of notes already converted, which are skipped; the hashes of
+    /// newly converted notes are added to it
+    pub seen_file: Option<PathBuf>,
+    /// characters stripped from derived titles, replacing the default set
+    pub title_strip_chars: Option<TitleStripChars>,
+    /// treat every note as markdown (`true`) or plain text (`false`) whatever its source says,
+    /// and add a `markdown` front matter field saying so
+    pub assume_markdown: Option<bool>,
+    /// when a note's file name is taken, first try suffixing it with the note's modified date
+    pub rename_collisions_by_date_suffix: bool,
+    /// reflow paragraphs and list items in note bodies to lines of at most this many characters
+    pub wrap: Option<usize>,
+    /// front matter name for the creation date in place of `created`
+    pub created_key: Option<String>,
+    /// front matter name for the modification date in place of `modified`
+    pub modified_key: Option<String>,
+    /// warn about notes whose content is over this many bytes
+    pub warn_large: Option<usize>,
+    /// skip notes whose content is over this many bytes
+    pub skip_large: Option<usize>,
+    /// start each Simplenote file name with the note's zero-padded position in the export
+    pub order_prefix: bool,
+    /// leave the line a Simplenote note's title is taken from out of its body
+    pub strip_title_line: bool,
+    /// stop converting before a note would leave dest_dir's disk with less than this many bytes
+    /// free
+    pub reserve_space: Option<u64>,
+    /// warn about fields in a Simplenote export which aren't converted, and so are lost
+    pub warn_unknown_fields: bool,
+    /// longest a title taken from a note's first line may be, in characters; 200 when unset
+    pub max_title_len: Option<usize>,
+    /// every note must have a tag: one without is warned about, or under `strict` stops the run
+    pub require_tags: bool,
+    /// tag given to notes which have none
+    pub default_tag: Option<String>,
+    /// name files with the lowercase, hyphenated ASCII slug of the title, which is kept as it
+    /// is in the front matter
+    pub slug: bool,
+    /// wait for each note, and the manifest, to be on disk before going on
+    pub fsync: bool,
+    /// put before each note's title, and so its file name, to mark where imported notes came from
+    pub title_prefix: Option<String>,
+    /// put after each note's title, and so its file name
+    pub title_suffix: Option<String>,
+    /// keep converted Simplenote notes here, and take a note from it instead of converting it
+    /// again while its modified date and these options are unchanged
+    pub cache_dir: Option<PathBuf>,
+    /// strftime pattern Simplenote dates are written in, in place of RFC 3339
+    pub date_format: Option<String>,
+    /// write each note into a directory of dest_dir for every one of its tags
+    pub duplicate_into_tag_dirs: bool,
+    /// with `duplicate_into_tag_dirs`, hard link the copies after the first instead of writing
+    /// them
+    pub link_tag_copies: bool,
+    /// set each written note file's modification time to the note's modified date
+    pub preserve_mtime: bool,
+    /// write each note into a directory of dest_dir named for its first tag, or `untagged`
+    pub group_by_tag: bool,
+    /// replace a file left at a note's path by an earlier run instead of numbering the note's
+    /// file name; files written earlier in the same run are still numbered around. Journal day
+    /// files and the JSON array are always added to
+    pub overwrite: bool,
+}
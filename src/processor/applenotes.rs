@@ -1,7 +1,15 @@
+use crate::clock::Clock;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
 use std::io::Error;
 use std::path::PathBuf;
 
-pub fn process(_source_dir: PathBuf, _dest_dir: PathBuf) -> Result<(), Error> {
+pub fn process(
+    _source_dir: PathBuf,
+    _dest_dir: PathBuf,
+    _options: &ConversionOptions,
+    _clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
     println!("Apple Notes conversion not yet implemented.");
-    Ok(())
+    Ok(ConversionStats::default())
 }
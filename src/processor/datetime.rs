@@ -0,0 +1,130 @@
+use crate::ConversionOptions;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, ParseError, SecondsFormat, Utc};
+use std::io::{Error, ErrorKind};
+
+/// Forms of date without an offset which are accepted besides RFC 3339, and taken as UTC.
+const NAIVE_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// A note's date, which is RFC 3339 in an export, or in one edited by hand may lack its offset
+/// or time of day. Dates without an offset are UTC, and a date alone is its midnight.
+pub fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, ParseError> {
+    let value = value.trim();
+    let rfc3339_error = match DateTime::parse_from_rfc3339(value) {
+        Ok(date) => return Ok(date.with_timezone(&Utc)),
+        Err(e) => e,
+    };
+    for format in NAIVE_FORMATS {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(date.and_utc());
+        }
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()),
+        Err(_) => Err(rfc3339_error),
+    }
+}
+
+/// A date read back from front matter, where it was written in the `date_format` strftime
+/// pattern if one was given. A date in a form `parse_timestamp` accepts is read too, keeping an
+/// RFC 3339 date's offset. Returns `None` for a date in neither, or a pattern which leaves out
+/// part of the date, like its year.
+pub fn parse_note_timestamp(
+    value: &str,
+    date_format: Option<&str>,
+) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Some(format) = date_format {
+        if let Ok(date) = DateTime::parse_from_str(value, format) {
+            return Some(date);
+        }
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(date.and_utc().fixed_offset());
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return date
+                .and_hms_opt(0, 0, 0)
+                .map(|d| d.and_utc().fixed_offset());
+        }
+    }
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(date) => Some(date),
+        Err(_) => parse_timestamp(value).ok().map(|d| d.fixed_offset()),
+    }
+}
+
+/// `date` as it is written to front matter: in the `date_format` strftime pattern when given,
+/// otherwise RFC 3339 in UTC to the millisecond.
+pub fn format_timestamp(date: DateTime<Utc>, options: &ConversionOptions) -> String {
+    match &options.date_format {
+        Some(format) => date.format(format).to_string(),
+        None => date.to_rfc3339_opts(SecondsFormat::Millis, true),
+    }
+}
+
+/// Check a `date_format` is a strftime pattern chrono can write, as formatting a date with an
+/// invalid one panics.
+pub fn validate_date_format(format: &str) -> Result<(), Error> {
+    match StrftimeItems::new(format).any(|item| item == Item::Error) {
+        true => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("date_format: '{}' is not a valid strftime pattern", format),
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps_in_several_forms() {
+        let expected = "2022-01-14T07:36:50.656Z";
+        for value in [
+            "2022-01-14T07:36:50.656Z",
+            "2022-01-14T08:36:50.656+01:00",
+            "2022-01-14T07:36:50.656",
+            " 2022-01-14 07:36:50.656 ",
+        ] {
+            let date = parse_timestamp(value).unwrap();
+            assert_eq!(expected, date.to_rfc3339_opts(SecondsFormat::Millis, true));
+        }
+        let midnight = parse_timestamp("2022-01-14").unwrap();
+        assert_eq!(
+            "2022-01-14T00:00:00.000Z",
+            midnight.to_rfc3339_opts(SecondsFormat::Millis, true)
+        );
+        assert!(parse_timestamp("14/01/2022").is_err());
+        assert!(parse_timestamp("").is_err());
+    }
+
+    #[test]
+    fn format_timestamps_with_date_format() {
+        let date = parse_timestamp("2022-01-14T07:36:50.656Z").unwrap();
+        assert_eq!(
+            "2022-01-14T07:36:50.656Z",
+            format_timestamp(date, &ConversionOptions::default())
+        );
+        let options = ConversionOptions {
+            date_format: Some(String::from("%Y-%m-%d")),
+            ..Default::default()
+        };
+        assert_eq!("2022-01-14", format_timestamp(date, &options));
+
+        for format in ["%Y-%m-%d", "%d.%m.%Y %H:%M", "%a, %d %b %Y %H:%M:%S %z"] {
+            let written = date.format(format).to_string();
+            let read = parse_note_timestamp(&written, Some(format)).unwrap();
+            assert_eq!(written, read.format(format).to_string());
+        }
+        let offset = parse_note_timestamp("2022-01-14T08:36:50.656+01:00", Some("%d.%m.%Y"));
+        assert_eq!("08:36", offset.unwrap().format("%H:%M").to_string());
+        assert_eq!(None, parse_note_timestamp("14.01", Some("%d.%m")));
+
+        assert!(validate_date_format("%Y-%m-%d %H:%M").is_ok());
+        assert_eq!(
+            "date_format: '%Y-%Q' is not a valid strftime pattern",
+            validate_date_format("%Y-%Q").unwrap_err().to_string()
+        );
+    }
+}
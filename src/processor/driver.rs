@@ -0,0 +1,171 @@
+use super::markdown::Markdown;
+use super::sink::NoteSink;
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::filter::{is_recent, is_tagged, is_within_size, untagged, NoteFields};
+use crate::seen::{content_hash, SeenHashes};
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use std::io::Error;
+
+/// One note of a source as it was read, before it is converted.
+pub(crate) struct SourceNote<T> {
+    /// how the note is named in messages and the failure summary, usually its file
+    pub name: String,
+    /// the note as read, or why it couldn't be, which fails only this note
+    pub source: Result<T, Error>,
+}
+
+/// Convert each of `notes` with `convert` and write it through `sink`, counting what happens in
+/// `stats`; the loop every source but Simplenote shares. `raw` gives the bytes a note was read
+/// from, which `seen_file` records the hash of and `reserve_space` is checked against. Notes
+/// left out by `--where`, `--recent` or their size are skipped, and a note which fails is
+/// counted before going on to the next, but an untagged note with `--strict` tags, or a written
+/// note which doesn't read back, stops the run.
+pub(crate) fn convert_notes<T>(
+    notes: impl IntoIterator<Item = SourceNote<T>>,
+    raw: fn(&T) -> &[u8],
+    mut convert: impl FnMut(T, &str) -> Result<Markdown, Error>,
+    sink: &mut dyn NoteSink,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+    stats: &mut ConversionStats,
+) -> Result<(), Error> {
+    let mut seen = match &options.seen_file {
+        Some(seen_file) => Some(SeenHashes::load(seen_file)?),
+        None => None,
+    };
+    for SourceNote { name, source } in notes {
+        let read = source.as_ref().ok().map(|source| {
+            let bytes = raw(source);
+            (content_hash(bytes), bytes.len() as u64)
+        });
+        if let Some((hash, bytes)) = read {
+            if seen.as_ref().is_some_and(|s| s.contains(hash)) {
+                stats.skipped += 1;
+                continue;
+            }
+            sink.check_reserve(bytes)?;
+        }
+        let result = match (source.and_then(|s| convert(s, &name)), &options.filter) {
+            (Ok(md), Some(filter)) if !filter.matches(&note_fields(&md)) => {
+                stats.skipped += 1;
+                continue;
+            }
+            (Ok(md), _) if !is_recent(Some(&md.meta.modified), &name, options, clock) => {
+                stats.skipped += 1;
+                continue;
+            }
+            (Ok(md), _) if !is_within_size(&md.meta.title, &md.content, options, clock) => {
+                stats.skipped += 1;
+                continue;
+            }
+            (Ok(md), _) if !is_tagged(&name, md.meta.tags.as_deref(), options, clock) => {
+                return Err(untagged(&name));
+            }
+            (result, _) => result.and_then(|md| {
+                let tags = md.meta.tags.clone().unwrap_or_default();
+                sink.write(&md)
+                    .map_err(Error::from)
+                    .map(|file_paths| (file_paths, tags))
+            }),
+        };
+        match result {
+            Ok((file_paths, tags)) => {
+                stats.record_tags(&tags);
+                if let (Some(seen), Some((hash, _))) = (&mut seen, read) {
+                    seen.record(hash)?;
+                }
+                for file_path in file_paths {
+                    sink.verify(&file_path)?;
+                    stats.record_converted(&file_path);
+                }
+            }
+            Err(e) => {
+                stats.record_failure(&name, &e);
+                diagnostics::error(options, clock, Some(&name), &e.to_string());
+            }
+        }
+    }
+    stats.collisions = sink.collisions();
+    Ok(())
+}
+
+/// The fields a `--where` filter tests, for a converted note. Every source's notes are
+/// markdown once converted, and only some can be pinned or trashed.
+fn note_fields(markdown: &Markdown) -> NoteFields<'_> {
+    NoteFields {
+        pinned: markdown.meta.pinned == Some(true),
+        trashed: markdown.meta.deleted == Some(true),
+        markdown: true,
+        tags: markdown.meta.tags.as_deref().unwrap_or_default(),
+        content: &markdown.content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::error::Notes2mdError;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    struct VecSink(Vec<Markdown>);
+
+    impl NoteSink for VecSink {
+        fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError> {
+            self.0.push(md.clone());
+            Ok(vec![PathBuf::from(&md.meta.title)])
+        }
+    }
+
+    #[test]
+    fn convert_notes_skips_seen_notes_and_counts_failures() {
+        let seen_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            seen_file: Some(seen_dir.path().join("seen.txt")),
+            ..Default::default()
+        };
+        let note = |name: &str, source: Result<&'static str, Error>| SourceNote {
+            name: name.to_string(),
+            source,
+        };
+        let notes = vec![
+            note("a", Ok("Shopping")),
+            note("b", Err(Error::new(ErrorKind::NotFound, "gone"))),
+            note("c", Ok("Shopping")),
+            note("d", Ok("")),
+        ];
+        let mut sink = VecSink(Vec::new());
+        let mut stats = ConversionStats::default();
+        convert_notes(
+            notes,
+            |text| text.as_bytes(),
+            |text, name| match text {
+                "" => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} is empty", name),
+                )),
+                _ => Ok(Markdown::new(text, "")),
+            },
+            &mut sink,
+            &options,
+            &SystemClock,
+            &mut stats,
+        )
+        .unwrap();
+
+        assert_eq!((1, 1, 2), (stats.converted, stats.skipped, stats.failed));
+        assert_eq!(
+            vec!["b", "d"],
+            stats
+                .failures
+                .iter()
+                .map(|f| f.note.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![PathBuf::from("Shopping")], stats.written);
+        assert_eq!(1, sink.0.len());
+    }
+}
@@ -0,0 +1,275 @@
+use super::datetime::format_timestamp;
+use super::driver::{convert_notes, SourceNote};
+use super::html::html_to_markdown;
+use super::markdown::{
+    age_between, with_default_tag, with_title_affixes, wrap_content, Markdown, MarkdownMeta,
+};
+use super::sink::dest_sink;
+use crate::clock::Clock;
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, Utc};
+use mail_parser::{Message, MessageParser, PartType};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+pub fn process(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    ConversionStats::collect(options, |stats| {
+        let mut email_files = Vec::new();
+        find_emails(&source_dir, &mut email_files)?;
+        email_files.sort();
+        if email_files.is_empty() {
+            println!("No email files found to process.");
+        }
+        let emails = email_files.into_iter().map(|email_file| SourceNote {
+            name: email_file
+                .strip_prefix(&source_dir)
+                .unwrap_or(&email_file)
+                .to_string_lossy()
+                .to_string(),
+            source: fs::read(&email_file).map(|bytes| (email_file, bytes)),
+        });
+        convert_notes(
+            emails,
+            |(_, bytes)| bytes,
+            |(email_file, bytes), name| {
+                let message = parse_email(&bytes, name)?;
+                convert_to_markdown(&message, &email_file, name, options, clock)
+            },
+            dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+            options,
+            clock,
+            stats,
+        )
+    })
+}
+
+/// Collect the `.eml` files beneath `dir`.
+fn find_emails(dir: &Path, email_files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_emails(&path, email_files)?;
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("eml"))
+        {
+            email_files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Parse the contents of an email file, undoing the transfer encodings and charsets of its
+/// headers and parts.
+fn parse_email<'x>(bytes: &'x [u8], name: &str) -> Result<Message<'x>, Error> {
+    MessageParser::default().parse(bytes).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("email: '{}' is not an email message", name),
+        )
+    })
+}
+
+/// The text of a message: its first inline text part, which for a message with only HTML is
+/// that converted to markdown. Attachments are ignored.
+fn message_text(message: &Message) -> Option<String> {
+    match &message.text_part(0)?.body {
+        PartType::Text(text) => Some(text.to_string()),
+        PartType::Html(html) => Some(html_to_markdown(html)),
+        _ => None,
+    }
+}
+
+/// The `Date` header of a message, in UTC.
+fn parse_email_date(message: &Message) -> Option<DateTime<Utc>> {
+    let date = message.date().filter(|d| d.is_valid())?;
+    DateTime::from_timestamp(date.to_timestamp(), 0)
+}
+
+fn convert_to_markdown(
+    message: &Message,
+    email_file: &Path,
+    name: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, Error> {
+    let date = match parse_email_date(message) {
+        Some(date) => date,
+        None if options.strict => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("email: '{}' is missing a valid Date header", name),
+            ))
+        }
+        None => clock.now(),
+    };
+    let title = match message.subject() {
+        Some(subject) if !subject.trim().is_empty() => subject.trim().to_string(),
+        _ => email_file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    let content = message_text(message).unwrap_or_default();
+    let content = content.replace("\r\n", "\n").trim().to_string() + "\n";
+    let content = match options.wrap {
+        Some(width) => wrap_content(&content, width),
+        None => content,
+    };
+    let age = match options.emit_age {
+        true => age_between(&date.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let date = format_timestamp(date, options);
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from email file={}",
+            env!("CARGO_PKG_VERSION"),
+            name
+        )),
+        false => None,
+    };
+
+    Ok(Markdown {
+        meta: MarkdownMeta {
+            title: with_title_affixes(title, options),
+            created: date.clone(),
+            modified: date,
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: with_default_tag(None, options),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: options.assume_markdown,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        },
+        content,
+        provenance,
+        file_prefix: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{FixedClock, SystemClock};
+
+    fn convert_file(path: &str) -> Markdown {
+        let path = PathBuf::from(path);
+        let bytes = fs::read(&path).unwrap();
+        convert_to_markdown(
+            &parse_email(&bytes, "sample.eml").unwrap(),
+            &path,
+            "sample.eml",
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn convert_plain_text_email() {
+        let md = convert_file("test_data/email/plain.eml");
+        assert_eq!("Groceries for the weekend", md.meta.title);
+        assert_eq!("2022-01-14T15:36:00.000Z", md.meta.created);
+        assert_eq!(md.meta.created, md.meta.modified);
+        assert_eq!(
+            "Eggs, milk and bread.\n\nDon't forget the coffee!\n",
+            md.content
+        );
+    }
+
+    #[test]
+    fn convert_html_only_email() {
+        let md = convert_file("test_data/email/html-only.eml");
+        assert_eq!("Café plans", md.meta.title);
+        assert_eq!("2022-03-01T09:00:00.000Z", md.meta.created);
+        let expected = "Plans\n==========\n\nMeet at **noon** at [the café](https://example.com/cafe).\n\n* bring a book\n* bring a friend\n";
+        assert_eq!(expected, md.content);
+    }
+
+    #[test]
+    fn multipart_prefers_plain_text_and_skips_attachments() {
+        let message = b"Content-Type: multipart/mixed; boundary=\"outer\"\r\n\r\n--outer\r\nContent-Type: multipart/alternative; boundary=inner\r\n\r\n--inner\r\nContent-Type: text/html\r\n\r\n<p>html</p>\r\n--inner\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\ncGxhaW4gdGV4dA==\r\n--inner--\r\n--outer\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; filename=a.txt\r\n\r\nattached\r\n--outer--\r\n";
+        assert_eq!(
+            Some(String::from("plain text")),
+            message_text(&parse_email(message, "multipart.eml").unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_email_dates() {
+        let date = |header: &str| {
+            let bytes = format!("Date: {}\r\n\r\nbody", header);
+            parse_email_date(&parse_email(bytes.as_bytes(), "dated.eml").unwrap())
+                .map(|d| format_timestamp(d, &ConversionOptions::default()))
+        };
+        assert_eq!(
+            Some(String::from("2022-01-14T15:36:00.000Z")),
+            date("Fri, 14 Jan 2022 07:36:00 -0800 (PST)")
+        );
+        assert_eq!(None, date("yesterday"));
+    }
+
+    #[test]
+    fn convert_email_without_date_uses_clock() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let md = convert_to_markdown(
+            &parse_email(b"Subject: Undated\n\nbody", "undated.eml").unwrap(),
+            Path::new("undated.eml"),
+            "undated.eml",
+            &ConversionOptions::default(),
+            &FixedClock(now),
+        )
+        .unwrap();
+        assert_eq!("2024-05-01T00:00:00.000Z", md.meta.created);
+
+        let options = ConversionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = convert_to_markdown(
+            &parse_email(b"Subject: Undated\n\nbody", "undated.eml").unwrap(),
+            Path::new("undated.eml"),
+            "undated.eml",
+            &options,
+            &FixedClock(now),
+        )
+        .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn process_email_directory() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            PathBuf::from("test_data/email"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(2, stats.converted);
+        assert!(dest_dir
+            .path()
+            .join("Groceries for the weekend.md")
+            .exists());
+        assert!(dest_dir.path().join("Café plans.md").exists());
+    }
+}
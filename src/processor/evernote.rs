@@ -0,0 +1,265 @@
+use super::datetime::format_timestamp;
+use super::driver::{convert_notes, SourceNote};
+use super::html::html_to_markdown;
+use super::markdown::{
+    age_between, normalize_tag_case, with_default_tag, with_title_affixes, wrap_content, Markdown,
+    MarkdownMeta,
+};
+use super::sink::dest_sink;
+use crate::clock::Clock;
+use crate::error::Notes2mdError;
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use html_escape::decode_html_entities;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref RE_NOTE: Regex = Regex::new(r"(?s)<note>(.*?)</note>").unwrap();
+    static ref RE_TAG: Regex = Regex::new(r"(?s)<tag>(.*?)</tag>").unwrap();
+}
+
+pub fn process(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    ConversionStats::collect(options, |stats| {
+        let source_name = source_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let export = load_export(&source_file)?;
+        let notes = RE_NOTE
+            .captures_iter(&export)
+            .enumerate()
+            .map(|(i, caps)| SourceNote {
+                name: format!("{}[{}]", source_name, i),
+                source: Ok(caps.get(1).map_or("", |m| m.as_str())),
+            });
+        convert_notes(
+            notes,
+            |note| note.as_bytes(),
+            |note, name| convert_to_markdown(note, name, options, clock),
+            dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+            options,
+            clock,
+            stats,
+        )?;
+        if stats.converted + stats.skipped + stats.failed == 0 {
+            println!("No notes found to process.");
+        }
+        Ok(())
+    })
+}
+
+/// The text of an ENEX export, which is always UTF-8 XML.
+fn load_export(source_file: &Path) -> Result<String, Error> {
+    let text = String::from_utf8(fs::read(source_file)?).map_err(|_| {
+        Notes2mdError::NotUtf8(format!(
+            "source_file: '{}' contains data which is not UTF8",
+            source_file.to_string_lossy()
+        ))
+        .into_io(ErrorKind::InvalidData)
+    })?;
+    match text.contains("<en-export") {
+        true => Ok(text),
+        false => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "source_file: '{}' is not an Evernote export",
+                source_file.to_string_lossy()
+            ),
+        )),
+    }
+}
+
+/// The text of the first `name` element within `note`, with CDATA unwrapped and entities
+/// decoded.
+fn element(note: &str, name: &str) -> Option<String> {
+    let start = note.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + note[start..].find(&format!("</{}>", name))?;
+    let text = note[start..end].trim();
+    match text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+    {
+        Some(cdata) => Some(cdata.to_string()),
+        None => Some(decode_html_entities(text).to_string()),
+    }
+}
+
+/// An ENEX timestamp like `20220113T223618Z`, which is always UTC.
+fn parse_enex_date(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.trim(), "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|d| d.and_utc())
+}
+
+fn convert_to_markdown(
+    note: &str,
+    name: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, Error> {
+    let title = match element(note, "title") {
+        Some(title) if !title.trim().is_empty() => title.trim().to_string(),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("note: '{}' has no title", name),
+            ))
+        }
+    };
+    let created = element(note, "created").and_then(|d| parse_enex_date(&d));
+    let modified = element(note, "updated").and_then(|d| parse_enex_date(&d));
+    let (created, modified) = match (created, modified) {
+        (Some(c), Some(m)) => (c, m),
+        _ if options.strict => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "note: '{}' is missing its creation or modification date",
+                    name
+                ),
+            ))
+        }
+        (Some(c), None) => (c, c),
+        (None, Some(m)) => (m, m),
+        (None, None) => (clock.now(), clock.now()),
+    };
+    let tags: Vec<String> = RE_TAG
+        .captures_iter(note)
+        .map(|caps| decode_html_entities(caps[1].trim()).to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let tags = match tags.is_empty() {
+        true => None,
+        false => Some(normalize_tag_case(tags, options.tag_case)),
+    };
+    let content = html_to_markdown(&element(note, "content").unwrap_or_default());
+    let age = match options.emit_age {
+        true => age_between(&created.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let (created, modified) = (
+        format_timestamp(created, options),
+        format_timestamp(modified, options),
+    );
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from evernote note={}",
+            env!("CARGO_PKG_VERSION"),
+            name
+        )),
+        false => None,
+    };
+
+    Ok(Markdown {
+        meta: MarkdownMeta {
+            title: with_title_affixes(title, options),
+            created,
+            modified,
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: with_default_tag(tags, options),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: options.assume_markdown,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        },
+        content: match options.wrap {
+            Some(width) => wrap_content(&content, width),
+            None => content,
+        },
+        provenance,
+        file_prefix: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn parse_enex_dates() {
+        assert_eq!(
+            Some(String::from("2022-01-13T22:36:18.000Z")),
+            parse_enex_date("20220113T223618Z")
+                .map(|d| format_timestamp(d, &ConversionOptions::default()))
+        );
+        assert_eq!(None, parse_enex_date("2022-01-13"));
+    }
+
+    #[test]
+    fn convert_enml_note() {
+        let export = fs::read_to_string("test_data/evernote.enex").unwrap();
+        let note = &RE_NOTE.captures(&export).unwrap()[1];
+        let md = convert_to_markdown(
+            note,
+            "evernote.enex[0]",
+            &ConversionOptions::default(),
+            &FixedClock(Utc::now()),
+        )
+        .unwrap();
+        assert_eq!("Groceries & errands", md.meta.title);
+        assert_eq!("2022-01-13T22:36:18.000Z", md.meta.created);
+        assert_eq!("2022-01-14T07:36:50.000Z", md.meta.modified);
+        assert_eq!(
+            Some(vec![String::from("home"), String::from("shopping")]),
+            md.meta.tags
+        );
+        assert_eq!(
+            "Before the **weekend**:\n\n- [x] eggs\n\n- [ ] milk\n\n[the full list](https://example.com/list)",
+            md.content
+        );
+
+        let options = ConversionOptions {
+            date_format: Some(String::from("%d.%m.%Y %H:%M")),
+            emit_age: true,
+            ..Default::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let md = convert_to_markdown(note, "evernote.enex[0]", &options, &FixedClock(now)).unwrap();
+        assert_eq!("13.01.2022 22:36", md.meta.created);
+        assert_eq!("14.01.2022 07:36", md.meta.modified);
+        assert_eq!(Some(String::from("2y 3mo")), md.meta.age);
+    }
+
+    #[test]
+    fn process_enex_export() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            PathBuf::from("test_data/evernote.enex"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &FixedClock(now),
+        )
+        .unwrap();
+
+        assert_eq!(2, stats.converted);
+        assert!(dest_dir.path().join("Groceries & errands.md").exists());
+        let undated = fs::read_to_string(dest_dir.path().join("Undated.md")).unwrap();
+        assert!(undated.contains("created: \"2024-05-01T00:00:00.000Z\"\n"));
+        assert!(undated.ends_with("---\nno dates here\n"));
+    }
+}
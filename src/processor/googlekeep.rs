@@ -0,0 +1,302 @@
+use super::datetime::format_timestamp;
+use super::driver::{convert_notes, SourceNote};
+use super::markdown::{
+    age_between, normalize_tag_case, with_default_tag, with_title_affixes, wrap_content, Markdown,
+    MarkdownMeta,
+};
+use super::simplenote::title_from_content;
+use super::sink::dest_sink;
+use crate::clock::Clock;
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// One note of a Google Takeout Keep export, each of which is a JSON file of its own.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct KeepNote {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    text_content: String,
+    /// the items of a checklist note, which has no text content
+    list_content: Option<Vec<KeepListItem>>,
+    labels: Option<Vec<KeepLabel>>,
+    #[serde(default)]
+    is_trashed: bool,
+    #[serde(default)]
+    is_pinned: bool,
+    /// only in newer exports
+    created_timestamp_usec: Option<i64>,
+    user_edited_timestamp_usec: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct KeepListItem {
+    text: String,
+    #[serde(default)]
+    is_checked: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct KeepLabel {
+    name: String,
+}
+
+pub fn process(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    ConversionStats::collect(options, |stats| {
+        let note_files = find_notes(&source_dir)?;
+        if note_files.is_empty() {
+            println!("No Google Keep notes found to process.");
+        }
+        let notes = note_files.into_iter().map(|note_file| SourceNote {
+            name: note_file
+                .strip_prefix(&source_dir)
+                .unwrap_or(&note_file)
+                .to_string_lossy()
+                .to_string(),
+            source: fs::read_to_string(&note_file),
+        });
+        convert_notes(
+            notes,
+            |text| text.as_bytes(),
+            |text, name| {
+                let note: KeepNote = serde_json::from_str(&text).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("note: '{}' is not a Google Keep note: {}", name, e),
+                    )
+                })?;
+                convert_to_markdown(note, name, options, clock)
+            },
+            dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+            options,
+            clock,
+            stats,
+        )
+    })
+}
+
+/// The `.json` note files in a Keep export directory, in name order. Takeout puts an `.html`
+/// copy and any attachments beside each note, which are left alone.
+fn find_notes(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut note_files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+        {
+            note_files.push(path);
+        }
+    }
+    note_files.sort();
+    Ok(note_files)
+}
+
+/// A Keep timestamp in microseconds since the Unix epoch.
+fn parse_keep_timestamp(usec: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_micros(usec).single()
+}
+
+/// The body of a note: its text, or its checklist as a task list.
+fn note_body(note: &KeepNote) -> String {
+    match &note.list_content {
+        Some(items) if note.text_content.is_empty() => items
+            .iter()
+            .map(|item| match item.is_checked {
+                true => format!("- [x] {}", item.text),
+                false => format!("- [ ] {}", item.text),
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        _ => note.text_content.clone(),
+    }
+}
+
+/// The text a title is derived from when a note has none: its text, or its checklist items
+/// without their checkboxes.
+fn untitled_text(note: &KeepNote) -> String {
+    match &note.list_content {
+        Some(items) if note.text_content.is_empty() => items
+            .iter()
+            .map(|item| item.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        _ => note.text_content.clone(),
+    }
+}
+
+fn convert_to_markdown(
+    note: KeepNote,
+    name: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, Error> {
+    let body = note_body(&note);
+    let title = match note.title.trim() {
+        "" => title_from_content(&untitled_text(&note), options),
+        title => title.to_string(),
+    };
+    let created = note.created_timestamp_usec.and_then(parse_keep_timestamp);
+    let modified = note
+        .user_edited_timestamp_usec
+        .and_then(parse_keep_timestamp);
+    let (created, modified) = match (created, modified) {
+        (Some(c), Some(m)) => (c, m),
+        // older exports only have the edited time
+        (None, Some(m)) => (m, m),
+        _ if options.strict => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("note: '{}' is missing its modification date", name),
+            ))
+        }
+        (Some(c), None) => (c, c),
+        (None, None) => (clock.now(), clock.now()),
+    };
+    let tags: Vec<String> = note
+        .labels
+        .unwrap_or_default()
+        .into_iter()
+        .map(|label| label.name.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let tags = match tags.is_empty() {
+        true => None,
+        false => Some(normalize_tag_case(tags, options.tag_case)),
+    };
+    let age = match options.emit_age {
+        true => age_between(&created.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let (created, modified) = (
+        format_timestamp(created, options),
+        format_timestamp(modified, options),
+    );
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from googlekeep file={}",
+            env!("CARGO_PKG_VERSION"),
+            name
+        )),
+        false => None,
+    };
+
+    Ok(Markdown {
+        meta: MarkdownMeta {
+            title: with_title_affixes(title, options),
+            created,
+            modified,
+            deleted: if note.is_trashed { Some(true) } else { None },
+            favorited: None,
+            pinned: if note.is_pinned { Some(true) } else { None },
+            tags: with_default_tag(tags, options),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: options.assume_markdown,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        },
+        content: match options.wrap {
+            Some(width) => wrap_content(&body, width),
+            None => body,
+        },
+        provenance,
+        file_prefix: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    #[test]
+    fn parse_keep_timestamps() {
+        assert_eq!(
+            Some(String::from("2022-01-13T22:36:18.906Z")),
+            parse_keep_timestamp(1642113378906000)
+                .map(|d| format_timestamp(d, &ConversionOptions::default()))
+        );
+    }
+
+    #[test]
+    fn convert_keep_note() {
+        let note: KeepNote = serde_json::from_str(
+            r#"{
+                "color": "DEFAULT",
+                "isTrashed": true,
+                "isPinned": true,
+                "isArchived": false,
+                "textContent": "milk\neggs",
+                "title": "Groceries",
+                "userEditedTimestampUsec": 1642145810656000,
+                "createdTimestampUsec": 1642113378906000,
+                "labels": [{"name": "home"}, {"name": "shopping"}]
+            }"#,
+        )
+        .unwrap();
+        let md = convert_to_markdown(
+            note,
+            "Groceries.json",
+            &ConversionOptions::default(),
+            &FixedClock(Utc::now()),
+        )
+        .unwrap();
+        assert_eq!("Groceries", md.meta.title);
+        assert_eq!("2022-01-13T22:36:18.906Z", md.meta.created);
+        assert_eq!("2022-01-14T07:36:50.656Z", md.meta.modified);
+        assert_eq!(Some(true), md.meta.deleted);
+        assert_eq!(Some(true), md.meta.pinned);
+        assert_eq!(
+            Some(vec![String::from("home"), String::from("shopping")]),
+            md.meta.tags
+        );
+        assert_eq!("milk\neggs", md.content);
+    }
+
+    #[test]
+    fn process_keep_directory() {
+        let source_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            source_dir.path().join("Untitled.json"),
+            r#"{"title": "", "userEditedTimestampUsec": 1642145810656000, "listContent": [
+                {"text": "Weekend jobs", "isChecked": false},
+                {"text": "mow the lawn", "isChecked": true}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(source_dir.path().join("Untitled.html"), "<html></html>").unwrap();
+        fs::write(source_dir.path().join("Broken.json"), "[").unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+
+        let stats = process(
+            source_dir.path().to_path_buf(),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &FixedClock(Utc::now()),
+        )
+        .unwrap();
+
+        assert_eq!((1, 1), (stats.converted, stats.failed));
+        let written = fs::read_to_string(dest_dir.path().join("Weekend jobs.md")).unwrap();
+        assert!(written.contains("created: \"2022-01-14T07:36:50.656Z\"\n"));
+        assert!(written.ends_with("---\n- [ ] Weekend jobs\n- [x] mow the lawn\n"));
+    }
+}
@@ -0,0 +1,53 @@
+use html2md::common::get_tag_attr;
+use html2md::{Handle, StructuredPrinter, TagHandler, TagHandlerFactory};
+use std::collections::HashMap;
+
+/// Leaves out an element and everything in it, for the parts of a page which aren't its text.
+struct Hidden;
+
+impl TagHandler for Hidden {
+    fn handle(&mut self, _tag: &Handle, _printer: &mut StructuredPrinter) {}
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+
+    fn skip_descendants(&self) -> bool {
+        true
+    }
+}
+
+/// An Evernote `<en-todo>` checkbox, as the start of a task list item.
+struct Todo;
+
+impl TagHandler for Todo {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        match get_tag_attr(tag, "checked").as_deref() {
+            Some("true") => printer.append_str("- [x] "),
+            _ => printer.append_str("- [ ] "),
+        }
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+}
+
+impl TagHandlerFactory for Hidden {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(Hidden)
+    }
+}
+
+impl TagHandlerFactory for Todo {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(Todo)
+    }
+}
+
+/// A markdown rendering of an HTML email body or an Evernote ENML note. Checkboxes become task
+/// list items; styles, scripts, media and other Evernote elements are dropped.
+pub(crate) fn html_to_markdown(html: &str) -> String {
+    let mut handlers: HashMap<String, Box<dyn TagHandlerFactory>> = HashMap::new();
+    for hidden in ["head", "style", "script", "en-media", "en-crypt"] {
+        handlers.insert(hidden.to_string(), Box::new(Hidden));
+    }
+    handlers.insert("en-todo".to_string(), Box::new(Todo));
+    html2md::parse_html_custom(html, &handlers)
+}
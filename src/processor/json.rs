@@ -0,0 +1,326 @@
+use super::datetime::format_timestamp;
+use super::driver::{convert_notes, SourceNote};
+use super::markdown::{
+    age_between, normalize_tag_case, with_default_tag, with_title_affixes, wrap_content, Markdown,
+    MarkdownMeta,
+};
+use super::simplenote::title_from_content;
+use super::sink::dest_sink;
+use crate::clock::Clock;
+use crate::error::Notes2mdError;
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+/// Where in each note of a JSON export its parts are found, as dotted paths like
+/// `content.text`; a number steps into an array. In a batch config any left out take their
+/// default.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonFields {
+    /// the array of notes, or `None` when it is the whole document
+    pub notes: Option<String>,
+    pub title: String,
+    pub body: String,
+    pub created: String,
+    pub modified: String,
+    pub tags: String,
+}
+
+impl Default for JsonFields {
+    fn default() -> JsonFields {
+        JsonFields {
+            notes: None,
+            title: String::from("title"),
+            body: String::from("content"),
+            created: String::from("created"),
+            modified: String::from("modified"),
+            tags: String::from("tags"),
+        }
+    }
+}
+
+pub fn process(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    fields: &JsonFields,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    ConversionStats::collect(options, |stats| {
+        let source_name = source_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let document: Value = serde_json::from_str(&fs::read_to_string(&source_file)?)
+            .map_err(|e| invalid_source(&source_file, &e.to_string()))?;
+        let notes = match &fields.notes {
+            Some(path) => field(&document, path),
+            None => Some(&document),
+        };
+        let notes = match notes {
+            Some(Value::Array(notes)) => notes,
+            _ => {
+                return Err(invalid_source(
+                    &source_file,
+                    &format!(
+                        "'{}' is not an array of notes",
+                        fields.notes.as_deref().unwrap_or("the document")
+                    ),
+                ))
+            }
+        };
+        let notes = notes.iter().enumerate().map(|(i, note)| SourceNote {
+            name: format!("{}[{}]", source_name, i),
+            source: Ok((note, note.to_string())),
+        });
+        convert_notes(
+            notes,
+            |(_, text)| text.as_bytes(),
+            |(note, _), name| convert_to_markdown(note, fields, name, options, clock),
+            dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+            options,
+            clock,
+            stats,
+        )
+    })
+}
+
+fn invalid_source(source_file: &std::path::Path, reason: &str) -> Error {
+    Notes2mdError::InvalidJson(format!(
+        "source_file: '{}' can't be read as JSON notes: {}",
+        source_file.to_string_lossy(),
+        reason
+    ))
+    .into_io(ErrorKind::InvalidData)
+}
+
+/// The value at the dotted `path` within `value`, stepping into objects by key and into arrays
+/// by index.
+fn field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+/// A date given as an RFC 3339 string or as seconds since the Unix epoch.
+fn parse_json_date(value: &Value) -> Option<DateTime<Utc>> {
+    let date = match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s.trim())
+            .ok()?
+            .with_timezone(&Utc),
+        Value::Number(n) => {
+            let seconds = n.as_f64()?;
+            Utc.timestamp_millis_opt((seconds * 1000.0) as i64)
+                .single()?
+        }
+        _ => return None,
+    };
+    Some(date)
+}
+
+/// Tags given as an array of strings, or as one comma separated string.
+fn parse_json_tags(value: &Value) -> Option<Vec<String>> {
+    let tags: Vec<String> = match value {
+        Value::Array(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect(),
+        Value::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect(),
+        _ => return None,
+    };
+    Some(tags)
+}
+
+fn convert_to_markdown(
+    note: &Value,
+    fields: &JsonFields,
+    name: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, Error> {
+    let body = match field(note, &fields.body) {
+        Some(Value::String(body)) => body.clone(),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("note: '{}' has no '{}' text field", name, fields.body),
+            ))
+        }
+    };
+    let title = match field(note, &fields.title).and_then(Value::as_str) {
+        Some(title) if !title.trim().is_empty() => title.trim().to_string(),
+        _ => title_from_content(&body, options),
+    };
+    let created = field(note, &fields.created).and_then(parse_json_date);
+    let modified = field(note, &fields.modified).and_then(parse_json_date);
+    let (created, modified) = match (created, modified) {
+        (Some(c), Some(m)) => (c, m),
+        _ if options.strict => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "note: '{}' is missing its creation or modification date",
+                    name
+                ),
+            ))
+        }
+        (Some(c), None) => (c, c),
+        (None, Some(m)) => (m, m),
+        (None, None) => (clock.now(), clock.now()),
+    };
+    let tags = field(note, &fields.tags)
+        .and_then(parse_json_tags)
+        .map(|tags| normalize_tag_case(tags, options.tag_case));
+    let age = match options.emit_age {
+        true => age_between(&created.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let (created, modified) = (
+        format_timestamp(created, options),
+        format_timestamp(modified, options),
+    );
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from json note={}",
+            env!("CARGO_PKG_VERSION"),
+            name
+        )),
+        false => None,
+    };
+
+    Ok(Markdown {
+        meta: MarkdownMeta {
+            title: with_title_affixes(title, options),
+            created,
+            modified,
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: with_default_tag(tags, options),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: options.assume_markdown,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        },
+        content: match options.wrap {
+            Some(width) => wrap_content(&body, width),
+            None => body,
+        },
+        provenance,
+        file_prefix: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    #[test]
+    fn field_follows_dotted_paths() {
+        let note = serde_json::json!({"content": {"text": "body"}, "labels": ["a", "b"]});
+        assert_eq!(Some(&Value::from("body")), field(&note, "content.text"));
+        assert_eq!(Some(&Value::from("b")), field(&note, "labels.1"));
+        assert_eq!(None, field(&note, "content.missing"));
+        assert_eq!(None, field(&note, "labels.2"));
+    }
+
+    #[test]
+    fn convert_custom_shaped_note() {
+        let note = serde_json::json!({
+            "heading": "Groceries",
+            "content": {"text": "milk\neggs"},
+            "meta": {"at": "2022-01-13T22:36:18.906Z", "edited": 1642145810},
+            "labels": "shopping, home"
+        });
+        let fields = JsonFields {
+            notes: None,
+            title: String::from("heading"),
+            body: String::from("content.text"),
+            created: String::from("meta.at"),
+            modified: String::from("meta.edited"),
+            tags: String::from("labels"),
+        };
+        let md = convert_to_markdown(
+            &note,
+            &fields,
+            "notes.json[0]",
+            &ConversionOptions::default(),
+            &FixedClock(Utc::now()),
+        )
+        .unwrap();
+        assert_eq!("Groceries", md.meta.title);
+        assert_eq!("milk\neggs", md.content);
+        assert_eq!("2022-01-13T22:36:18.906Z", md.meta.created);
+        assert_eq!("2022-01-14T07:36:50.000Z", md.meta.modified);
+        assert_eq!(
+            Some(vec![String::from("shopping"), String::from("home")]),
+            md.meta.tags
+        );
+    }
+
+    #[test]
+    fn process_json_export() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("export.json");
+        fs::write(
+            &source_file,
+            r#"{"data": {"items": [
+                {"body": "Untitled note\nwith text", "updated": "2022-01-14T07:36:50.656Z"},
+                {"name": "Named", "body": "text"},
+                {"name": "No body"}
+            ]}}"#,
+        )
+        .unwrap();
+        let fields = JsonFields {
+            notes: Some(String::from("data.items")),
+            title: String::from("name"),
+            body: String::from("body"),
+            modified: String::from("updated"),
+            ..JsonFields::default()
+        };
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &fields,
+            &ConversionOptions::default(),
+            &FixedClock(Utc::now()),
+        )
+        .unwrap();
+
+        assert_eq!(2, stats.converted);
+        assert_eq!(1, stats.failed);
+        let untitled = fs::read_to_string(dest_dir.path().join("Untitled note.md")).unwrap();
+        assert!(untitled.contains("created: \"2022-01-14T07:36:50.656Z\"\n"));
+        assert!(untitled.ends_with("---\nUntitled note\nwith text\n"));
+        assert!(dest_dir.path().join("Named.md").exists());
+        assert_eq!(
+            "note: 'export.json[2]' has no 'body' text field",
+            stats.failures[0].message
+        );
+    }
+}
@@ -1,14 +1,26 @@
+use super::datetime::parse_note_timestamp;
+use super::sink::dest_sink;
+use crate::clock::{Clock, SystemClock};
+use crate::diagnostics;
+use crate::error::Notes2mdError;
+use crate::space::SystemFreeSpace;
+use crate::{ConversionOptions, OutputFormat, TagCase};
+use chrono::{DateTime, Datelike, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::ffi::OsStr;
+use serde::{de, ser, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs;
 use std::io::prelude::*;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MarkdownMeta {
     pub title: String,
     pub created: String,
@@ -21,67 +33,978 @@ pub struct MarkdownMeta {
     pub pinned: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    /// how long ago the note was created, as of conversion; it is not updated afterwards
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_url: Option<String>,
+    /// other names the note is known by, such as the full URL a shortened title came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+    /// whether the note is markdown, only given when forced by `assume_markdown`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<bool>,
+    /// the id the note had in its source, to match the file back to the note it came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// front matter notes2md doesn't write itself, like keys added by hand, kept as read so a
+    /// note which is rewritten doesn't lose them
+    #[serde(flatten)]
+    pub extra: serde_yaml::Mapping,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Markdown {
+    #[serde(flatten)]
     pub meta: MarkdownMeta,
     pub content: String,
+    /// where the note came from, written as a YAML comment at the top of the front matter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<String>,
+    /// put before the note's file name, but not its title
+    #[serde(skip)]
+    pub file_prefix: Option<String>,
+}
+
+impl MarkdownMeta {
+    /// Front matter with just a title. The dates are empty until set.
+    pub fn new(title: &str) -> MarkdownMeta {
+        MarkdownMeta {
+            title: title.to_string(),
+            created: String::new(),
+            modified: String::new(),
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: None,
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        }
+    }
+}
+
+impl Markdown {
+    /// A note with `title` and `content`, to be filled out with the `with_` methods.
+    ///
+    /// ```
+    /// use notes2md::processor::markdown::Markdown;
+    ///
+    /// let note = Markdown::new("Groceries", "milk, eggs and\nbread")
+    ///     .with_created("2022-01-14T07:36:50.656Z")
+    ///     .with_tags(["shopping", "home"]);
+    /// assert_eq!("2022-01-14T07:36:50.656Z", note.meta.created);
+    /// assert_eq!(Some(vec![String::from("shopping"), String::from("home")]), note.meta.tags);
+    /// assert_eq!(4, note.word_count());
+    /// ```
+    pub fn new(title: &str, content: &str) -> Markdown {
+        Markdown {
+            meta: MarkdownMeta::new(title),
+            content: content.to_string(),
+            provenance: None,
+            file_prefix: None,
+        }
+    }
+
+    /// Set the creation date, and the modification date too if it isn't already set.
+    pub fn with_created(mut self, created: &str) -> Markdown {
+        self.meta.created = created.to_string();
+        if self.meta.modified.is_empty() {
+            self.meta.modified = created.to_string();
+        }
+        self
+    }
+
+    pub fn with_modified(mut self, modified: &str) -> Markdown {
+        self.meta.modified = modified.to_string();
+        self
+    }
+
+    pub fn with_tags<I, S>(mut self, tags: I) -> Markdown
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.meta.tags = Some(tags.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The number of whitespace separated words in the content.
+    pub fn word_count(&self) -> usize {
+        self.content.split_whitespace().count()
+    }
 }
 
 impl fmt::Display for Markdown {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match serialize_markdown(self) {
+        // marked so a failure can't be mistaken for note text
+        match serialize_markdown(self, &ConversionOptions::default()) {
             Ok(s) => write!(f, "{}", s),
-            Err(e) => write!(f, "{}", format!("{}", e)),
+            Err(e) => write!(f, "YAML ERROR: {}", e),
         }
     }
 }
 
-fn serialize_markdown(markdown: &Markdown) -> Result<String, serde_yaml::Error> {
-    match serde_yaml::to_string(&markdown.meta) {
-        Ok(m) => Ok(format!("{}{}\n{}\n", m, "---", markdown.content)),
+pub(crate) fn serialize_markdown(
+    markdown: &Markdown,
+    options: &ConversionOptions,
+) -> Result<String, serde_yaml::Error> {
+    match front_matter_yaml(&markdown.meta, options) {
+        Ok(m) => {
+            // the comment goes after the opening `---` so parsers still find it on line 1
+            let meta = match &markdown.provenance {
+                Some(p) => match m.strip_prefix("---\n") {
+                    Some(fields) => format!("---\n# {}\n{}", p, fields),
+                    None => format!("# {}\n{}", p, m),
+                },
+                None => m,
+            };
+            // a note without a body ends with its front matter, and a raw body ends as it was
+            // exported
+            match markdown.content.is_empty() {
+                true => Ok(format!("{}---\n", meta)),
+                false if options.raw_content => Ok(format!("{}---\n{}", meta, markdown.content)),
+                false => Ok(format!("{}{}\n{}\n", meta, "---", markdown.content)),
+            }
+        }
         Err(e) => Err(e),
     }
 }
 
-fn title_to_filepath(dest_dir: &PathBuf, title: &str) -> Result<PathBuf, std::io::Error> {
-    lazy_static! {
-        static ref RE_BOGUS_FILENAME_CHARS: Regex = Regex::new(r#"[:?]"#).unwrap();
+/// The front matter fields `meta` is written with, with its dates under the names given by
+/// `created_key` and `modified_key`.
+fn front_matter_yaml(
+    meta: &MarkdownMeta,
+    options: &ConversionOptions,
+) -> Result<String, serde_yaml::Error> {
+    let renames = date_key_renames(options);
+    if renames.is_empty() {
+        return serde_yaml::to_string(meta);
+    }
+    match serde_yaml::to_value(meta)? {
+        serde_yaml::Value::Mapping(fields) => {
+            serde_yaml::to_string(&rename_keys(fields, &renames).map_err(ser::Error::custom)?)
+        }
+        other => serde_yaml::to_string(&other),
     }
+}
 
-    if "".eq(title) {
+/// The date fields renamed by `created_key` and `modified_key`, as their usual name and the
+/// name they are written with.
+fn date_key_renames(options: &ConversionOptions) -> Vec<(&str, &str)> {
+    [
+        ("created", options.created_key.as_deref()),
+        ("modified", options.modified_key.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(from, to)| to.filter(|to| *to != from).map(|to| (from, to)))
+    .collect()
+}
+
+/// The front matter keys `MarkdownMeta` writes its fields with, besides those it keeps from a
+/// note's own front matter.
+const META_KEYS: [&str; 14] = [
+    "title",
+    "created",
+    "modified",
+    "deleted",
+    "favorited",
+    "pinned",
+    "tags",
+    "type",
+    "age",
+    "share_url",
+    "publish_url",
+    "aliases",
+    "markdown",
+    "id",
+];
+
+/// Check `created_key` and `modified_key` name neither another front matter field nor the same
+/// date, so renaming the dates never writes a key twice. The two may swap names.
+pub fn validate_date_keys(
+    created_key: Option<&str>,
+    modified_key: Option<&str>,
+) -> Result<(), std::io::Error> {
+    let (created, modified) = (
+        created_key.unwrap_or("created"),
+        modified_key.unwrap_or("modified"),
+    );
+    let invalid = |option: &str, key: &str, problem: &str| {
         Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}: '{}' is not valid, {}", option, key, problem),
+        ))
+    };
+    for (option, key) in [("created_key", created), ("modified_key", modified)] {
+        if key.trim().is_empty() {
+            return invalid(option, key, "it is empty");
+        }
+        if META_KEYS.contains(&key) && !["created", "modified"].contains(&key) {
+            return invalid(option, key, "another front matter field has that name");
+        }
+    }
+    match created == modified {
+        true => invalid("modified_key", modified, "the creation date has that name"),
+        false => Ok(()),
+    }
+}
+
+/// Rename the keys of `fields` from the first to the second of each pair in `renames`, keeping
+/// their order. A new name which is already taken is an error, rather than losing a field.
+fn rename_keys(
+    fields: serde_yaml::Mapping,
+    renames: &[(&str, &str)],
+) -> Result<serde_yaml::Mapping, String> {
+    let mut renamed = serde_yaml::Mapping::new();
+    for (key, value) in fields {
+        let key = match renames.iter().find(|(from, _)| key.as_str() == Some(from)) {
+            Some((_, to)) => serde_yaml::Value::from(*to),
+            None => key,
+        };
+        if renamed.contains_key(&key) {
+            return Err(format!(
+                "front matter key '{}' is used twice",
+                key.as_str().unwrap_or_default()
+            ));
+        }
+        renamed.insert(key, value);
+    }
+    Ok(renamed)
+}
+
+/// Escape characters which markdown would otherwise interpret, so plain text renders verbatim.
+/// List markers are only significant at the start of a line, so only those are escaped.
+pub fn escape_markdown(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        for (i, c) in line.char_indices() {
+            match c {
+                '\\' | '`' | '*' | '_' | '#' | '[' | ']' | '<' | '>' | '|' | '~' => {
+                    escaped.push('\\')
+                }
+                '-' | '+' if i == indent => escaped.push('\\'),
+                _ => (),
+            }
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Rewrite reference-style links like `[text][id]`, `[text][]` or `[id]` into inline
+/// `[text](url)` links, using the `[id]: url` definitions found in the content. Definitions
+/// which were inlined are removed, with the blank lines before them when nothing follows but
+/// other definitions; links without a matching definition are left as they are.
+pub fn inline_reference_links(content: &str) -> String {
+    lazy_static! {
+        static ref RE_LINK_DEFINITION: Regex =
+            Regex::new(r#"^ {0,3}\[([^\]]+)\]:\s*(\S+)(?:\s+("[^"]*"|'[^']*'|\([^)]*\)))?\s*$"#)
+                .unwrap();
+        static ref RE_REFERENCE_LINK: Regex =
+            Regex::new(r"\[([^\[\]]*)\](?:\[([^\[\]]*)\])?").unwrap();
+    }
+
+    let mut definitions: HashMap<String, String> = HashMap::new();
+    for line in content.lines() {
+        if let Some(caps) = RE_LINK_DEFINITION.captures(line) {
+            let url = match caps.get(3) {
+                Some(title) => format!("{} {}", &caps[2], title.as_str()),
+                None => caps[2].to_string(),
+            };
+            // reference ids are case-insensitive and the first definition wins
+            definitions.entry(caps[1].to_lowercase()).or_insert(url);
+        }
+    }
+    if definitions.is_empty() {
+        return content.to_string();
+    }
+
+    let mut used: Vec<String> = Vec::new();
+    let linked = RE_REFERENCE_LINK.replace_all(content, |caps: &regex::Captures| {
+        let whole = caps.get(0).map_or("", |m| m.as_str());
+        let after = &content[caps.get(0).map_or(0, |m| m.end())..];
+        let id = match caps.get(2) {
+            Some(id) if !id.as_str().is_empty() => id.as_str(),
+            Some(_) => &caps[1],
+            // a lone `[id]` is a shortcut reference, unless it starts an inline link or is
+            // a definition itself
+            None if after.starts_with(['(', ':']) => return whole.to_string(),
+            None => &caps[1],
+        }
+        .to_lowercase();
+        match definitions.get(&id) {
+            Some(url) => {
+                used.push(id);
+                format!("[{}]({})", &caps[1], url)
+            }
+            None => whole.to_string(),
+        }
+    });
+    if used.is_empty() {
+        return content.to_string();
+    }
+
+    let lines: Vec<&str> = linked.split_inclusive('\n').collect();
+    let is_inlined_definition =
+        |line: &str| match RE_LINK_DEFINITION.captures(line.trim_end_matches(['\r', '\n'])) {
+            Some(caps) => used.contains(&caps[1].to_lowercase()),
+            None => false,
+        };
+    let is_blank = |line: &str| line.trim().is_empty();
+    // definitions usually sit at the bottom, so the blank lines before them go with them
+    let last_text = lines
+        .iter()
+        .rposition(|line| !is_blank(line) && !is_inlined_definition(line));
+    let last_definition = lines.iter().rposition(|line| is_inlined_definition(line));
+    let mut inlined = String::with_capacity(linked.len());
+    for (i, line) in lines.iter().enumerate() {
+        let before_last_definitions =
+            is_blank(line) && last_text.is_none_or(|t| i > t) && last_definition > Some(i);
+        if !is_inlined_definition(line) && !before_last_definitions {
+            inlined.push_str(line);
+        }
+    }
+    inlined
+}
+
+/// The longest prefix of `text` with at most `max_chars` characters, never splitting a
+/// multibyte character.
+pub fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
+}
+
+/// Cap `content` at `max_chars` characters, marking any truncation with a trailing `…`.
+pub fn truncate_content(content: &str, max_chars: usize) -> String {
+    let truncated = truncate_chars(content, max_chars);
+    if truncated.len() < content.len() {
+        format!("{}…", truncated)
+    } else {
+        content.to_string()
+    }
+}
+
+/// A short human readable age like `2y 3mo` for a note created at `created`, as of `now`.
+/// Ages under a month are given in days. Returns `None` if `created` isn't an RFC 3339 date.
+pub fn age_between(created: &str, now: DateTime<Utc>) -> Option<String> {
+    let created = DateTime::parse_from_rfc3339(created)
+        .ok()?
+        .with_timezone(&Utc);
+    if created > now {
+        return Some(String::from("0d"));
+    }
+
+    let mut months =
+        (now.year() - created.year()) * 12 + now.month() as i32 - created.month() as i32;
+    if (now.day(), now.time()) < (created.day(), created.time()) {
+        months -= 1;
+    }
+    let (years, months) = (months / 12, months % 12);
+    Some(match (years, months) {
+        (0, 0) => format!("{}d", (now - created).num_days()),
+        (0, m) => format!("{}mo", m),
+        (y, 0) => format!("{}y", y),
+        (y, m) => format!("{}y {}mo", y, m),
+    })
+}
+
+/// Split each tag on `separator`, for sources which store several tags in one string.
+/// Empty pieces are dropped and duplicates removed, keeping the first occurrence.
+pub fn split_tags(tags: Vec<String>, separator: char) -> Vec<String> {
+    let mut split: Vec<String> = Vec::new();
+    for tag in tags {
+        for piece in tag.split(separator).map(str::trim) {
+            if !piece.is_empty() && !split.iter().any(|t| t == piece) {
+                split.push(piece.to_string());
+            }
+        }
+    }
+    split
+}
+
+/// Split a note into one note per heading of `level`, each titled with its heading text and
+/// holding the section below it, with the rest of the front matter copied from the original.
+/// Any text before the first such heading stays as a note under the original title. Headings
+/// inside fenced code blocks are ignored, and a note with no such headings is returned as is.
+pub fn split_on_headings(markdown: Markdown, level: usize) -> Vec<Markdown> {
+    let marker = format!("{} ", "#".repeat(level));
+    let mut preamble = String::new();
+    let mut sections: Vec<(String, String)> = Vec::new();
+    let mut in_fence = false;
+    for line in markdown.content.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        match line.strip_prefix(&marker) {
+            Some(heading) if !in_fence && level > 0 => {
+                let title = heading.trim().trim_end_matches('#').trim_end();
+                sections.push((title.to_string(), String::new()));
+            }
+            _ => match sections.last_mut() {
+                Some((_, body)) => body.push_str(line),
+                None => preamble.push_str(line),
+            },
+        }
+    }
+    if sections.is_empty() {
+        return vec![markdown];
+    }
+
+    let section_note = |title: String, body: &str| Markdown {
+        meta: MarkdownMeta {
+            title,
+            ..markdown.meta.clone()
+        },
+        content: body.trim_matches('\n').to_string(),
+        provenance: markdown.provenance.clone(),
+        file_prefix: markdown.file_prefix.clone(),
+    };
+    let mut notes = Vec::new();
+    if !preamble.trim().is_empty() {
+        notes.push(section_note(markdown.meta.title.clone(), &preamble));
+    }
+    for (title, body) in sections {
+        notes.push(section_note(title, &body));
+    }
+    notes
+}
+
+/// Reflow the paragraphs and list items of `content` into lines of at most `width` characters.
+/// Fenced and indented code, headings, tables, quotes and HTML are left as they are, and words
+/// longer than `width`, like URLs, are never broken.
+pub fn wrap_content(content: &str, width: usize) -> String {
+    lazy_static! {
+        static ref RE_LIST_MARKER: Regex = Regex::new(r"^\s*(?:[-*+]|\d+[.)])\s+").unwrap();
+    }
+
+    let mut wrapped: Vec<String> = Vec::new();
+    // the paragraph being collected, as the indent for its later lines and its text
+    let mut paragraph: Option<(String, String)> = None;
+    let mut in_fence = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let verbatim = in_fence
+            || trimmed.is_empty()
+            || trimmed.starts_with("```")
+            || trimmed.starts_with("~~~")
+            || line.starts_with("    ")
+            || line.starts_with('\t')
+            || trimmed.starts_with(['#', '|', '>', '<']);
+        if verbatim {
+            wrap_paragraph(paragraph.take(), width, &mut wrapped);
+            wrapped.push(line.to_string());
+            continue;
+        }
+        match (RE_LIST_MARKER.find(line), &mut paragraph) {
+            (Some(marker), _) => {
+                wrap_paragraph(paragraph.take(), width, &mut wrapped);
+                let indent = " ".repeat(marker.as_str().chars().count());
+                paragraph = Some((indent, line.trim_end().to_string()));
+            }
+            (None, Some((_, text))) => {
+                text.push(' ');
+                text.push_str(line.trim());
+            }
+            (None, None) => paragraph = Some((String::new(), line.trim_end().to_string())),
+        }
+    }
+    wrap_paragraph(paragraph, width, &mut wrapped);
+
+    let mut result = wrapped.join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+fn wrap_paragraph(paragraph: Option<(String, String)>, width: usize, wrapped: &mut Vec<String>) {
+    if let Some((indent, text)) = paragraph {
+        let options = textwrap::Options::new(width)
+            .subsequent_indent(&indent)
+            .break_words(false);
+        wrapped.extend(textwrap::wrap(&text, options).into_iter().map(String::from));
+    }
+}
+
+/// `title` with `title_prefix` before it and `title_suffix` after it. An empty title is left
+/// empty, so a note without one still fails rather than being named after the marker alone.
+pub fn with_title_affixes(title: String, options: &ConversionOptions) -> String {
+    match (&options.title_prefix, &options.title_suffix) {
+        (None, None) => title,
+        _ if title.is_empty() => title,
+        (prefix, suffix) => format!(
+            "{}{}{}",
+            prefix.as_deref().unwrap_or(""),
+            title,
+            suffix.as_deref().unwrap_or("")
+        ),
+    }
+}
+
+/// `tags`, or `default_tag` alone for a note which has none.
+pub fn with_default_tag(
+    tags: Option<Vec<String>>,
+    options: &ConversionOptions,
+) -> Option<Vec<String>> {
+    match (tags, &options.default_tag) {
+        (Some(tags), _) if !tags.is_empty() => Some(tags),
+        (_, Some(tag)) => Some(vec![tag.clone()]),
+        (tags, None) => tags,
+    }
+}
+
+/// Recase each tag as `case` asks, then remove any duplicates that creates, keeping the
+/// first occurrence.
+pub fn normalize_tag_case(tags: Vec<String>, case: TagCase) -> Vec<String> {
+    let recase = |tag: &str| match case {
+        TagCase::Preserve => tag.to_string(),
+        TagCase::Lower => tag.to_lowercase(),
+        TagCase::Upper => tag.to_uppercase(),
+        TagCase::Title => {
+            let mut titled = String::with_capacity(tag.len());
+            let mut word_start = true;
+            for c in tag.chars() {
+                if word_start {
+                    titled.extend(c.to_uppercase());
+                } else {
+                    titled.extend(c.to_lowercase());
+                }
+                word_start = c.is_whitespace();
+            }
+            titled
+        }
+    };
+
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = recase(&tag);
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Pipe `content` through the shell command `cmd`, returning what it writes to stdout.
+/// A command which can't be run or exits unsuccessfully is an error.
+pub fn run_transform_cmd(content: &str, cmd: &str) -> Result<String, std::io::Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // write from another thread so a command which streams its output can't deadlock on a full pipe
+    let mut stdin = child.stdin.take().expect("child stdin is piped");
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // a command which exits without reading all its input is judged by its exit status alone
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(std::io::Error::new(
             ErrorKind::InvalidData,
-            format!("title: '{}' is not valid for a filename", title),
+            format!("transform-cmd: '{}' failed, {}", cmd, output.status),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|_| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("transform-cmd: '{}' output is not valid UTF8", cmd),
+        )
+    })
+}
+
+/// Path segment used in place of one which can't be worked out for a note, like a template
+/// placeholder the note has no value for.
+const MISSING_SEGMENT_DEFAULT: &str = "unknown";
+
+/// Check a `missing_segment` is a single directory name, or empty to leave the segment out, so
+/// it can't lead a note out of dest_dir.
+pub fn validate_missing_segment(segment: &str) -> Result<(), std::io::Error> {
+    let mut components = Path::new(segment).components();
+    match (components.next(), components.next()) {
+        (None, _) | (Some(Component::Normal(_)), None) if !segment.contains(['/', '\\']) => Ok(()),
+        _ => Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "missing_segment: '{}' must be a single directory name, without separators, '.' or '..'",
+                segment
+            ),
+        )),
+    }
+}
+
+/// Directory `group_by_tag` puts notes without tags in.
+const UNTAGGED_DIR: &str = "untagged";
+
+/// The directory, relative to dest_dir, a note is written in: as `path_template` gives, or
+/// named for its first tag with `group_by_tag`. It is empty when neither is set.
+fn note_dir(meta: &MarkdownMeta, options: &ConversionOptions) -> PathBuf {
+    match (&options.path_template, options.group_by_tag) {
+        (Some(template), _) => render_path_template(
+            meta,
+            template,
+            options.missing_segment.as_deref(),
+            options.date_format.as_deref(),
+        ),
+        (None, true) => render_path_template(meta, "{first_tag}", Some(UNTAGGED_DIR), None),
+        (None, false) => PathBuf::new(),
+    }
+}
+
+/// The directory, relative to dest_dir, which `template` gives for a note. Placeholders like
+/// `{created_year}` or `{first_tag}` are replaced from `meta`, each becoming a single path
+/// segment. Placeholders without a value for the note, or unrecognised ones, become `missing`,
+/// or are left out when it is empty. Dates are read in the `date_format` pattern they were
+/// written in, if any. Notes are always named for their title, so a final `{title}` segment is
+/// ignored.
+pub fn render_path_template(
+    meta: &MarkdownMeta,
+    template: &str,
+    missing: Option<&str>,
+    date_format: Option<&str>,
+) -> PathBuf {
+    lazy_static! {
+        static ref RE_PLACEHOLDER: Regex = Regex::new(r"\{([a-z_]+)\}").unwrap();
+        static ref RE_BOGUS_SEGMENT_CHARS: Regex = Regex::new(r#"[/\\:?]"#).unwrap();
+    }
+
+    let missing = missing.unwrap_or(MISSING_SEGMENT_DEFAULT);
+    let date_part = |date: &str, format: &str| {
+        parse_note_timestamp(date, date_format).map(|d| d.format(format).to_string())
+    };
+    let value = |name: &str| -> Option<String> {
+        match name {
+            "created_year" => date_part(&meta.created, "%Y"),
+            "created_month" => date_part(&meta.created, "%m"),
+            "created_day" => date_part(&meta.created, "%d"),
+            "modified_year" => date_part(&meta.modified, "%Y"),
+            "modified_month" => date_part(&meta.modified, "%m"),
+            "modified_day" => date_part(&meta.modified, "%d"),
+            "first_tag" => meta.tags.as_ref().and_then(|tags| tags.first().cloned()),
+            "type" => meta.type_.clone(),
+            _ => None,
+        }
+    };
+
+    let template = template.trim_matches('/');
+    let template = template.strip_suffix("{title}").unwrap_or(template);
+    let mut path = PathBuf::new();
+    for segment in template.split('/').filter(|s| !s.is_empty()) {
+        let rendered = RE_PLACEHOLDER.replace_all(segment, |caps: &regex::Captures| {
+            let value = value(&caps[1]).unwrap_or_default();
+            RE_BOGUS_SEGMENT_CHARS
+                .replace_all(value.trim(), "_")
+                .to_string()
+        });
+        match rendered.trim() {
+            "" | "." | ".." if missing.is_empty() => (),
+            "" | "." | ".." => path.push(missing),
+            s => path.push(s),
+        }
+    }
+    path
+}
+
+/// `file_path` with `prefix`, if any, put before its file name.
+fn prefix_file_name(file_path: PathBuf, prefix: Option<&str>) -> PathBuf {
+    match (prefix, file_path.file_name()) {
+        (Some(prefix), Some(name)) => {
+            let mut prefixed = OsString::from(prefix);
+            prefixed.push(name);
+            file_path.with_file_name(prefixed)
+        }
+        _ => file_path,
+    }
+}
+
+/// The path of a note's file in `dest_dir`, as `title_to_filepath` makes it from the title and
+/// suffix, or from their slug when `options.slug` is set.
+fn note_filepath(
+    dest_dir: &Path,
+    title: &str,
+    suffix: Option<&str>,
+    options: &ConversionOptions,
+) -> Result<PathBuf, std::io::Error> {
+    if !options.slug {
+        return title_to_filepath(dest_dir, title, suffix);
+    }
+    let slug = slugify(&format!("{}{}", title, suffix.unwrap_or("")));
+    if slug.is_empty() {
+        return Err(Notes2mdError::InvalidTitle(format!(
+            "title: '{}' has no letters or digits to make a file name slug from",
+            title
         ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    title_to_filepath(dest_dir, &slug, None)
+}
+
+/// `text` as a lowercase ASCII slug for static site generators: runs of whitespace and hyphens
+/// become one hyphen, other characters which aren't ASCII letters or digits are dropped, and
+/// there are no hyphens at the ends.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn title_to_filepath(
+    dest_dir: &Path,
+    title: &str,
+    suffix: Option<&str>,
+) -> Result<PathBuf, std::io::Error> {
+    lazy_static! {
+        // the characters Windows won't have in a file name, so exports can be copied there;
+        // backslashes are handled as separators below
+        static ref RE_BOGUS_FILENAME_CHARS: Regex =
+            Regex::new(r#"[:?<>"|*\x00-\x1f]"#).unwrap();
+        // names Windows keeps for devices, whatever their extension
+        static ref RE_RESERVED_NAME: Regex =
+            Regex::new(r"(?i)^(CON|PRN|AUX|NUL|COM[1-9]|LPT[1-9])(\.|$)").unwrap();
+    }
+
+    if "".eq(title) {
+        Err(
+            Notes2mdError::InvalidTitle(format!("title: '{}' is not valid for a filename", title))
+                .into_io(ErrorKind::InvalidData),
+        )
     } else {
-        let bogus_stripped = RE_BOGUS_FILENAME_CHARS.replace_all(&title, "_");
-        let leading_stripped = bogus_stripped.trim_start_matches([' ', '.']).trim();
-        let trailing_stripped = leading_stripped.trim_end_matches('/');
-        let title_part = match trailing_stripped.rsplit_once("/") {
+        let bogus_stripped = RE_BOGUS_FILENAME_CHARS.replace_all(title, "_");
+        let leading_stripped = bogus_stripped
+            .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+            .trim();
+        // backslashes are separators on Windows, so they're treated as slashes everywhere
+        let trailing_stripped = leading_stripped.trim_end_matches(['/', '\\']);
+        let title_part = match trailing_stripped.rsplit_once(['/', '\\']) {
             Some(s) => s.1.to_string(),
             None => trailing_stripped.to_string(),
         };
-        let trimmed_title = title_part.trim();
-        let mut file_path = dest_dir.clone();
-        file_path.push(trimmed_title);
-        file_path.set_extension("md");
-        Ok(file_path)
+        // the part after a slash may itself be only dots or spaces, which can't be a file name,
+        // and Windows drops dots and spaces from the end of one
+        let trimmed_title = title_part
+            .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+            .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+        if !is_plain_file_name(trimmed_title) {
+            return Err(Notes2mdError::InvalidTitle(format!(
+                "title: '{}' is not valid for a filename",
+                title
+            ))
+            .into_io(ErrorKind::InvalidData));
+        }
+        let trimmed_title = RE_RESERVED_NAME.replace(trimmed_title, "${1}_${2}");
+        // appended rather than set, so a title with a dot in it keeps what follows the dot
+        Ok(dest_dir.join(format!("{}{}.md", trimmed_title, suffix.unwrap_or(""))))
+    }
+}
+
+/// Whether `name` is a single ordinary path component, so pushing it onto a directory can only
+/// name an entry inside that directory. `.`, `..`, roots and prefixes are all rejected.
+fn is_plain_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+/// Paths this long fail on Windows unless given the verbatim `\\?\` prefix.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Convert an absolute Windows path which exceeds `WINDOWS_MAX_PATH` into verbatim form, which
+/// also requires backslash separators. Shorter or already verbatim paths are returned unchanged.
+fn windows_verbatim_path(path: &str) -> String {
+    if path.len() < WINDOWS_MAX_PATH || path.starts_with(r"\\?\") {
+        path.to_string()
+    } else {
+        let backslashed = path.replace('/', r"\");
+        match backslashed.strip_prefix(r"\\") {
+            Some(unc) => format!(r"\\?\UNC\{}", unc),
+            None => format!(r"\\?\{}", backslashed),
+        }
+    }
+}
+
+/// On Windows, make a long file path usable by switching it to verbatim form.
+fn long_filepath(file_path: PathBuf) -> PathBuf {
+    if cfg!(windows) {
+        let absolute = match std::env::current_dir() {
+            Ok(cwd) if file_path.is_relative() => cwd.join(&file_path),
+            _ => file_path,
+        };
+        PathBuf::from(windows_verbatim_path(&absolute.to_string_lossy()))
+    } else {
+        file_path
+    }
+}
+
+/// Suffix added to the name of a note whose file name is taken, unless another is given.
+const COLLISION_FORMAT_DEFAULT: &str = " ({n})";
+
+lazy_static! {
+    static ref RE_COLLISION_INDEX: Regex = Regex::new(r"\{n(?::0(\d+))?\}").unwrap();
+}
+
+/// Check a `collision_format` has an `{n}` or zero-padded `{n:0W}` placeholder and no path
+/// separators, so every index gives a different name in the same directory.
+pub fn validate_collision_format(format: &str) -> Result<(), std::io::Error> {
+    let problem = if !RE_COLLISION_INDEX.is_match(format) {
+        Some("it has no {n} placeholder")
+    } else if format.contains(['/', '\\']) {
+        Some("it contains a path separator")
+    } else {
+        None
+    };
+    match problem {
+        Some(problem) => Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("collision_format: '{}' is not valid, {}", format, problem),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The collision suffix for index `n`, with each placeholder replaced by the index.
+fn collision_suffix(format: &str, n: usize) -> String {
+    RE_COLLISION_INDEX
+        .replace_all(format, |caps: &regex::Captures| {
+            let width = caps.get(1).map_or(0, |w| w.as_str().parse().unwrap_or(0));
+            format!("{:0width$}", n, width = width)
+        })
+        .to_string()
+}
+
+/// The files written by a run so far. They stay taken for the rest of the run even with
+/// `overwrite`, which only replaces files left by an earlier run, so two notes of one run with
+/// the same name are both kept.
+#[derive(Debug, Default)]
+pub(crate) struct RunWrites {
+    written: HashSet<PathBuf>,
+    /// the files this run started and adds its later notes to, `--journal` day files and the
+    /// JSON array, by the name they were meant to have, which was taken if it differs
+    started: HashMap<PathBuf, PathBuf>,
+    /// files written under another name because the one for their note was taken
+    pub collisions: usize,
+}
+
+impl RunWrites {
+    fn record(&mut self, file_path: &Path, renamed: bool) {
+        self.written.insert(resolved_filepath(file_path));
+        if renamed {
+            self.collisions += 1;
+        }
+    }
+}
+
+/// `file_path`, or the first free name `increment_filepath` gives when it is taken. With
+/// `overwrite` a file from before the run doesn't count as taken, so it is replaced, but the
+/// files `run` has written and the names reserved for other files of the run still do.
+fn increment_filepath_if_exists(
+    file_path: &Path,
+    modified: Option<&str>,
+    options: &ConversionOptions,
+    run: &RunWrites,
+) -> PathBuf {
+    let reserved = reserved_filepaths(options);
+    increment_filepath(file_path, modified, options, |path| {
+        let resolved = resolved_filepath(path);
+        (path.exists() && (!options.overwrite || run.written.contains(&resolved)))
+            || reserved.contains(&resolved)
+    })
+}
+
+/// The files, other than notes, which a run writes or may write later, like the manifest which
+/// is only saved at the end. A note must not take one of their names, or one would clobber the
+/// other.
+fn reserved_filepaths(options: &ConversionOptions) -> Vec<PathBuf> {
+    [
+        &options.update_from_manifest,
+        &options.track_created,
+        &options.error_log,
+        &options.seen_file,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| resolved_filepath(path))
+    .collect()
+}
+
+/// `path` with its directory canonicalized, so two spellings of the same location compare
+/// equal even though the file itself may not exist yet.
+fn resolved_filepath(path: &Path) -> PathBuf {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match (fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
     }
 }
 
-fn increment_filepath_if_exists(file_path: &PathBuf) -> PathBuf {
-    let mut corrected_path = file_path.clone();
-    let mut i: usize = 0;
+/// `file_path`, or if `taken` says it is already used, the first free name given by adding a
+/// collision suffix to it. With `rename_collisions_by_date_suffix` the note's `modified` date is
+/// tried as the suffix first, and numbered suffixes follow it if that is taken too.
+fn increment_filepath(
+    file_path: &Path,
+    modified: Option<&str>,
+    options: &ConversionOptions,
+    taken: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    let file_path = match (options.rename_collisions_by_date_suffix, modified) {
+        (true, Some(modified)) if taken(file_path) => date_suffixed_filepath(file_path, modified),
+        _ => file_path.to_path_buf(),
+    };
+    let file_path = file_path.as_path();
+    let format = options
+        .collision_format
+        .as_deref()
+        .unwrap_or(COLLISION_FORMAT_DEFAULT);
+    let mut corrected_path = file_path.to_path_buf();
+    let mut i: usize = options.collision_start.unwrap_or(1);
     loop {
-        if corrected_path.exists() {
-            i = i + 1;
+        if taken(&corrected_path) {
             let file_part = match file_path.file_stem() {
                 Some(s) => s,
                 None => OsStr::new(""),
             };
-            corrected_path.set_file_name(format!("{} ({}).md", file_part.to_string_lossy(), i));
+            let extension = match file_path.extension() {
+                Some(e) => e,
+                None => OsStr::new("md"),
+            };
+            corrected_path.set_file_name(format!(
+                "{}{}.{}",
+                file_part.to_string_lossy(),
+                collision_suffix(format, i),
+                extension.to_string_lossy()
+            ));
+            i += 1;
         } else {
             break;
         }
@@ -89,48 +1012,706 @@ fn increment_filepath_if_exists(file_path: &PathBuf) -> PathBuf {
     corrected_path
 }
 
-pub fn write_markdown(markdown: Markdown, dest_dir: &PathBuf) -> Result<(), std::io::Error> {
-    let filepath = match title_to_filepath(dest_dir, &markdown.meta.title) {
-        Ok(initial) => Ok(increment_filepath_if_exists(&initial)),
-        Err(e) => Err(e),
+/// `file_path` with ` <modified>` added to its name, the date's `:`s and other characters which
+/// aren't safe in file names replaced with `-`.
+fn date_suffixed_filepath(file_path: &Path, modified: &str) -> PathBuf {
+    lazy_static! {
+        static ref RE_UNSAFE_DATE_CHARS: Regex = Regex::new(r#"[:/\\?*<>|"\s]"#).unwrap();
+    }
+
+    let stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    let date = RE_UNSAFE_DATE_CHARS.replace_all(modified.trim(), "-");
+    let name = match file_path.extension() {
+        Some(extension) => format!("{} {}.{}", stem, date, extension.to_string_lossy()),
+        None => format!("{} {}", stem, date),
     };
+    file_path.with_file_name(name)
+}
 
-    match filepath {
-        Ok(file_path) => match fs::File::create(file_path) {
-            Ok(mut f) => match serialize_markdown(&markdown) {
-                Err(e) => Err(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    format!("YAML ERROR: {}", e),
-                )),
-                Ok(text) => match f.write_all(text.as_bytes()) {
-                    Ok(_) => Ok(()),
-                    Err(e) => Err(e),
-                },
+/// The path relative to dest_dir and the text `markdown` would be written as, without touching
+/// the filesystem. Collisions are resolved against the paths in `taken`, to which the new path
+/// is added.
+pub fn render_markdown(
+    markdown: &Markdown,
+    taken: &mut HashSet<PathBuf>,
+    options: &ConversionOptions,
+) -> Result<(PathBuf, String), std::io::Error> {
+    let suffix = match markdown.meta.deleted {
+        Some(true) => options.trashed_suffix.as_deref(),
+        _ => None,
+    };
+    let dir = note_dir(&markdown.meta, options);
+    let initial = prefix_file_name(
+        note_filepath(&dir, &markdown.meta.title, suffix, options)?,
+        markdown.file_prefix.as_deref(),
+    );
+    let file_path = increment_filepath(&initial, Some(&markdown.meta.modified), options, |path| {
+        taken.contains(path)
+    });
+    let text = serialize_markdown(markdown, options)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("YAML ERROR: {}", e)))?;
+    taken.insert(file_path.clone());
+    Ok((file_path, text))
+}
+
+/// Write `markdown` into `dest_dir` as a run of its own, through the sink `options` ask for,
+/// returning the note's own file. Copies of it in tag directories aren't returned.
+pub fn write_markdown(
+    markdown: Markdown,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+) -> Result<PathBuf, std::io::Error> {
+    let file_paths = dest_sink(
+        dest_dir.to_path_buf(),
+        options,
+        &SystemFreeSpace,
+        &SystemClock,
+    )
+    .write(&markdown)
+    .map_err(std::io::Error::from)?;
+    file_paths
+        .into_iter()
+        .next()
+        .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))
+}
+
+/// Write a note into a directory of dest_dir named for each of its tags, returning the path of
+/// every copy in the order of its tags. With `link_tag_copies` the others are hard links to the
+/// first, unless the note is a textbundle, which as a directory can't be linked. A note without
+/// tags goes into the same 'untagged' directory as with `group_by_tag`.
+pub(crate) fn write_into_tag_dirs(
+    markdown: Markdown,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+    run: &mut RunWrites,
+    clock: &dyn Clock,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let tags = match &markdown.meta.tags {
+        Some(tags) if !tags.is_empty() => tags.clone(),
+        _ => vec![String::new()],
+    };
+    let mut file_paths: Vec<PathBuf> = Vec::new();
+    for tag in tags {
+        let meta = MarkdownMeta {
+            tags: Some(vec![tag]),
+            ..markdown.meta.clone()
+        };
+        let tag_dir = dest_dir.join(render_path_template(
+            &meta,
+            "{first_tag}",
+            Some(UNTAGGED_DIR),
+            None,
+        ));
+        let link = options.link_tag_copies && options.format == OutputFormat::Markdown;
+        let file_path = match file_paths.first() {
+            Some(original) if link => {
+                link_note(original, &tag_dir, dest_dir, &markdown, options, run)?
+            }
+            _ => write_note(markdown.clone(), dest_dir, &tag_dir, options, run, clock)?,
+        };
+        file_paths.push(file_path);
+    }
+    Ok(file_paths)
+}
+
+/// Hard link the note file `original` into `dir`, under the same name unless that is taken.
+fn link_note(
+    original: &Path,
+    dir: &Path,
+    root_dir: &Path,
+    markdown: &Markdown,
+    options: &ConversionOptions,
+    run: &mut RunWrites,
+) -> Result<PathBuf, std::io::Error> {
+    let initial = dir.join(original.file_name().unwrap_or_default());
+    ensure_within_dir(root_dir, &initial)?;
+    fs::create_dir_all(dir)?;
+    let file_path =
+        increment_filepath_if_exists(&initial, Some(&markdown.meta.modified), options, run);
+    if options.overwrite && file_path.is_file() {
+        fs::remove_file(&file_path)?;
+    }
+    fs::hard_link(original, &file_path)?;
+    run.record(&file_path, file_path != initial);
+    if let Some(track_file) = &options.track_created {
+        record_created(track_file, &file_path)?;
+    }
+    Ok(file_path)
+}
+
+/// Add a note to the day file for its creation date, `YYYY/MM/YYYY-MM-DD.md` under dest_dir,
+/// as a section headed with its creation time. The run's first note of a day creates the file,
+/// so the day's front matter comes from that note. Notes without a usable creation date are
+/// written as usual into the `missing_segment` directory, or dest_dir itself when it is empty.
+pub(crate) fn write_journal(
+    markdown: Markdown,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+    run: &mut RunWrites,
+    clock: &dyn Clock,
+) -> Result<PathBuf, std::io::Error> {
+    let created = match parse_note_timestamp(&markdown.meta.created, options.date_format.as_deref())
+    {
+        Some(created) => created,
+        None => {
+            let unknown_dir = dest_dir.join(
+                options
+                    .missing_segment
+                    .as_deref()
+                    .unwrap_or(MISSING_SEGMENT_DEFAULT),
+            );
+            return write_note(markdown, dest_dir, &unknown_dir, options, run, clock);
+        }
+    };
+
+    let day_dir = dest_dir.join(created.format("%Y/%m").to_string());
+    fs::create_dir_all(&day_dir)?;
+    let day_path = day_dir.join(created.format("%Y-%m-%d.md").to_string());
+    let section = format!(
+        "## {}\n\n{}",
+        created.format("%H:%M"),
+        markdown.content.trim_end()
+    );
+
+    // only a day file this run started is added to; one from before the run, which may have
+    // been edited by hand since, is a collision like any other file, or replaced with overwrite
+    if let Some(file_path) = run.started.get(&day_path) {
+        let mut day_file = fs::OpenOptions::new().append(true).open(file_path)?;
+        write!(day_file, "\n{}\n", section)?;
+        sync_if_asked(&day_file, options)?;
+        Ok(file_path.clone())
+    } else {
+        let file_path = increment_filepath_if_exists(&day_path, None, options, run);
+        let day = Markdown {
+            meta: MarkdownMeta {
+                title: created.format("%Y-%m-%d").to_string(),
+                ..markdown.meta
             },
-            Err(e) => Err(e),
-        },
-        Err(e) => {
-            eprintln!("ERROR processing Note:\n{}", markdown);
-            Err(e)
+            content: section,
+            provenance: markdown.provenance,
+            file_prefix: None,
+        };
+        write_serialized(&file_path, serialize_markdown(&day, options), options)?;
+        run.record(&file_path, file_path != day_path);
+        run.started.insert(day_path, file_path.clone());
+        if let Some(track_file) = &options.track_created {
+            record_created(track_file, &file_path)?;
         }
+        Ok(file_path)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Name of the file all notes are written to as a JSON array.
+const JSON_ARRAY_FILE: &str = "notes.json";
 
-    #[test]
-    fn serialize_meta_with_all_fields() {
-        // this just demonstrates how a fully populated MarkdownMeta will render
-        let source = MarkdownMeta {
-            title: String::from("A title"),
+/// Add `markdown` as an object to the JSON array in `notes.json` in dest_dir. The run's first
+/// note starts the file, which like any note is numbered when `notes.json` is taken from before
+/// the run, or replaces it with `overwrite`. Later notes are added by overwriting the array's
+/// closing bracket, so the file is never rewritten whole.
+pub(crate) fn write_json_array(
+    markdown: Markdown,
+    dest_dir: &Path,
+    options: &ConversionOptions,
+    run: &mut RunWrites,
+) -> Result<PathBuf, std::io::Error> {
+    const CLOSING: &str = "\n]\n";
+
+    let element = serde_json::to_string_pretty(&markdown)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("JSON ERROR: {}", e)))?
+        .replace('\n', "\n  ");
+    let array_path = dest_dir.join(JSON_ARRAY_FILE);
+    match run.started.get(&array_path) {
+        Some(file_path) => {
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(file_path)?;
+            let len = file.metadata()?.len();
+            let mut tail = [0; CLOSING.len()];
+            let closed = len >= tail.len() as u64
+                && file.seek(SeekFrom::End(-(tail.len() as i64))).is_ok()
+                && file.read_exact(&mut tail).is_ok()
+                && tail == CLOSING.as_bytes();
+            if !closed {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "file: '{}' doesn't end with a JSON array to add to",
+                        file_path.to_string_lossy()
+                    ),
+                ));
+            }
+            file.seek(SeekFrom::End(-(CLOSING.len() as i64)))?;
+            write!(file, ",\n  {}{}", element, CLOSING)?;
+            sync_if_asked(&file, options)?;
+            Ok(file_path.clone())
+        }
+        None => {
+            let file_path = increment_filepath_if_exists(&array_path, None, options, run);
+            write_serialized(
+                &file_path,
+                Ok(format!("[\n  {}{}", element, CLOSING)),
+                options,
+            )?;
+            run.record(&file_path, file_path != array_path);
+            run.started.insert(array_path, file_path.clone());
+            if let Some(track_file) = &options.track_created {
+                record_created(track_file, &file_path)?;
+            }
+            Ok(file_path)
+        }
+    }
+}
+
+/// Write a note into `dir`, or the directory `note_dir` gives for it under `dir`, which must be
+/// within `root_dir`, the dest_dir of the run.
+pub(crate) fn write_note(
+    markdown: Markdown,
+    root_dir: &Path,
+    dir: &Path,
+    options: &ConversionOptions,
+    run: &mut RunWrites,
+    clock: &dyn Clock,
+) -> Result<PathBuf, std::io::Error> {
+    let suffix = match markdown.meta.deleted {
+        Some(true) => options.trashed_suffix.as_deref(),
+        _ => None,
+    };
+    let dest_dir = match note_dir(&markdown.meta, options) {
+        sub_dir if sub_dir.as_os_str().is_empty() => dir.to_path_buf(),
+        sub_dir => dir.join(sub_dir),
+    };
+    let filepath = match note_filepath(&dest_dir, &markdown.meta.title, suffix, options) {
+        Ok(initial) => {
+            let initial = prefix_file_name(initial, markdown.file_prefix.as_deref());
+            let initial = match options.format {
+                // a JSON array only reaches here for --journal notes without a date
+                OutputFormat::Markdown | OutputFormat::JsonArray => initial,
+                OutputFormat::Textbundle => initial.with_extension("textbundle"),
+            };
+            // checked before the note's directory is made, so none is made outside root_dir
+            ensure_within_dir(root_dir, &initial)
+                .and_then(|_| fs::create_dir_all(&dest_dir))
+                .map(|_| {
+                    let initial = long_filepath(initial);
+                    let file_path = increment_filepath_if_exists(
+                        &initial,
+                        Some(&markdown.meta.modified),
+                        options,
+                        run,
+                    );
+                    let renamed = file_path != initial;
+                    (file_path, renamed)
+                })
+        }
+        Err(e) => Err(e),
+    };
+
+    match filepath {
+        Ok((file_path, renamed)) => {
+            let written = match options.format {
+                OutputFormat::Markdown | OutputFormat::JsonArray => {
+                    write_serialized(&file_path, serialize_markdown(&markdown, options), options)
+                }
+                OutputFormat::Textbundle => write_textbundle(&file_path, &markdown, options),
+            }
+            .and_then(|_| preserve_mtime(&file_path, &markdown, options, clock));
+            match written {
+                Ok(_) => {
+                    run.record(&file_path, renamed);
+                    match &options.track_created {
+                        Some(track_file) => {
+                            record_created(track_file, &file_path).map(|_| file_path)
+                        }
+                        None => Ok(file_path),
+                    }
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => {
+            eprintln!("ERROR processing Note:\n{}", markdown);
+            Err(e)
+        }
+    }
+}
+
+/// Check a note about to be written at `file_path` would land inside `dir`, so no title,
+/// template value or missing segment can place a note elsewhere. The nearest directory above
+/// the file which exists is canonicalized, and the ones below it which don't exist yet must be
+/// plain names, so this is checked before they are made.
+fn ensure_within_dir(dir: &Path, file_path: &Path) -> Result<(), std::io::Error> {
+    let dir = fs::canonicalize(dir)?;
+    let mut unmade = file_path;
+    let mut plain_names = true;
+    let existing = loop {
+        plain_names &= matches!(unmade.components().next_back(), Some(Component::Normal(_)));
+        match unmade.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => break Path::new("."),
+            Some(parent) if parent.exists() => break parent,
+            Some(parent) => unmade = parent,
+            None => {
+                plain_names = false;
+                break unmade;
+            }
+        }
+    };
+    let within = plain_names && fs::canonicalize(existing)?.starts_with(&dir);
+    match within {
+        true => Ok(()),
+        false => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "path: '{}' is outside of '{}'",
+                file_path.display(),
+                dir.display()
+            ),
+        )),
+    }
+}
+
+/// Replace the contents of an existing note file with `markdown`, keeping its name. Warnings are
+/// logged at the time of `clock`.
+pub fn overwrite_markdown(
+    markdown: Markdown,
+    file_path: &Path,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<PathBuf, std::io::Error> {
+    match serialize_markdown(&markdown, options) {
+        Ok(text) => write_file(file_path, text.as_bytes(), options)?,
+        Err(e) => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("YAML ERROR: {}", e),
+            ))
+        }
+    }
+    preserve_mtime(file_path, &markdown, options, clock)?;
+    Ok(file_path.to_path_buf())
+}
+
+/// With `preserve_mtime`, give the note written at `file_path`, or a textbundle's markdown file,
+/// the note's modified date as its modification time. A date which can't be read is warned
+/// about, and the file keeps the time it was written.
+fn preserve_mtime(
+    file_path: &Path,
+    markdown: &Markdown,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<(), std::io::Error> {
+    if !options.preserve_mtime {
+        return Ok(());
+    }
+    let modified = match parse_note_timestamp(
+        &markdown.meta.modified,
+        options.date_format.as_deref(),
+    ) {
+        Some(modified) => modified,
+        None => {
+            diagnostics::warning(
+                options, clock,
+                None,
+                &format!(
+                    "note: '{}' has a modified date '{}' which can't be read, its file keeps the time it was written",
+                    markdown.meta.title, markdown.meta.modified
+                ),
+            );
+            return Ok(());
+        }
+    };
+    let file_path = match file_path.is_dir() {
+        true => file_path.join(TEXTBUNDLE_TEXT),
+        false => file_path.to_path_buf(),
+    };
+    fs::OpenOptions::new()
+        .write(true)
+        .open(file_path)?
+        .set_modified(modified.into())
+}
+
+/// Write a serialized note to `file_path`. Nothing is created when serialization failed, so a
+/// note file never holds an error message instead of a note.
+fn write_serialized(
+    file_path: &Path,
+    text: Result<String, serde_yaml::Error>,
+    options: &ConversionOptions,
+) -> Result<(), std::io::Error> {
+    match text {
+        // unless asked to overwrite, an existing file is never replaced, even one created since
+        // the collision check
+        Ok(text) => write_atomically(file_path, options.overwrite, options, |file| {
+            file.write_all(text.as_bytes())
+        }),
+        Err(e) => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("YAML ERROR: {}", e),
+        )),
+    }
+}
+
+/// Write `contents` to `file_path`, replacing any file already there, as `sync_if_asked` says.
+pub(crate) fn write_file(
+    file_path: &Path,
+    contents: &[u8],
+    options: &ConversionOptions,
+) -> Result<(), std::io::Error> {
+    write_atomically(file_path, true, options, |file| file.write_all(contents))
+}
+
+/// Write `file_path` by calling `write` on a temporary file beside it, which is renamed to
+/// `file_path` once written, so a run which is killed part way never leaves a partly written
+/// note; nothing is left when `write` fails. A file already at `file_path` is only replaced
+/// when `replace` is set, otherwise it is an `AlreadyExists` error.
+fn write_atomically(
+    file_path: &Path,
+    replace: bool,
+    options: &ConversionOptions,
+    write: impl FnOnce(&mut fs::File) -> Result<(), std::io::Error>,
+) -> Result<(), std::io::Error> {
+    let dir = match file_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    // hidden, and not named .md, so nothing looking for notes picks it up
+    builder.prefix(".notes2md-").suffix(".tmp");
+    #[cfg(unix)]
+    {
+        // the permissions a newly created file gets, rather than a temporary file's owner-only
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o666));
+    }
+    let mut temp_file = builder.tempfile_in(dir)?;
+    write(temp_file.as_file_mut())?;
+    sync_if_asked(temp_file.as_file(), options)?;
+    match replace {
+        true => temp_file.persist(file_path).map(drop),
+        false => temp_file.persist_noclobber(file_path).map(drop),
+    }
+    .map_err(|e| e.error)
+}
+
+/// With `options.fsync`, wait until what was written to `file` is on disk, so a note the run
+/// reported as written survives a crash or power loss.
+fn sync_if_asked(file: &fs::File, options: &ConversionOptions) -> Result<(), std::io::Error> {
+    match options.fsync {
+        true => file.sync_all(),
+        false => Ok(()),
+    }
+}
+
+/// Name of the markdown file inside a textbundle.
+const TEXTBUNDLE_TEXT: &str = "text.md";
+
+/// Write `markdown` as a textbundle directory at `bundle_path`, holding the note as `text.md`
+/// and an `info.json` which also carries the front matter fields under a `notes2md` key.
+fn write_textbundle(
+    bundle_path: &Path,
+    markdown: &Markdown,
+    options: &ConversionOptions,
+) -> Result<(), std::io::Error> {
+    let text = serialize_markdown(markdown, options);
+    match text.is_ok() {
+        // the files of the bundle are replaced, and anything else in it is left alone
+        true if options.overwrite => fs::create_dir_all(bundle_path)?,
+        true => fs::create_dir(bundle_path)?,
+        false => (),
+    }
+    write_serialized(&bundle_path.join(TEXTBUNDLE_TEXT), text, options)?;
+
+    let info = serde_json::json!({
+        "version": 2,
+        "type": "net.daringfireball.markdown",
+        "transient": false,
+        "creatorIdentifier": "notes2md",
+        "notes2md": markdown.meta,
+    });
+    let info = serde_json::to_string_pretty(&info)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("JSON ERROR: {}", e)))?;
+    write_file(&bundle_path.join("info.json"), info.as_bytes(), options)
+}
+
+/// Append the absolute form of `file_path` to `track_file`, the list of files created by
+/// this and earlier runs.
+fn record_created(track_file: &Path, file_path: &Path) -> Result<(), std::io::Error> {
+    let created = fs::canonicalize(file_path)?;
+    let mut list = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(track_file)?;
+    writeln!(list, "{}", created.to_string_lossy())
+}
+
+/// Read back a written note and ensure its front matter parses into a `MarkdownMeta`. For a
+/// textbundle, its `text.md` is checked.
+pub fn verify_markdown_file(
+    file_path: &Path,
+    options: &ConversionOptions,
+) -> Result<(), std::io::Error> {
+    let invalid = |reason: String| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "file: '{}' failed verification: {}",
+                file_path.to_string_lossy(),
+                reason
+            ),
+        )
+    };
+
+    if file_path.extension().is_some_and(|e| e == "json") {
+        return match serde_json::from_str::<Vec<serde_json::Value>>(&fs::read_to_string(file_path)?)
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(invalid(format!("JSON ERROR: {}", e))),
+        };
+    }
+    let text = match file_path.is_dir() {
+        true => fs::read_to_string(file_path.join(TEXTBUNDLE_TEXT))?,
+        false => fs::read_to_string(file_path)?,
+    };
+    match parse_markdown(&text, options) {
+        Ok(_) => Ok(()),
+        Err(reason) => Err(invalid(reason)),
+    }
+}
+
+/// Read the text of a written note back into a note, with its dates read under the names
+/// `options` writes them with. A provenance comment opening the front matter is kept, and the
+/// extra `---` lines some early versions opened front matter with are skipped. The error is a
+/// description of what is wrong with the text.
+pub fn parse_markdown(text: &str, options: &ConversionOptions) -> Result<Markdown, String> {
+    let (front_matter, content) = match text.strip_prefix("---\n") {
+        Some(rest) => match rest.trim_start_matches("---\n").split_once("\n---\n") {
+            Some(parts) => parts,
+            None => return Err(String::from("front matter is not terminated")),
+        },
+        None => return Err(String::from("front matter is missing")),
+    };
+
+    // dates written under other names are read back under their usual ones
+    let renames: Vec<(&str, &str)> = date_key_renames(options)
+        .into_iter()
+        .map(|(from, to)| (to, from))
+        .collect();
+    let meta =
+        serde_yaml::from_str::<serde_yaml::Value>(front_matter).and_then(|fields| match fields {
+            serde_yaml::Value::Mapping(fields) => {
+                let fields = rename_keys(fields, &renames).map_err(de::Error::custom)?;
+                serde_yaml::from_value::<MarkdownMeta>(serde_yaml::Value::Mapping(
+                    scalars_as_strings(fields),
+                ))
+            }
+            fields => serde_yaml::from_value::<MarkdownMeta>(fields),
+        });
+    match meta {
+        Ok(meta) => Ok(Markdown {
+            meta,
+            // the newline after the content is added when serializing, unless it is raw
+            content: match options.raw_content {
+                true => content.to_string(),
+                false => content.strip_suffix('\n').unwrap_or(content).to_string(),
+            },
+            provenance: front_matter
+                .lines()
+                .next()
+                .and_then(|line| line.strip_prefix("# "))
+                .map(String::from),
+            file_prefix: None,
+        }),
+        Err(e) => Err(format!("YAML ERROR: {}", e)),
+    }
+}
+
+/// Early versions wrote titles and tags like `2021` or `yes` unquoted, which YAML reads as
+/// numbers and booleans; they are read back as text.
+fn scalars_as_strings(mut fields: serde_yaml::Mapping) -> serde_yaml::Mapping {
+    let as_string = |value: &mut serde_yaml::Value| {
+        let text = match value {
+            serde_yaml::Value::Number(n) => n.to_string(),
+            serde_yaml::Value::Bool(b) => b.to_string(),
+            _ => return,
+        };
+        *value = serde_yaml::Value::String(text);
+    };
+    if let Some(title) = fields.get_mut(&serde_yaml::Value::from("title")) {
+        as_string(title);
+    }
+    if let Some(serde_yaml::Value::Sequence(tags)) =
+        fields.get_mut(&serde_yaml::Value::from("tags"))
+    {
+        tags.iter_mut().for_each(as_string);
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Strings likely to trip up title and path handling: anything at all, runs of path
+    /// punctuation, control characters, and long multibyte text.
+    fn awkward_text() -> impl Strategy<Value = String> {
+        prop_oneof![
+            any::<String>(),
+            "[/. :?\\\\]{0,12}",
+            "(\\PC|[\\x00-\\x1f]){0,400}",
+            "[a-zé /.]{150,300}",
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn title_to_filepath_stays_in_dest_dir(title in awkward_text(), trashed in any::<bool>()) {
+            let dest_dir = Path::new("/tmp/notes");
+            let suffix = if trashed { Some(" (trashed)") } else { None };
+            match title_to_filepath(dest_dir, &title, suffix) {
+                Ok(path) => {
+                    prop_assert_eq!(Some(dest_dir), path.parent());
+                    prop_assert_eq!(Some(OsStr::new("md")), path.extension());
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    prop_assert!(!stem.is_empty());
+                    prop_assert!(!stem.starts_with('.'));
+                }
+                Err(e) => prop_assert_eq!(ErrorKind::InvalidData, e.kind()),
+            }
+        }
+
+        #[test]
+        fn increment_filepath_finds_a_free_name(title in awkward_text()) {
+            let dest_dir = tempfile::tempdir().unwrap();
+            if let Ok(path) = title_to_filepath(dest_dir.path(), &title, None) {
+                // names the filesystem rejects, like overlong ones, can't collide
+                if fs::write(&path, "").is_ok() {
+                    let next = increment_filepath_if_exists(&path, None, &ConversionOptions::default(), &RunWrites::default());
+                    prop_assert_eq!(path.parent(), next.parent());
+                    prop_assert!(!next.exists());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_meta_with_all_fields() {
+        // this just demonstrates how a fully populated MarkdownMeta will render
+        let source = MarkdownMeta {
+            title: String::from("A title"),
             created: String::from("2022-01-13T22:36:18.906Z"),
             modified: String::from("2022-01-14T07:36:50.656Z"),
             deleted: Some(true),
             favorited: Some(true),
             pinned: Some(true),
             tags: Some(vec![String::from("Personal"), String::from("Business")]),
+            type_: Some(String::from("note")),
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: Some(String::from("acf1756c")),
+            extra: serde_yaml::Mapping::new(),
         };
         let expected = r#"---
 title: A title
@@ -142,6 +1723,39 @@ pinned: true
 tags:
   - Personal
   - Business
+type: note
+id: acf1756c
+"#;
+        let actual = serde_yaml::to_string(&source).unwrap();
+        println!("{}", expected);
+        println!("{}", actual);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn serialize_meta_with_minimal_fields() {
+        // this just demonstrates how a minimally populated MarkdownMeta will render
+        let source = MarkdownMeta {
+            title: String::from("A title"),
+            created: String::from("2022-01-13T22:36:18.906Z"),
+            modified: String::from("2022-01-14T07:36:50.656Z"),
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: None,
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        };
+        let expected = r#"---
+title: A title
+created: "2022-01-13T22:36:18.906Z"
+modified: "2022-01-14T07:36:50.656Z"
 "#;
         let actual = serde_yaml::to_string(&source).unwrap();
         println!("{}", expected);
@@ -150,189 +1764,1257 @@ tags:
     }
 
     #[test]
-    fn serialize_meta_with_minimal_fields() {
-        // this just demonstrates how a minimally populated MarkdownMeta will render
-        let source = MarkdownMeta {
+    fn serialize_markdown_with_all_meta_fields() {
+        // this just demonstrates how a fully populated MarkdownMeta will render
+        let meta = MarkdownMeta {
+            title: String::from("A title"),
+            created: String::from("2022-01-13T22:36:18.906Z"),
+            modified: String::from("2022-01-14T07:36:50.656Z"),
+            deleted: Some(true),
+            favorited: Some(true),
+            pinned: Some(true),
+            tags: Some(vec![String::from("Personal"), String::from("Business")]),
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: Some(String::from("acf1756c")),
+            extra: serde_yaml::Mapping::new(),
+        };
+        let source = Markdown {
+            meta,
+            content: String::from("This is a\ngreat piece of\nsample content!"),
+            provenance: None,
+            file_prefix: None,
+        };
+        let expected = r#"---
+title: A title
+created: "2022-01-13T22:36:18.906Z"
+modified: "2022-01-14T07:36:50.656Z"
+deleted: true
+favorited: true
+pinned: true
+tags:
+  - Personal
+  - Business
+id: acf1756c
+---
+This is a
+great piece of
+sample content!
+"#;
+        let actual = serialize_markdown(&source, &ConversionOptions::default()).unwrap();
+        println!("{}", expected);
+        println!("{}", actual);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn serialize_markdown_with_minimal_meta_fields() {
+        // this just demonstrates how a fully populated MarkdownMeta will render
+        let meta = MarkdownMeta {
+            title: String::from("A title"),
+            created: String::from("2022-01-13T22:36:18.906Z"),
+            modified: String::from("2022-01-14T07:36:50.656Z"),
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: None,
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        };
+        let source = Markdown {
+            meta,
+            content: String::from("This is a\ngreat piece of\nsample content!"),
+            provenance: None,
+            file_prefix: None,
+        };
+        let expected = r#"---
+title: A title
+created: "2022-01-13T22:36:18.906Z"
+modified: "2022-01-14T07:36:50.656Z"
+---
+This is a
+great piece of
+sample content!
+"#;
+        let actual = serialize_markdown(&source, &ConversionOptions::default()).unwrap();
+        println!("{}", expected);
+        println!("{}", actual);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn serialize_markdown_writes_repeated_values_inline() {
+        // serde_yaml writes every value out in full, so parsers which don't follow YAML
+        // anchors and aliases read the same front matter as those which do
+        let mut source = Markdown::new("Repeated", "body");
+        let tags = vec![String::from("work"), String::from("work")];
+        source.meta.created = String::from("2022-01-14T07:36:50.656Z");
+        source.meta.modified = source.meta.created.clone();
+        source.meta.tags = Some(tags.clone());
+        source.meta.aliases = Some(tags);
+        let expected = r#"---
+title: Repeated
+created: "2022-01-14T07:36:50.656Z"
+modified: "2022-01-14T07:36:50.656Z"
+tags:
+  - work
+  - work
+aliases:
+  - work
+  - work
+---
+body
+"#;
+        let actual = serialize_markdown(&source, &ConversionOptions::default()).unwrap();
+        assert_eq!(expected, actual);
+        assert!(!actual.contains('&') && !actual.contains('*'));
+    }
+
+    #[test]
+    fn escape_markdown_escapes_significant_chars() {
+        let source = "# not a heading\n*not* _emphasis_ [or] <html> `code`";
+        let expected = "\\# not a heading\n\\*not\\* \\_emphasis\\_ \\[or\\] \\<html\\> \\`code\\`";
+        assert_eq!(expected, escape_markdown(source));
+    }
+
+    #[test]
+    fn escape_markdown_escapes_list_markers_only_at_line_start() {
+        let source = "- item\n  + nested\nwell-known 1+1";
+        let expected = "\\- item\n  \\+ nested\nwell-known 1+1";
+        assert_eq!(expected, escape_markdown(source));
+    }
+
+    #[test]
+    fn inline_reference_links_rewrites_and_removes_definitions() {
+        let source = "See [the docs][docs] and [Rust][] or ![a logo][Logo].\n\n[docs]: https://doc.rust-lang.org\n[rust]: https://www.rust-lang.org \"Rust\"\n[logo]: https://example.com/logo.png\n";
+        let expected = "See [the docs](https://doc.rust-lang.org) and [Rust](https://www.rust-lang.org \"Rust\") or ![a logo](https://example.com/logo.png).\n";
+        assert_eq!(expected, inline_reference_links(source));
+    }
+
+    #[test]
+    fn inline_reference_links_keeps_unmatched_links_and_definitions() {
+        let source = "A [missing][nope] link and [a used][one] one.\n\n[one]: http://one.example\n[two]: http://two.example\n";
+        let expected = "A [missing][nope] link and [a used](http://one.example) one.\n\n[two]: http://two.example\n";
+        assert_eq!(expected, inline_reference_links(source));
+
+        let untouched = "No links here, just [brackets].\n";
+        assert_eq!(untouched, inline_reference_links(untouched));
+    }
+
+    #[test]
+    fn inline_reference_links_handles_shortcut_and_collapsed_references() {
+        let source = "Read [Docs], [the book][] and [inline](http://x.example), not [other].\n\n[docs]: http://docs.example\n[The Book]: http://book.example\n";
+        let expected = "Read [Docs](http://docs.example), [the book](http://book.example) and [inline](http://x.example), not [other].\n";
+        assert_eq!(expected, inline_reference_links(source));
+    }
+
+    #[test]
+    fn inline_reference_links_keeps_trailing_whitespace() {
+        let source = "Last line  \n\n\n";
+        assert_eq!(source, inline_reference_links(source));
+
+        let source = "A [link].  \n\n[link]: http://link.example\n\n";
+        let expected = "A [link](http://link.example).  \n\n";
+        assert_eq!(expected, inline_reference_links(source));
+
+        let source = "A [link].\n\n[link]: http://link.example\n\nMore text\n";
+        let expected = "A [link](http://link.example).\n\n\nMore text\n";
+        assert_eq!(expected, inline_reference_links(source));
+    }
+
+    #[test]
+    fn truncate_chars_is_multibyte_safe() {
+        assert_eq!("héllo", truncate_chars("héllo wörld", 5));
+        assert_eq!("日本", truncate_chars("日本語", 2));
+        assert_eq!("short", truncate_chars("short", 10));
+        assert_eq!("", truncate_chars("anything", 0));
+    }
+
+    #[test]
+    fn truncate_content_under_and_over_limit() {
+        assert_eq!("under", truncate_content("under", 5));
+        assert_eq!("over…", truncate_content("over the limit", 4));
+        assert_eq!("日本…", truncate_content("日本語のノート", 2));
+    }
+
+    #[test]
+    fn age_between_years_months_and_days() {
+        let now = DateTime::parse_from_rfc3339("2024-04-20T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            Some(String::from("2y 3mo")),
+            age_between("2022-01-13T22:36:18.906Z", now)
+        );
+        assert_eq!(
+            Some(String::from("2y")),
+            age_between("2022-04-20T11:00:00Z", now)
+        );
+        // not quite a whole year yet
+        assert_eq!(
+            Some(String::from("11mo")),
+            age_between("2023-04-20T13:00:00Z", now)
+        );
+        assert_eq!(
+            Some(String::from("12d")),
+            age_between("2024-04-08T12:00:00Z", now)
+        );
+        assert_eq!(
+            Some(String::from("0d")),
+            age_between("2024-04-20T11:59:00Z", now)
+        );
+    }
+
+    #[test]
+    fn age_between_invalid_or_future_dates() {
+        let now = DateTime::parse_from_rfc3339("2024-04-20T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(None, age_between("last tuesday", now));
+        assert_eq!(
+            Some(String::from("0d")),
+            age_between("2025-01-01T00:00:00Z", now)
+        );
+    }
+
+    #[test]
+    fn split_tags_on_separator() {
+        let tags = vec![String::from("a,b,c")];
+        assert_eq!(vec!["a", "b", "c"], split_tags(tags, ','));
+    }
+
+    #[test]
+    fn split_tags_leaves_split_tags_alone_and_dedupes() {
+        let tags = vec![
+            String::from("a"),
+            String::from("b, a"),
+            String::from(" c ,"),
+        ];
+        assert_eq!(vec!["a", "b", "c"], split_tags(tags, ','));
+
+        let spaced = vec![String::from("work  home")];
+        assert_eq!(vec!["work", "home"], split_tags(spaced, ' '));
+    }
+
+    #[test]
+    fn split_on_headings_makes_a_note_per_section() {
+        let note = Markdown {
+            meta: MarkdownMeta {
+                title: String::from("Sections"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: Some(vec![String::from("work")]),
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from(
+                "# Sections\n\n## First\none\n### Detail\nmore\n\n## Second\n```\n## not a heading\n```\n## Third ##\nthree",
+            ),
+            provenance: None,
+            file_prefix: None,
+        };
+        let notes = split_on_headings(note.clone(), 2);
+        let summary: Vec<(&str, &str)> = notes
+            .iter()
+            .map(|n| (n.meta.title.as_str(), n.content.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Sections", "# Sections"),
+                ("First", "one\n### Detail\nmore"),
+                ("Second", "```\n## not a heading\n```"),
+                ("Third", "three"),
+            ],
+            summary
+        );
+        assert!(notes.iter().all(|n| n.meta.tags == note.meta.tags
+            && n.meta.created == note.meta.created
+            && n.meta.modified == note.meta.modified));
+
+        assert_eq!(vec![note.clone()], split_on_headings(note, 4));
+    }
+
+    #[test]
+    fn markdown_builder_matches_literal() {
+        let built = Markdown::new("A title", "content")
+            .with_created("2022-01-13T22:36:18.906Z")
+            .with_modified("2022-01-14T07:36:50.656Z")
+            .with_tags(vec![String::from("work")]);
+        let expected = Markdown {
+            meta: MarkdownMeta {
+                title: String::from("A title"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: Some(vec![String::from("work")]),
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("content"),
+            provenance: None,
+            file_prefix: None,
+        };
+        assert_eq!(expected, built);
+        assert_eq!(0, Markdown::new("Empty", " \n ").word_count());
+    }
+
+    #[test]
+    fn normalize_tag_case_in_each_mode() {
+        let tags = || {
+            vec![
+                String::from("Work"),
+                String::from("work"),
+                String::from("WORK"),
+                String::from("side PROJECT"),
+            ]
+        };
+        assert_eq!(
+            vec!["Work", "work", "WORK", "side PROJECT"],
+            normalize_tag_case(tags(), TagCase::Preserve)
+        );
+        assert_eq!(
+            vec!["work", "side project"],
+            normalize_tag_case(tags(), TagCase::Lower)
+        );
+        assert_eq!(
+            vec!["WORK", "SIDE PROJECT"],
+            normalize_tag_case(tags(), TagCase::Upper)
+        );
+        assert_eq!(
+            vec!["Work", "Side Project"],
+            normalize_tag_case(tags(), TagCase::Title)
+        );
+    }
+
+    #[test]
+    fn filepath_invalid_empty() {
+        let path = PathBuf::from("/tmp");
+        let title = "";
+        let error = title_to_filepath(&path, title, None).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            format!("title: '{}' is not valid for a filename", title),
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn filename_strips_leading_trailing_spaces() {
+        let path = PathBuf::from("/tmp");
+        let title = "  A Title With Spaces  ";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push(title.trim_start_matches(" ").trim_end_matches(" "));
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn filename_simple_success() {
+        let path = PathBuf::from("/tmp");
+        let title = "A Simple Filename";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push(title.trim_start_matches(" ").trim_end_matches(" "));
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn filename_uses_last_slash_part() {
+        let path = PathBuf::from("/tmp");
+        let title = "https://www.rust-lang.org/learn/get-started";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push("get-started");
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn filename_strips_question_and_colon() {
+        let path = PathBuf::from("/tmp");
+        let title = "A: Simple? Filename";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push("A_ Simple_ Filename");
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn filename_replaces_windows_reserved_characters() {
+        let path = PathBuf::from("/tmp");
+        let actual = title_to_filepath(&path, "a|b<c>d", None).unwrap();
+        assert_eq!(path.join("a_b_c_d.md"), actual);
+
+        let actual = title_to_filepath(&path, "\"Quoted\" *stars*", None).unwrap();
+        assert_eq!(path.join("_Quoted_ _stars_.md"), actual);
+
+        let actual = title_to_filepath(&path, "To be continued...", None).unwrap();
+        assert_eq!(path.join("To be continued.md"), actual);
+    }
+
+    #[test]
+    fn filename_avoids_windows_reserved_names() {
+        let path = PathBuf::from("/tmp");
+        assert_eq!(
+            path.join("CON_.md"),
+            title_to_filepath(&path, "CON", None).unwrap()
+        );
+        assert_eq!(
+            path.join("nul_.md"),
+            title_to_filepath(&path, "nul", None).unwrap()
+        );
+        assert_eq!(
+            path.join("LPT1_.md"),
+            title_to_filepath(&path, "LPT1", None).unwrap()
+        );
+        // only the whole name is reserved
+        assert_eq!(
+            path.join("Console.md"),
+            title_to_filepath(&path, "Console", None).unwrap()
+        );
+        // a numbered copy keeps the same name before its number
+        assert_eq!(
+            path.join("CON_ (1).md"),
+            title_to_filepath(&path, "CON", Some(" (1)")).unwrap()
+        );
+    }
+
+    #[test]
+    fn filename_url_part_with_trailing_slash() {
+        let path = PathBuf::from("/tmp");
+        let title = "http://example.com/name-with-trailing-slash/";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push("name-with-trailing-slash");
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn filename_invalid_when_only_dots_after_slash() {
+        let path = PathBuf::from("/tmp");
+        for title in ["...", "notes/..", "notes/ . "] {
+            let error = title_to_filepath(&path, title, None).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    fn filename_never_names_dest_dir_or_its_parent() {
+        let path = PathBuf::from("/tmp/notes");
+        for title in [".", "..", " .. ", "..\\..", "notes\\.."] {
+            let error = title_to_filepath(&path, title, None).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+        for title in ["../escape", "..\\escape", "a/../../escape"] {
+            let actual = title_to_filepath(&path, title, None).unwrap();
+            assert_eq!(path.join("escape.md"), actual);
+        }
+    }
+
+    #[test]
+    fn ensure_within_dir_rejects_escaping_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let dest_dir = root.path().join("notes");
+        fs::create_dir(&dest_dir).unwrap();
+        assert!(ensure_within_dir(&dest_dir, &dest_dir.join("a.md")).is_ok());
+        assert!(ensure_within_dir(&dest_dir, &dest_dir.join("new/dirs/a.md")).is_ok());
+        for path in [
+            dest_dir.join("../a.md"),
+            dest_dir.join("..").join("..").join("a.md"),
+            root.path().join("a.md"),
+            dest_dir.join("new/../../a.md"),
+            dest_dir.join("../../escaped/a.md"),
+        ] {
+            let error = ensure_within_dir(&dest_dir, &path).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    fn missing_segment_stays_in_dest_dir() {
+        assert!(validate_missing_segment("unknown").is_ok());
+        assert!(validate_missing_segment("").is_ok());
+        for segment in ["..", ".", "../../escaped", "a/b", "a\\b", "/tmp"] {
+            let error = validate_missing_segment(segment).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, error.kind(), "{}", segment);
+        }
+
+        // options which skipped validation still can't write or make directories outside
+        let root = tempfile::tempdir().unwrap();
+        let dest_dir = root.path().join("out");
+        fs::create_dir(&dest_dir).unwrap();
+        let options = ConversionOptions {
+            journal: true,
+            missing_segment: Some(String::from("../escaped")),
+            ..Default::default()
+        };
+        let note = Markdown::new("Escaper", "body").with_created("January");
+        let error = write_markdown(note, &dest_dir, &options).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(!root.path().join("escaped").exists());
+    }
+
+    #[test]
+    fn write_markdown_preserves_modified_time() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            preserve_mtime: true,
+            ..Default::default()
+        };
+        let note = Markdown::new("Old note", "body").with_modified("2021-02-15T17:05:25.325Z");
+        let path = write_markdown(note, dest_dir.path(), &options).unwrap();
+        let mtime: DateTime<Utc> = fs::metadata(&path).unwrap().modified().unwrap().into();
+        let expected: DateTime<Utc> = parse_note_timestamp("2021-02-15T17:05:25.325Z", None)
+            .unwrap()
+            .into();
+        assert!((mtime - expected).num_milliseconds().abs() < 1000);
+    }
+
+    #[test]
+    fn write_markdown_overwrite_replaces_existing_file() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let note = |content: &str| Markdown::new("Rerun", content);
+        let options = ConversionOptions::default();
+        write_markdown(note("first run"), dest_dir.path(), &options).unwrap();
+        let path = write_markdown(note("second run"), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("Rerun (1).md"), path);
+
+        let overwrite = ConversionOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        let path = write_markdown(note("third run"), dest_dir.path(), &overwrite).unwrap();
+        assert_eq!(dest_dir.path().join("Rerun.md"), path);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("---\nthird run\n"));
+        assert_eq!(2, fs::read_dir(dest_dir.path()).unwrap().count());
+
+        let textbundle = ConversionOptions {
+            format: OutputFormat::Textbundle,
+            ..overwrite
+        };
+        write_markdown(note("bundled"), dest_dir.path(), &textbundle).unwrap();
+        let path = write_markdown(note("bundled again"), dest_dir.path(), &textbundle).unwrap();
+        assert_eq!(dest_dir.path().join("Rerun.textbundle"), path);
+        assert!(fs::read_to_string(path.join(TEXTBUNDLE_TEXT))
+            .unwrap()
+            .ends_with("---\nbundled again\n"));
+    }
+
+    #[test]
+    fn write_markdown_overwrite_keeps_notes_of_the_same_run() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        let meeting = |content: &str| Markdown::new("Meeting", content);
+        write_markdown(meeting("earlier run"), dest_dir.path(), &options).unwrap();
+
+        for rerun in ["first", "second"] {
+            let mut sink = dest_sink(
+                dest_dir.path().to_path_buf(),
+                &options,
+                &SystemFreeSpace,
+                &SystemClock,
+            );
+            let paths: Vec<PathBuf> = ["monday", "tuesday", "wednesday"]
+                .into_iter()
+                .map(|day| {
+                    let note = meeting(&format!("{} {}", rerun, day));
+                    sink.write(&note).unwrap().remove(0)
+                })
+                .collect();
+            let expected: Vec<PathBuf> = ["Meeting.md", "Meeting (1).md", "Meeting (2).md"]
+                .into_iter()
+                .map(|name| dest_dir.path().join(name))
+                .collect();
+            assert_eq!(expected, paths);
+            assert_eq!(2, sink.collisions());
+            assert!(fs::read_to_string(&paths[0])
+                .unwrap()
+                .ends_with(&format!("---\n{} monday\n", rerun)));
+            assert_eq!(3, fs::read_dir(dest_dir.path()).unwrap().count());
+        }
+    }
+
+    #[test]
+    fn write_markdown_grouped_by_first_tag() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            group_by_tag: true,
+            ..Default::default()
+        };
+        let tagged = |title: &str, tags: &[&str]| {
+            let mut note = Markdown::new(title, "body");
+            note.meta.tags = Some(tags.iter().map(|t| t.to_string()).collect());
+            note
+        };
+
+        let path = write_markdown(
+            tagged("Plans", &["work", "ideas"]),
+            dest_dir.path(),
+            &options,
+        );
+        assert_eq!(dest_dir.path().join("work/Plans.md"), path.unwrap());
+        // collisions are numbered within the tag's directory
+        let path = write_markdown(tagged("Plans", &["work"]), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("work/Plans (1).md"), path.unwrap());
+        let path = write_markdown(tagged("Plans", &["ideas"]), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("ideas/Plans.md"), path.unwrap());
+
+        let path = write_markdown(Markdown::new("Loose", "body"), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("untagged/Loose.md"), path.unwrap());
+
+        // a slash in a tag doesn't make nested directories
+        let path = write_markdown(tagged("Chess", &["Fun/Games"]), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("Fun_Games/Chess.md"), path.unwrap());
+        let path = write_markdown(tagged("Up", &[".."]), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("untagged/Up.md"), path.unwrap());
+    }
+
+    #[test]
+    fn write_markdown_into_every_tag_dir() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            duplicate_into_tag_dirs: true,
+            ..Default::default()
+        };
+        let mut note = Markdown::new("Plans", "body");
+        note.meta.tags = Some(vec![String::from("work"), String::from("ideas")]);
+        let path = write_markdown(note.clone(), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("work/Plans.md"), path);
+        let copy = dest_dir.path().join("ideas/Plans.md");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            fs::read_to_string(&copy).unwrap()
+        );
+
+        let untagged = write_markdown(Markdown::new("Loose", "body"), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("untagged/Loose.md"), untagged.unwrap());
+
+        let linked = ConversionOptions {
+            link_tag_copies: true,
+            ..options
+        };
+        let path = write_markdown(note, dest_dir.path(), &linked).unwrap();
+        assert_eq!(dest_dir.path().join("work/Plans (1).md"), path);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let link = dest_dir.path().join("ideas/Plans (1).md");
+            assert_eq!(
+                fs::metadata(&path).unwrap().ino(),
+                fs::metadata(link).unwrap().ino()
+            );
+        }
+    }
+
+    #[test]
+    fn write_markdown_keeps_malicious_titles_in_dest_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let dest_dir = root.path().join("notes");
+        fs::create_dir(&dest_dir).unwrap();
+        let options = ConversionOptions {
+            path_template: Some(String::from("{first_tag}")),
+            ..Default::default()
+        };
+        let note = |title: &str| Markdown::new(title, "body").with_tags(vec![String::from("..")]);
+        // only the last part of a title names the file, and a tag of '..' is no directory
+        for (title, file) in [
+            ("../../etc/passwd", "passwd.md"),
+            ("foo/../../bar", "bar.md"),
+            ("..\\..\\baz", "baz.md"),
+            ("/abs/qux", "qux.md"),
+        ] {
+            let path = write_markdown(note(title), &dest_dir, &options).unwrap();
+            assert_eq!(dest_dir.join("unknown").join(file), path, "{}", title);
+        }
+        // with nothing left to name a file the note fails rather than being written
+        for title in ["..", "../..", "/", "foo/.."] {
+            let error = write_markdown(note(title), &dest_dir, &options).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+            assert_eq!(
+                format!("title: '{}' is not valid for a filename", title),
+                error.to_string()
+            );
+        }
+        assert_eq!(1, fs::read_dir(root.path()).unwrap().count());
+    }
+
+    #[test]
+    fn plain_file_names() {
+        assert!(is_plain_file_name("A Title"));
+        assert!(is_plain_file_name("a..b"));
+        for name in ["", ".", "..", "a/b", "/a"] {
+            assert!(!is_plain_file_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn filename_with_leading_dots() {
+        let path = PathBuf::from("/tmp");
+        let title = ". ..Some Title";
+        let actual = title_to_filepath(&path, title, None).unwrap();
+        let mut expected = PathBuf::from(path.to_str().unwrap());
+        expected.push("Some Title");
+        expected.set_extension("md");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn windows_verbatim_path_for_long_paths() {
+        let dir = "C:/Users/someone/".to_string() + &"deeply nested/".repeat(20);
+        let long = format!("{}A title.md", dir);
+        let expected = format!(r"\\?\{}", long.replace('/', r"\"));
+        assert_eq!(expected, windows_verbatim_path(&long));
+
+        let unc = format!(r"\\server\share\{}", "x".repeat(WINDOWS_MAX_PATH));
+        let expected = format!(r"\\?\UNC\server\share\{}", "x".repeat(WINDOWS_MAX_PATH));
+        assert_eq!(expected, windows_verbatim_path(&unc));
+
+        // already verbatim paths are left alone
+        assert_eq!(expected, windows_verbatim_path(&expected));
+    }
+
+    #[test]
+    fn windows_verbatim_path_leaves_short_paths() {
+        let short = r"C:\Users\someone\A title.md";
+        assert_eq!(short, windows_verbatim_path(short));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_filepath_is_verbatim_on_windows() {
+        let long = PathBuf::from(r"C:\").join("x".repeat(WINDOWS_MAX_PATH));
+        assert!(long_filepath(long)
+            .to_string_lossy()
+            .starts_with(r"\\?\C:\"));
+    }
+
+    #[test]
+    fn filename_with_suffix() {
+        let path = PathBuf::from("/tmp");
+        let actual = title_to_filepath(&path, " A Title ", Some(" (trashed)")).unwrap();
+        assert_eq!(PathBuf::from("/tmp/A Title (trashed).md"), actual);
+    }
+
+    #[test]
+    fn trashed_markdown_written_with_suffix() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            trashed_suffix: Some(String::from(" (trashed)")),
+            ..Default::default()
+        };
+        let note = |deleted| Markdown {
+            meta: MarkdownMeta {
+                title: String::from("A title"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted,
+                favorited: None,
+                pinned: None,
+                tags: None,
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("content"),
+            provenance: None,
+            file_prefix: None,
+        };
+
+        let trashed = write_markdown(note(Some(true)), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("A title (trashed).md"), trashed);
+        let active = write_markdown(note(None), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("A title.md"), active);
+    }
+
+    #[test]
+    fn path_template_with_date_and_tag() {
+        let meta = |tags: Option<Vec<String>>| MarkdownMeta {
             title: String::from("A title"),
             created: String::from("2022-01-13T22:36:18.906Z"),
             modified: String::from("2022-01-14T07:36:50.656Z"),
             deleted: None,
             favorited: None,
             pinned: None,
-            tags: None,
+            tags,
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
         };
-        let expected = r#"---
-title: A title
-created: "2022-01-13T22:36:18.906Z"
-modified: "2022-01-14T07:36:50.656Z"
-"#;
-        let actual = serde_yaml::to_string(&source).unwrap();
-        println!("{}", expected);
-        println!("{}", actual);
-        assert_eq!(expected, actual);
+        let tagged = meta(Some(vec![
+            String::from("work/projects"),
+            String::from("home"),
+        ]));
+        let template = "{created_year}-{created_month}/{first_tag}/{title}";
+
+        assert_eq!(
+            PathBuf::from("2022-01/work_projects"),
+            render_path_template(&tagged, template, None, None)
+        );
+        assert_eq!(
+            PathBuf::from("2022-01/unknown"),
+            render_path_template(&meta(None), template, None, None)
+        );
+        assert_eq!(
+            PathBuf::from("none/none"),
+            render_path_template(&meta(None), "{first_tag}/{bogus}", Some("none"), None)
+        );
+        assert_eq!(
+            PathBuf::from("2022-01"),
+            render_path_template(&meta(None), template, Some(""), None)
+        );
+        assert_eq!(
+            PathBuf::new(),
+            render_path_template(&meta(None), "{first_tag}/{bogus}", Some(""), None)
+        );
     }
 
     #[test]
-    fn serialize_markdown_with_all_meta_fields() {
-        // this just demonstrates how a fully populated MarkdownMeta will render
-        let meta = MarkdownMeta {
-            title: String::from("A title"),
-            created: String::from("2022-01-13T22:36:18.906Z"),
-            modified: String::from("2022-01-14T07:36:50.656Z"),
-            deleted: Some(true),
-            favorited: Some(true),
-            pinned: Some(true),
-            tags: Some(vec![String::from("Personal"), String::from("Business")]),
+    fn journal_and_path_template_read_dates_in_date_format() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let note = || Markdown::new("Standup", "Notes").with_created("14.01.2022 07:36");
+        let journal = ConversionOptions {
+            journal: true,
+            date_format: Some(String::from("%d.%m.%Y %H:%M")),
+            ..Default::default()
         };
-        let source = Markdown {
-            meta: meta,
-            content: String::from("This is a\ngreat piece of\nsample content!"),
+        let path = write_markdown(note(), dest_dir.path(), &journal).unwrap();
+        assert_eq!(dest_dir.path().join("2022/01/2022-01-14.md"), path);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("## 07:36\n\nNotes\n"));
+
+        let template = ConversionOptions {
+            path_template: Some(String::from("{created_year}/{created_month}")),
+            date_format: Some(String::from("%d.%m.%Y %H:%M")),
+            ..Default::default()
         };
-        let expected = r#"---
-title: A title
-created: "2022-01-13T22:36:18.906Z"
-modified: "2022-01-14T07:36:50.656Z"
-deleted: true
-favorited: true
-pinned: true
-tags:
-  - Personal
-  - Business
----
-This is a
-great piece of
-sample content!
-"#;
-        let actual = serialize_markdown(&source).unwrap();
-        println!("{}", expected);
-        println!("{}", actual);
-        assert_eq!(expected, actual);
+        let path = write_markdown(note(), dest_dir.path(), &template).unwrap();
+        assert_eq!(dest_dir.path().join("2022/01/Standup.md"), path);
     }
 
     #[test]
-    fn serialize_markdown_with_minimal_meta_fields() {
-        // this just demonstrates how a fully populated MarkdownMeta will render
-        let meta = MarkdownMeta {
-            title: String::from("A title"),
-            created: String::from("2022-01-13T22:36:18.906Z"),
-            modified: String::from("2022-01-14T07:36:50.656Z"),
-            deleted: None,
-            favorited: None,
-            pinned: None,
-            tags: None,
+    fn journal_appends_notes_from_the_same_day() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            journal: true,
+            ..Default::default()
         };
-        let source = Markdown {
-            meta: meta,
-            content: String::from("This is a\ngreat piece of\nsample content!"),
+        let note = |title: &str, created: &str, content: &str| Markdown {
+            meta: MarkdownMeta {
+                title: String::from(title),
+                created: String::from(created),
+                modified: String::from(created),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: None,
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from(content),
+            provenance: None,
+            file_prefix: None,
         };
+
+        let morning = note("Morning", "2022-01-14T07:36:50.656Z", "Coffee first\n");
+        let evening = note("Evening", "2022-01-14T21:05:00.000Z", "Early night");
+        let mut sink = dest_sink(
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemFreeSpace,
+            &SystemClock,
+        );
+        let first = sink.write(&morning).unwrap().remove(0);
+        let second = sink.write(&evening).unwrap().remove(0);
+        assert_eq!(dest_dir.path().join("2022/01/2022-01-14.md"), first);
+        assert_eq!(first, second);
+        assert_eq!(0, sink.collisions());
         let expected = r#"---
-title: A title
-created: "2022-01-13T22:36:18.906Z"
+title: 2022-01-14
+created: "2022-01-14T07:36:50.656Z"
 modified: "2022-01-14T07:36:50.656Z"
 ---
-This is a
-great piece of
-sample content!
+## 07:36
+
+Coffee first
+
+## 21:05
+
+Early night
 "#;
-        let actual = serialize_markdown(&source).unwrap();
-        println!("{}", expected);
-        println!("{}", actual);
-        assert_eq!(expected, actual);
+        assert_eq!(expected, fs::read_to_string(&first).unwrap());
+
+        // a later run leaves the day file alone, unless asked to overwrite it
+        let later = || note("Later", "2022-01-14T22:00:00.000Z", "Next run");
+        let mut sink = dest_sink(
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemFreeSpace,
+            &SystemClock,
+        );
+        let written = sink.write(&later()).unwrap().remove(0);
+        assert_eq!(dest_dir.path().join("2022/01/2022-01-14 (1).md"), written);
+        assert_eq!(1, sink.collisions());
+        assert_eq!(expected, fs::read_to_string(&first).unwrap());
+        let overwrite = ConversionOptions {
+            overwrite: true,
+            ..options.clone()
+        };
+        let written = write_markdown(later(), dest_dir.path(), &overwrite).unwrap();
+        assert_eq!(first, written);
+        let replaced = fs::read_to_string(&first).unwrap();
+        assert!(replaced.contains("## 22:00\n\nNext run"));
+        assert!(!replaced.contains("Coffee first"));
+
+        let undated = || note("Undated", "sometime", "No date");
+        let written = write_markdown(undated(), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("unknown/Undated.md"), written);
+
+        let missing = |segment: &str| ConversionOptions {
+            missing_segment: Some(String::from(segment)),
+            ..options.clone()
+        };
+        let written = write_markdown(undated(), dest_dir.path(), &missing("undated")).unwrap();
+        assert_eq!(dest_dir.path().join("undated/Undated.md"), written);
+        let written = write_markdown(undated(), dest_dir.path(), &missing("")).unwrap();
+        assert_eq!(dest_dir.path().join("Undated.md"), written);
     }
 
     #[test]
-    fn filepath_invalid_empty() {
-        let path = PathBuf::from("/tmp");
-        let title = "";
-        let error = title_to_filepath(&path, title).unwrap_err();
-        assert_eq!(ErrorKind::InvalidData, error.kind());
-        assert_eq!(
-            format!("title: '{}' is not valid for a filename", title),
-            format!("{}", error)
-        );
+    fn markdown_written_under_path_template() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            path_template: Some(String::from("{created_year}/{first_tag}")),
+            ..Default::default()
+        };
+        let note = Markdown {
+            meta: MarkdownMeta {
+                title: String::from("A title"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: Some(vec![String::from("work")]),
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("content"),
+            provenance: None,
+            file_prefix: None,
+        };
+
+        let written = write_markdown(note, dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("2022/work/A title.md"), written);
+        assert!(written.exists());
     }
 
     #[test]
-    fn filename_strips_leading_trailing_spaces() {
-        let path = PathBuf::from("/tmp");
-        let title = "  A Title With Spaces  ";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push(title.trim_start_matches(" ").trim_end_matches(" "));
-        expected.set_extension("md");
+    fn filepath_increments_if_already_exists_once() {
+        let path = PathBuf::from("test_data/dir_you_can_write/single-exists.md");
+        let expected = PathBuf::from("test_data/dir_you_can_write/single-exists (1).md");
+        let actual = increment_filepath_if_exists(
+            &path,
+            None,
+            &ConversionOptions::default(),
+            &RunWrites::default(),
+        );
         assert_eq!(actual, expected);
     }
 
     #[test]
-    fn filename_simple_success() {
-        let path = PathBuf::from("/tmp");
-        let title = "A Simple Filename";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push(title.trim_start_matches(" ").trim_end_matches(" "));
-        expected.set_extension("md");
+    fn filepath_increments_if_already_exists_n3() {
+        // `test_data/dir_you_can_write` already contains `sample-exists.md` plus 2 versions with numbers `(1)` and `(2)` so this should give us `(3)`
+        let path = PathBuf::from("test_data/dir_you_can_write/sample-exists.md");
+        let expected = PathBuf::from("test_data/dir_you_can_write/sample-exists (3).md");
+        let actual = increment_filepath_if_exists(
+            &path,
+            None,
+            &ConversionOptions::default(),
+            &RunWrites::default(),
+        );
         assert_eq!(actual, expected);
     }
 
     #[test]
-    fn filename_uses_last_slash_part() {
-        let path = PathBuf::from("/tmp");
-        let title = "https://www.rust-lang.org/learn/get-started";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push("get-started");
-        expected.set_extension("md");
-        assert_eq!(actual, expected);
+    fn filepath_increments_with_collision_format_and_start() {
+        let path = PathBuf::from("test_data/dir_you_can_write/single-exists.md");
+        let options = ConversionOptions {
+            collision_format: Some(String::from("-{n:03}")),
+            collision_start: Some(7),
+            ..Default::default()
+        };
+        let expected = PathBuf::from("test_data/dir_you_can_write/single-exists-007.md");
+        assert_eq!(
+            expected,
+            increment_filepath_if_exists(&path, None, &options, &RunWrites::default())
+        );
+
+        // the default format, counting from 0
+        let options = ConversionOptions {
+            collision_start: Some(0),
+            ..Default::default()
+        };
+        let path = PathBuf::from("test_data/dir_you_can_write/sample-exists.md");
+        let expected = PathBuf::from("test_data/dir_you_can_write/sample-exists (0).md");
+        assert_eq!(
+            expected,
+            increment_filepath_if_exists(&path, None, &options, &RunWrites::default())
+        );
     }
 
     #[test]
-    fn filename_strips_question_and_colon() {
-        let path = PathBuf::from("/tmp");
-        let title = "A: Simple? Filename";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push("A_ Simple_ Filename");
-        expected.set_extension("md");
-        assert_eq!(actual, expected);
+    fn render_markdown_resolves_collisions_in_memory() {
+        let mut taken = HashSet::new();
+        let options = ConversionOptions::default();
+        let note = Markdown::new("Same", "body");
+        let (first, text) = render_markdown(&note, &mut taken, &options).unwrap();
+        let (second, _) = render_markdown(&note, &mut taken, &options).unwrap();
+        assert_eq!(PathBuf::from("Same.md"), first);
+        assert_eq!(PathBuf::from("Same (1).md"), second);
+        assert!(text.ends_with("---\nbody\n"));
     }
 
     #[test]
-    fn filename_url_part_with_trailing_slash() {
-        let path = PathBuf::from("/tmp");
-        let title = "http://example.com/name-with-trailing-slash/";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push("name-with-trailing-slash");
-        expected.set_extension("md");
-        assert_eq!(actual, expected);
+    fn write_markdown_as_json_array() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            format: OutputFormat::JsonArray,
+            verify_output: true,
+            ..Default::default()
+        };
+        let mut trashed = Markdown::new("Trashed", "gone").with_tags(["a"]);
+        trashed.meta.deleted = Some(true);
+        let mut sink = dest_sink(
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemFreeSpace,
+            &SystemClock,
+        );
+        for note in [
+            Markdown::new("One", "first"),
+            Markdown::new("One", "second"),
+            trashed,
+        ] {
+            let path = sink.write(&note).unwrap().remove(0);
+            assert_eq!(dest_dir.path().join("notes.json"), path);
+            sink.verify(&path).unwrap();
+        }
+
+        let text = fs::read_to_string(dest_dir.path().join("notes.json")).unwrap();
+        let notes: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+        assert_eq!(3, notes.len());
+        assert_eq!("second", notes[1]["content"]);
+        assert_eq!(serde_json::Value::Null, notes[1]["deleted"]);
+        assert_eq!(true, notes[2]["deleted"]);
+        assert_eq!("a", notes[2]["tags"][0]);
+        assert!(text.starts_with("[\n  {\n    \"title\": \"One\",\n"));
+
+        // a later run starts its own array rather than adding to this one
+        let path = write_markdown(Markdown::new("Two", "third"), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("notes (1).json"), path.unwrap());
+        assert_eq!(
+            text,
+            fs::read_to_string(dest_dir.path().join("notes.json")).unwrap()
+        );
+        let overwrite = ConversionOptions {
+            overwrite: true,
+            ..options.clone()
+        };
+        let path = write_markdown(Markdown::new("Two", "third"), dest_dir.path(), &overwrite);
+        assert_eq!(dest_dir.path().join("notes.json"), path.unwrap());
+        let text = fs::read_to_string(dest_dir.path().join("notes.json")).unwrap();
+        let notes: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+        assert_eq!(1, notes.len());
+        assert_eq!("third", notes[0]["content"]);
     }
 
     #[test]
-    fn filename_with_leading_dots() {
-        let path = PathBuf::from("/tmp");
-        let title = ". ..Some Title";
-        let actual = title_to_filepath(&path, title).unwrap();
-        let mut expected = PathBuf::from(path.to_str().unwrap());
-        expected.push("Some Title");
-        expected.set_extension("md");
-        assert_eq!(actual, expected);
+    fn write_markdown_suffixes_collisions_with_modified_date() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            rename_collisions_by_date_suffix: true,
+            ..Default::default()
+        };
+        let note = |modified| Markdown::new("Same", "body").with_modified(modified);
+        let written: Vec<PathBuf> = [
+            "2022-01-14T07:36:50.656Z",
+            "2022-02-01T10:00:00.000Z",
+            "2022-02-01T10:00:00.000Z",
+        ]
+        .into_iter()
+        .map(|modified| write_markdown(note(modified), dest_dir.path(), &options).unwrap())
+        .collect();
+        assert_eq!(
+            vec![
+                dest_dir.path().join("Same.md"),
+                dest_dir.path().join("Same 2022-02-01T10-00-00.000Z.md"),
+                dest_dir.path().join("Same 2022-02-01T10-00-00.000Z (1).md"),
+            ],
+            written
+        );
     }
 
     #[test]
-    fn filepath_increments_if_already_exists_once() {
-        let path = PathBuf::from("test_data/dir_you_can_write/single-exists.md");
-        let expected = PathBuf::from("test_data/dir_you_can_write/single-exists (1).md");
-        let actual = increment_filepath_if_exists(&path);
-        assert_eq!(actual, expected);
+    fn slugify_titles() {
+        assert_eq!("hello-world", slugify("Hello, World!"));
+        assert_eq!("a-b-c", slugify("  a -- b\t\tc -"));
+        assert_eq!("caf-plans", slugify("Café plans"));
+        assert_eq!("", slugify("!!! ???"));
     }
 
     #[test]
-    fn filepath_increments_if_already_exists_n3() {
-        // `test_data/dir_you_can_write` already contains `sample-exists.md` plus 2 versions with numbers `(1)` and `(2)` so this should give us `(3)`
-        let path = PathBuf::from("test_data/dir_you_can_write/sample-exists.md");
-        let expected = PathBuf::from("test_data/dir_you_can_write/sample-exists (3).md");
-        let actual = increment_filepath_if_exists(&path);
-        assert_eq!(actual, expected);
+    fn write_markdown_with_slug_file_names() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            slug: true,
+            ..Default::default()
+        };
+        let path = write_markdown(
+            Markdown::new("Hello, World!", "body"),
+            dest_dir.path(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(dest_dir.path().join("hello-world.md"), path);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("title: \"Hello, World!\"\n"));
+
+        let error =
+            write_markdown(Markdown::new("!!!", "body"), dest_dir.path(), &options).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "title: '!!!' has no letters or digits to make a file name slug from",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn write_markdown_with_fsync() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            fsync: true,
+            ..Default::default()
+        };
+        let path =
+            write_markdown(Markdown::new("Synced", "body"), dest_dir.path(), &options).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().ends_with("---\nbody\n"));
+
+        // replacing a file syncs it too
+        let manifest = dest_dir.path().join("manifest.json");
+        write_file(&manifest, b"{}", &options).unwrap();
+        write_file(&manifest, b"[]", &options).unwrap();
+        assert_eq!("[]", fs::read_to_string(&manifest).unwrap());
+    }
+
+    #[test]
+    fn write_markdown_avoids_names_reserved_for_other_files() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            // not written until the end of the run
+            update_from_manifest: Some(dest_dir.path().join("index.md")),
+            track_created: Some(dest_dir.path().join(".").join("created.md")),
+            ..Default::default()
+        };
+        let index = write_markdown(Markdown::new("index", "a note"), dest_dir.path(), &options);
+        assert_eq!(dest_dir.path().join("index (1).md"), index.unwrap());
+        let created = write_markdown(
+            Markdown::new("created", "a note"),
+            dest_dir.path(),
+            &options,
+        );
+        assert_eq!(dest_dir.path().join("created (1).md"), created.unwrap());
+        assert!(!dest_dir.path().join("index.md").exists());
+    }
+
+    #[test]
+    fn wrap_content_reflows_paragraphs_and_lists() {
+        let content = "A long paragraph which goes on and on\nacross lines that are\nuneven.\n\n- a list item which is long enough to wrap\n- short\n\n## A heading which is longer than the width\n";
+        let expected = "A long paragraph which\ngoes on and on across\nlines that are uneven.\n\n- a list item which is\n  long enough to wrap\n- short\n\n## A heading which is longer than the width\n";
+        assert_eq!(expected, wrap_content(content, 22));
+        for line in wrap_content(content, 22)
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+        {
+            assert!(line.chars().count() <= 22, "{}", line);
+        }
+    }
+
+    #[test]
+    fn wrap_content_leaves_code_alone() {
+        let content = "```\nlet x = \"a code line which is much longer than the width\";\n\nmore code\n```\n    indented code which is also too long to fit\nhttps://example.com/a/url/longer/than/the/width\n";
+        assert_eq!(content, wrap_content(content, 20));
+    }
+
+    #[test]
+    fn collision_suffixes() {
+        assert_eq!(" (12)", collision_suffix(" ({n})", 12));
+        assert_eq!("-007", collision_suffix("-{n:03}", 7));
+        assert_eq!("-1234", collision_suffix("-{n:03}", 1234));
+        assert!(validate_collision_format("_{n:02}").is_ok());
+        for format in ["-copy", "/{n}", "{m}"] {
+            let error = validate_collision_format(format).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, error.kind());
+        }
     }
 
     #[test]
@@ -353,13 +3035,23 @@ sample content!
             favorited: Some(true),
             pinned: Some(true),
             tags: Some(vec![String::from("Personal"), String::from("Business")]),
+            type_: None,
+            age: None,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: None,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
         };
         let source = Markdown {
-            meta: meta,
+            meta,
             content: String::from("This is a\ngreat piece of\nsample content!"),
+            provenance: None,
+            file_prefix: None,
         };
         let path = PathBuf::from("test_data/out");
-        write_markdown(source, &path).unwrap();
+        write_markdown(source, &path, &ConversionOptions::default()).unwrap();
 
         let actual: String =
             String::from_utf8_lossy(&fs::read("test_data/out/A title.md").unwrap())
@@ -370,4 +3062,217 @@ sample content!
         println!("{}", actual);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn markdown_written_as_textbundle() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            format: OutputFormat::Textbundle,
+            ..Default::default()
+        };
+        let note = || Markdown {
+            meta: MarkdownMeta {
+                title: String::from("A title"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: None,
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("content"),
+            provenance: None,
+            file_prefix: None,
+        };
+
+        let bundle = write_markdown(note(), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("A title.textbundle"), bundle);
+        assert_eq!(
+            fs::read_to_string(bundle.join("text.md")).unwrap(),
+            serialize_markdown(&note(), &ConversionOptions::default()).unwrap()
+        );
+        let info: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(bundle.join("info.json")).unwrap()).unwrap();
+        assert_eq!(2, info["version"]);
+        assert_eq!("net.daringfireball.markdown", info["type"]);
+        assert_eq!("A title", info["notes2md"]["title"]);
+        verify_markdown_file(&bundle, &ConversionOptions::default()).unwrap();
+
+        let collided = write_markdown(note(), dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("A title (1).textbundle"), collided);
+    }
+
+    #[test]
+    fn serialization_error_writes_no_file() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let file_path = dest_dir.path().join("A title.md");
+        let yaml_error = serde_yaml::from_str::<MarkdownMeta>("- not\n- front matter").unwrap_err();
+
+        let error = write_serialized(&file_path, Err(yaml_error), &ConversionOptions::default())
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(format!("{}", error).starts_with("YAML ERROR: "));
+        assert!(!file_path.exists());
+    }
+
+    #[test]
+    fn failed_write_leaves_no_partial_file() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let file_path = dest_dir.path().join("A title.md");
+        let options = ConversionOptions::default();
+
+        let error = write_atomically(&file_path, false, &options, |file| {
+            file.write_all(
+                b"---
+title: A ti",
+            )?;
+            Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "YAML ERROR: cut short",
+            ))
+        })
+        .unwrap_err();
+        assert_eq!("YAML ERROR: cut short", error.to_string());
+        // neither the note nor the temporary file it was being written to is left behind
+        assert_eq!(0, fs::read_dir(dest_dir.path()).unwrap().count());
+
+        write_atomically(&file_path, false, &options, |file| file.write_all(b"first")).unwrap();
+        let error = write_atomically(&file_path, false, &options, |file| {
+            file.write_all(b"second")
+        })
+        .unwrap_err();
+        assert_eq!(ErrorKind::AlreadyExists, error.kind());
+        write_atomically(&file_path, true, &options, |file| file.write_all(b"third")).unwrap();
+        assert_eq!("third", fs::read_to_string(&file_path).unwrap());
+        assert_eq!(1, fs::read_dir(dest_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn written_markdown_passes_verification() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source = Markdown {
+            meta: MarkdownMeta {
+                title: String::from("Verify: me"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: Some(true),
+                tags: Some(vec![String::from("yes: no"), String::from("---")]),
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: None,
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("---\nnot front matter\n---\n"),
+            provenance: None,
+            file_prefix: None,
+        };
+        let file_path =
+            write_markdown(source, dest_dir.path(), &ConversionOptions::default()).unwrap();
+        assert_eq!(dest_dir.path().join("Verify_ me.md"), file_path);
+        verify_markdown_file(&file_path, &ConversionOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn serialize_markdown_with_renamed_date_keys() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            created_key: Some(String::from("date")),
+            modified_key: Some(String::from("updated")),
+            ..ConversionOptions::default()
+        };
+        let note = || Markdown::new("Dated", "body\n").with_created("2021-02-15T17:04:31.319Z");
+        let file_path = write_markdown(note(), dest_dir.path(), &options).unwrap();
+        let text = fs::read_to_string(&file_path).unwrap();
+        assert!(
+            text.contains("\ndate: \"2021-02-15T17:04:31.319Z\"\nupdated: "),
+            "{}",
+            text
+        );
+        assert!(!text.contains("created:") && !text.contains("modified:"));
+        verify_markdown_file(&file_path, &options).unwrap();
+        assert!(verify_markdown_file(&file_path, &ConversionOptions::default()).is_err());
+
+        let clashing = ConversionOptions {
+            modified_key: Some(String::from("title")),
+            ..ConversionOptions::default()
+        };
+        let error = serialize_markdown(&note(), &clashing).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("front matter key 'title' is used twice"));
+    }
+
+    #[test]
+    fn validate_date_keys_rejects_names_already_written() {
+        assert!(validate_date_keys(None, None).is_ok());
+        assert!(validate_date_keys(Some("date"), Some("updated")).is_ok());
+        assert!(validate_date_keys(Some("modified"), Some("created")).is_ok());
+
+        for (created_key, modified_key, message) in [
+            (
+                None,
+                Some("title"),
+                "modified_key: 'title' is not valid, another front matter field has that name",
+            ),
+            (
+                Some("type"),
+                None,
+                "created_key: 'type' is not valid, another front matter field has that name",
+            ),
+            (
+                Some("modified"),
+                None,
+                "modified_key: 'modified' is not valid, the creation date has that name",
+            ),
+            (
+                Some("date"),
+                Some("date"),
+                "modified_key: 'date' is not valid, the creation date has that name",
+            ),
+            (
+                Some(" "),
+                None,
+                "created_key: ' ' is not valid, it is empty",
+            ),
+        ] {
+            let error = validate_date_keys(created_key, modified_key).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, error.kind());
+            assert_eq!(message, error.to_string());
+        }
+    }
+
+    #[test]
+    fn verification_fails_for_bad_front_matter() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let bad_yaml = dest_dir.path().join("bad yaml.md");
+        fs::write(&bad_yaml, "---\ntitle: [unclosed\n---\nbody\n").unwrap();
+        let error = verify_markdown_file(&bad_yaml, &ConversionOptions::default()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(format!("{}", error).contains("YAML ERROR"));
+
+        let missing_fields = dest_dir.path().join("missing fields.md");
+        fs::write(&missing_fields, "---\ntitle: A title\n---\nbody\n").unwrap();
+        let error =
+            verify_markdown_file(&missing_fields, &ConversionOptions::default()).unwrap_err();
+        assert!(format!("{}", error).contains("missing field `created`"));
+
+        let unterminated = dest_dir.path().join("unterminated.md");
+        fs::write(&unterminated, "---\ntitle: A title\nbody\n").unwrap();
+        let error = verify_markdown_file(&unterminated, &ConversionOptions::default()).unwrap_err();
+        assert!(format!("{}", error).contains("front matter is not terminated"));
+    }
 }
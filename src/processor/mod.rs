@@ -1,3 +1,12 @@
 pub mod applenotes;
+pub mod datetime;
+pub mod driver;
+pub mod email;
+pub mod evernote;
+pub mod googlekeep;
+pub mod html;
+pub mod json;
 pub mod markdown;
+pub mod notion;
 pub mod simplenote;
+pub mod sink;
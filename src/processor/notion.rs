@@ -0,0 +1,352 @@
+use super::datetime::format_timestamp;
+use super::driver::{convert_notes, SourceNote};
+use super::markdown::{
+    age_between, normalize_tag_case, with_default_tag, with_title_affixes, wrap_content, Markdown,
+    MarkdownMeta,
+};
+use super::sink::dest_sink;
+use crate::clock::Clock;
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// A page from a Notion markdown export: its title, the `Name: value` property lines Notion
+/// writes below it, and the rest of the page.
+#[derive(Debug, PartialEq)]
+struct NotionPage {
+    title: String,
+    properties: Vec<(String, String)>,
+    body: String,
+}
+
+pub fn process(
+    source_dir: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    ConversionStats::collect(options, |stats| {
+        let mut page_files = Vec::new();
+        find_pages(&source_dir, &mut page_files)?;
+        page_files.sort();
+        if page_files.is_empty() {
+            println!("No Notion pages found to process.");
+        }
+        let pages = page_files.into_iter().map(|page_file| SourceNote {
+            name: page_file
+                .strip_prefix(&source_dir)
+                .unwrap_or(&page_file)
+                .to_string_lossy()
+                .to_string(),
+            source: fs::read_to_string(&page_file).map(|text| (page_file, text)),
+        });
+        convert_notes(
+            pages,
+            |(_, text)| text.as_bytes(),
+            |(page_file, text), name| {
+                convert_to_markdown(parse_page(&text, &page_file), name, options, clock)
+            },
+            dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+            options,
+            clock,
+            stats,
+        )
+    })
+}
+
+/// Collect the markdown pages beneath `dir`. Subpages are in directories named like their
+/// parent page; database CSVs are skipped.
+fn find_pages(dir: &Path, page_files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_pages(&path, page_files)?;
+        } else if path.extension().is_some_and(|e| e == "md") {
+            page_files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Notion appends a 32 hex digit page id to every exported file and directory name.
+fn strip_page_id(name: &str) -> &str {
+    lazy_static! {
+        static ref RE_PAGE_ID: Regex = Regex::new(r"^(.*?)\s+[0-9a-f]{32}$").unwrap();
+    }
+    match RE_PAGE_ID.captures(name) {
+        Some(caps) => caps.get(1).map_or(name, |m| m.as_str()),
+        None => name,
+    }
+}
+
+fn parse_page(text: &str, page_file: &Path) -> NotionPage {
+    lazy_static! {
+        static ref RE_PROPERTY: Regex = Regex::new(r"^([A-Za-z][\w ]*): (.*)$").unwrap();
+    }
+
+    let mut lines = text
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .peekable();
+    let title = match lines.peek().and_then(|line| line.strip_prefix("# ")) {
+        Some(heading) => {
+            let title = heading.trim().to_string();
+            lines.next();
+            title
+        }
+        None => {
+            let stem = page_file.file_stem().unwrap_or_default().to_string_lossy();
+            strip_page_id(&stem).to_string()
+        }
+    };
+
+    let mut lines = lines.skip_while(|line| line.trim().is_empty()).peekable();
+    let mut properties = Vec::new();
+    while let Some(caps) = lines.peek().and_then(|line| RE_PROPERTY.captures(line)) {
+        properties.push((caps[1].to_string(), caps[2].trim().to_string()));
+        lines.next();
+    }
+
+    let body: Vec<&str> = lines.skip_while(|line| line.trim().is_empty()).collect();
+    NotionPage {
+        title,
+        properties,
+        body: body.join("\n"),
+    }
+}
+
+/// A Notion property date like `January 14, 2022 7:36 AM`. Notion exports dates without a time
+/// zone, so they are taken to be UTC.
+fn parse_notion_date(value: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value, "%B %d, %Y %I:%M %p")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%B %d, %Y")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// Point links between exported pages at the files notes2md writes, which are all in one
+/// directory and named without Notion's page ids.
+fn rewrite_page_links(content: &str) -> String {
+    lazy_static! {
+        static ref RE_PAGE_LINK: Regex =
+            Regex::new(r"\]\(([^()\s]*?)%20[0-9a-f]{32}\.md\)").unwrap();
+    }
+    RE_PAGE_LINK
+        .replace_all(content, |caps: &regex::Captures| {
+            let target = caps[1].rsplit('/').next().unwrap_or("");
+            format!("]({}.md)", target)
+        })
+        .to_string()
+}
+
+fn convert_to_markdown(
+    page: NotionPage,
+    name: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, Error> {
+    let property = |names: &[&str]| {
+        page.properties
+            .iter()
+            .find(|(k, _)| names.iter().any(|n| k.eq_ignore_ascii_case(n)))
+            .map(|(_, v)| v.as_str())
+    };
+    let created = property(&["Created", "Created time"]).and_then(parse_notion_date);
+    let modified =
+        property(&["Last edited time", "Last edited", "Updated"]).and_then(parse_notion_date);
+    let (created, modified) = match (created, modified) {
+        (Some(c), Some(m)) => (c, m),
+        _ if options.strict => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "page: '{}' is missing its creation or modification date",
+                    name
+                ),
+            ))
+        }
+        (Some(c), None) => (c, c),
+        (None, Some(m)) => (m, m),
+        (None, None) => (clock.now(), clock.now()),
+    };
+    let tags = property(&["Tags"]).map(|tags| {
+        let tags = tags
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect();
+        normalize_tag_case(tags, options.tag_case)
+    });
+    let age = match options.emit_age {
+        true => age_between(&created.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let (created, modified) = (
+        format_timestamp(created, options),
+        format_timestamp(modified, options),
+    );
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from notion page={}",
+            env!("CARGO_PKG_VERSION"),
+            name
+        )),
+        false => None,
+    };
+
+    Ok(Markdown {
+        meta: MarkdownMeta {
+            title: with_title_affixes(page.title, options),
+            created,
+            modified,
+            deleted: None,
+            favorited: None,
+            pinned: None,
+            tags: with_default_tag(tags, options),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: None,
+            publish_url: None,
+            aliases: None,
+            markdown: options.assume_markdown,
+            id: None,
+            extra: serde_yaml::Mapping::new(),
+        },
+        content: match options.wrap {
+            Some(width) => wrap_content(&rewrite_page_links(&page.body), width),
+            None => rewrite_page_links(&page.body),
+        },
+        provenance,
+        file_prefix: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{FixedClock, SystemClock};
+    use chrono::DateTime;
+
+    #[test]
+    fn strip_page_id_from_names() {
+        assert_eq!(
+            "Meeting Notes",
+            strip_page_id("Meeting Notes 0123456789abcdef0123456789abcdef")
+        );
+        assert_eq!("No id here", strip_page_id("No id here"));
+    }
+
+    #[test]
+    fn parse_page_with_properties() {
+        let text = "# A page\n\nCreated: January 14, 2022 7:36 AM\nTags: a, b\n\nBody: not a property\nmore";
+        let expected = NotionPage {
+            title: String::from("A page"),
+            properties: vec![
+                (
+                    String::from("Created"),
+                    String::from("January 14, 2022 7:36 AM"),
+                ),
+                (String::from("Tags"), String::from("a, b")),
+            ],
+            body: String::from("Body: not a property\nmore"),
+        };
+        assert_eq!(expected, parse_page(text, Path::new("A page.md")));
+    }
+
+    #[test]
+    fn parse_page_without_heading_uses_file_name() {
+        let page = parse_page(
+            "Just text",
+            Path::new("Loose 0123456789abcdef0123456789abcdef.md"),
+        );
+        assert_eq!("Loose", page.title);
+        assert_eq!("Just text", page.body);
+    }
+
+    #[test]
+    fn parse_notion_dates() {
+        assert_eq!(
+            Some(String::from("2022-01-14T19:36:00.000Z")),
+            parse_notion_date("January 14, 2022 7:36 PM")
+                .map(|d| format_timestamp(d, &ConversionOptions::default()))
+        );
+        assert_eq!(
+            Some(String::from("2022-01-14T00:00:00.000Z")),
+            parse_notion_date("January 14, 2022")
+                .map(|d| format_timestamp(d, &ConversionOptions::default()))
+        );
+        assert_eq!(None, parse_notion_date("last Tuesday"));
+    }
+
+    #[test]
+    fn rewrite_page_links_drops_ids_and_directories() {
+        let content = "[A](Dir%20aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa/Sub%20Page%20bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb.md) [B](https://example.com/x.md)";
+        assert_eq!(
+            "[A](Sub%20Page.md) [B](https://example.com/x.md)",
+            rewrite_page_links(content)
+        );
+    }
+
+    #[test]
+    fn convert_page_without_dates_uses_clock() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let page = NotionPage {
+            title: String::from("Undated"),
+            properties: vec![],
+            body: String::from("text"),
+        };
+        let md = convert_to_markdown(
+            page,
+            "Undated.md",
+            &ConversionOptions::default(),
+            &FixedClock(now),
+        )
+        .unwrap();
+        assert_eq!("2024-05-01T00:00:00.000Z", md.meta.created);
+        assert_eq!("2024-05-01T00:00:00.000Z", md.meta.modified);
+    }
+
+    #[test]
+    fn process_notion_export() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            PathBuf::from("test_data/notion"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(2, stats.converted);
+
+        let meeting = fs::read_to_string(dest_dir.path().join("Meeting Notes.md")).unwrap();
+        let expected = r#"---
+title: Meeting Notes
+created: "2022-01-14T07:36:00.000Z"
+modified: "2022-01-15T21:05:00.000Z"
+tags:
+  - Work
+  - Planning
+---
+Agreed to ship on Friday.
+
+See [Action Items](Action%20Items.md) for who does what.
+"#;
+        assert_eq!(expected, meeting);
+
+        let actions = fs::read_to_string(dest_dir.path().join("Action Items.md")).unwrap();
+        assert!(actions.contains("- Back to [Meeting Notes](Meeting%20Notes.md)\n"));
+    }
+}
@@ -1,10 +1,30 @@
-use super::markdown::{write_markdown, Markdown, MarkdownMeta};
+use super::datetime::{format_timestamp, parse_timestamp};
+use super::markdown::{
+    age_between, escape_markdown, inline_reference_links, normalize_tag_case, render_markdown,
+    run_transform_cmd, split_on_headings, split_tags, truncate_chars, truncate_content,
+    with_default_tag, with_title_affixes, wrap_content, Markdown, MarkdownMeta,
+};
+use super::sink::{dest_sink, NoteSink};
+use crate::cache::ConversionCache;
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::error::Notes2mdError;
+use crate::filter::{is_recent, is_tagged, is_within_size, untagged, NoteFields};
+use crate::manifest::Manifest;
+use crate::seen::{content_hash, SeenHashes};
+use crate::space::SystemFreeSpace;
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
 use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
 use std::str;
-use std::{fs, path::PathBuf};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct SimpleNotes {
@@ -21,46 +41,262 @@ struct SimpleNote {
     id: String,
     content: String,
     #[serde(rename(deserialize = "creationDate"))]
-    creation_date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    creation_date: Option<String>,
     #[serde(rename(deserialize = "lastModified"))]
-    last_modified: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     markdown: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pinned: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tags: Option<Vec<String>>,
+    #[serde(rename(deserialize = "shareURL"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    share_url: Option<String>,
+    #[serde(rename(deserialize = "publishURL"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publish_url: Option<String>,
+    /// path of a file holding the body, relative to the source file, used when `content` is empty
+    #[serde(rename(deserialize = "contentFile"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_file: Option<String>,
+    /// put before the note's file name to keep the export's order, set by `number_notes`
+    #[serde(skip)]
+    file_prefix: Option<String>,
+    /// the note's id in the export when `disambiguate_trashed_ids` has changed `id`, which the
+    /// run records the note under, so front matter keeps the id the note really has
+    #[serde(skip)]
+    source_id: Option<String>,
+}
+
+pub fn process(
+    source_file: PathBuf,
+    dest_dir: PathBuf,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, std::io::Error> {
+    process_into(
+        source_file,
+        dest_sink(dest_dir, options, &SystemFreeSpace, clock).as_mut(),
+        options,
+        clock,
+    )
 }
 
-pub fn process(source_file: PathBuf, dest_dir: PathBuf) -> Result<(), std::io::Error> {
-    let source_text = load_file(&source_file)?;
-    let all_notes = deserialize_notes(source_text)?;
+/// Convert a Simplenote export as `process` does, handing each note to `sink` to be written.
+pub fn process_into(
+    source_file: PathBuf,
+    sink: &mut dyn NoteSink,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, std::io::Error> {
+    ConversionStats::collect(options, |stats| {
+        let source_text = load_file(&source_file, options.source_encoding.as_deref())?;
+        if options.warn_unknown_fields {
+            for message in unknown_fields(&source_file, &source_text) {
+                diagnostics::warning(options, clock, None, &message);
+            }
+        }
+        let mut all_notes = deserialize_notes(source_text).map_err(invalid_json)?;
+        if options.order_prefix {
+            number_notes(&mut all_notes);
+        }
+        disambiguate_trashed_ids(&mut all_notes, options, clock);
+
+        let source_dir = source_file.parent().unwrap_or_else(|| Path::new(""));
+        for notes in [&mut all_notes.active_notes, &mut all_notes.trashed_notes]
+            .into_iter()
+            .flatten()
+        {
+            notes.retain_mut(|note| match load_content_file(note, source_dir) {
+                Ok(_) => true,
+                Err(e) => {
+                    stats.record_failure(&note.id, &e);
+                    diagnostics::error(options, clock, Some(&note.id), &e.to_string());
+                    false
+                }
+            });
+        }
+
+        if options.validate_dates {
+            let invalid = invalid_dates(&all_notes);
+            if !invalid.is_empty() {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "source_file: '{}' has notes with invalid dates:\n{}",
+                        source_file.to_string_lossy(),
+                        invalid.join("\n")
+                    ),
+                ));
+            }
+        }
+
+        if options.report_title_collisions {
+            report_title_collisions(&all_notes, options);
+            stats.skipped += [&all_notes.active_notes, &all_notes.trashed_notes]
+                .into_iter()
+                .flatten()
+                .map(Vec::len)
+                .sum::<usize>();
+            return Ok(());
+        }
+
+        let mut records = RunRecords {
+            manifest: match &options.update_from_manifest {
+                Some(manifest_file) => Some(Manifest::load(manifest_file)?),
+                None => None,
+            },
+            seen: match &options.seen_file {
+                Some(seen_file) => Some(SeenHashes::load(seen_file)?),
+                None => None,
+            },
+            cache: match &options.cache_dir {
+                Some(cache_dir) => Some(ConversionCache::open(cache_dir, options)?),
+                None => None,
+            },
+        };
+        process_notes(
+            all_notes.active_notes,
+            false,
+            sink,
+            options,
+            clock,
+            stats,
+            &mut records,
+        )?;
+        process_notes(
+            all_notes.trashed_notes,
+            true,
+            sink,
+            options,
+            clock,
+            stats,
+            &mut records,
+        )?;
+        if let Some(manifest) = records.manifest {
+            manifest.save(options)?;
+        }
+
+        stats.collisions = sink.collisions();
+        Ok(())
+    })
+}
 
-    let active_result = process_notes(all_notes.active_notes, false, &dest_dir);
-    if active_result.is_err() {
-        println!("{}", active_result.unwrap_err());
+/// Rename trashed notes which share an id with an active note, a sync artifact of some exports,
+/// to `id (trashed)` with a warning. The active note keeps the id, so what is recorded by id,
+/// like the manifest, refers to it.
+fn disambiguate_trashed_ids(
+    all_notes: &mut SimpleNotes,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) {
+    let active_ids: HashSet<String> = all_notes
+        .active_notes
+        .iter()
+        .flatten()
+        .map(|note| note.id.clone())
+        .collect();
+    for note in all_notes.trashed_notes.iter_mut().flatten() {
+        if active_ids.contains(&note.id) {
+            let renamed = format!("{} (trashed)", note.id);
+            diagnostics::warning(
+                options,
+                clock,
+                None,
+                &format!(
+                    "note: '{}' is both active and trashed, the trashed note is recorded as '{}'",
+                    note.id, renamed
+                ),
+            );
+            note.source_id = Some(std::mem::replace(&mut note.id, renamed));
+        }
     }
+}
 
-    let trashed_result = process_notes(all_notes.trashed_notes, true, &dest_dir);
-    if trashed_result.is_err() {
-        println!("{}", trashed_result.unwrap_err());
+/// Give each note a file name prefix of its position in the export, active notes first, so
+/// the files sort in the export's order. Positions are zero-padded to at least four digits.
+fn number_notes(all_notes: &mut SimpleNotes) {
+    let notes: Vec<&mut SimpleNote> = [&mut all_notes.active_notes, &mut all_notes.trashed_notes]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    let width = notes.len().to_string().len().max(4);
+    for (i, note) in notes.into_iter().enumerate() {
+        note.file_prefix = Some(format!("{:0width$} ", i + 1, width = width));
     }
+}
 
-    Ok(())
+/// What a run keeps for later runs: where each note was written, which notes' content has
+/// already been converted, and the notes as they were converted.
+struct RunRecords {
+    manifest: Option<Manifest>,
+    seen: Option<SeenHashes>,
+    cache: Option<ConversionCache>,
 }
 
 fn process_notes(
     notes: Option<Vec<SimpleNote>>,
     trashed: bool,
-    dest_dir: &PathBuf,
+    sink: &mut dyn NoteSink,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+    stats: &mut ConversionStats,
+    records: &mut RunRecords,
 ) -> Result<(), std::io::Error> {
+    let manifest = &mut records.manifest;
     match notes {
         Some(n) => {
             for note in n {
-                let md = convert_to_markdown(note, trashed);
-                let result = write_markdown(md, dest_dir);
-                if result.is_err() {
-                    println!("{}", result.unwrap_err());
+                let hash = content_hash(note.content.as_bytes());
+                let seen = records.seen.as_ref().is_some_and(|s| s.contains(hash));
+                if seen || !is_selected(&note, trashed, options, clock) {
+                    stats.skipped += 1;
+                    continue;
+                }
+                let tags = with_default_tag(note.tags.clone(), options);
+                if !is_tagged(&note.id, tags.as_deref(), options, clock) {
+                    return Err(untagged(&note.id));
+                }
+                sink.check_reserve(note.content.len() as u64)?;
+                let id = note.id.clone();
+                let cache = records.cache.as_ref();
+                let result = convert_cached(note, trashed, options, clock, cache).and_then(|md| {
+                    let tags = md.meta.tags.clone().unwrap_or_default();
+                    let notes = match options.split_on_headings {
+                        Some(level) => split_on_headings(md, level),
+                        None => vec![md],
+                    };
+                    let sections = notes.len();
+                    let mut file_paths = Vec::new();
+                    for (i, md) in notes.into_iter().enumerate() {
+                        // sections of a split note are told apart by their position
+                        let key = match sections {
+                            1 => id.clone(),
+                            _ => format!("{}#{}", id, i + 1),
+                        };
+                        file_paths.extend(write_converted(md, &key, sink, manifest)?);
+                    }
+                    Ok((file_paths, tags))
+                });
+                match result {
+                    Ok((file_paths, tags)) => {
+                        stats.record_tags(&tags);
+                        if let Some(seen) = &mut records.seen {
+                            seen.record(hash)?;
+                        }
+                        for file_path in file_paths {
+                            sink.verify(&file_path)?;
+                            stats.record_converted(&file_path);
+                        }
+                    }
+                    Err(e) => {
+                        stats.record_failure(&id, &e);
+                        diagnostics::error(options, clock, Some(&id), &e.to_string());
+                    }
                 }
             }
         }
@@ -75,95 +311,732 @@ fn process_notes(
     Ok(())
 }
 
-fn load_file(source_file: &PathBuf) -> Result<String, std::io::Error> {
+/// Convert `note` as `convert_to_markdown` does, or take it from `cache` when it was converted
+/// before and hasn't been modified since. Notes without a modified date aren't cached, as their
+/// dates come from the clock.
+fn convert_cached(
+    note: SimpleNote,
+    trashed: bool,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+    cache: Option<&ConversionCache>,
+) -> Result<Markdown, std::io::Error> {
+    let (cache, modified) = match (cache, note.last_modified.clone()) {
+        (Some(cache), Some(modified)) => (cache, modified),
+        _ => return convert_to_markdown(note, trashed, options, clock),
+    };
+    if let Some(mut markdown) = cache.get(&note.id, &modified, trashed) {
+        // the note's position in the export and its age aren't part of the note
+        markdown.file_prefix = note.file_prefix;
+        // a creation date in a custom date_format can't be read back, so keeps its age
+        let age = age_between(&markdown.meta.created, clock.now());
+        if options.emit_age && age.is_some() {
+            markdown.meta.age = age;
+        }
+        return Ok(markdown);
+    }
+    let id = note.id.clone();
+    let markdown = convert_to_markdown(note, trashed, options, clock)?;
+    cache.put(&id, &modified, trashed, &markdown, options)?;
+    Ok(markdown)
+}
+
+/// Whether a note is markdown, as forced by `assume_markdown` or else as its source says.
+fn is_markdown(note: &SimpleNote, options: &ConversionOptions) -> Option<bool> {
+    options.assume_markdown.or(note.markdown)
+}
+
+/// Whether a note passes the `--where` filter and `--recent`, if given.
+fn is_selected(
+    note: &SimpleNote,
+    trashed: bool,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> bool {
+    if let Some(filter) = &options.filter {
+        let fields = NoteFields {
+            pinned: note.pinned == Some(true),
+            trashed,
+            markdown: is_markdown(note, options) == Some(true),
+            tags: note.tags.as_deref().unwrap_or_default(),
+            content: &note.content,
+        };
+        if !filter.matches(&fields) {
+            return false;
+        }
+    }
+    is_recent(note.last_modified.as_deref(), &note.id, options, clock)
+        && is_within_size(
+            &title_from_content(&note.content, options),
+            &note.content,
+            options,
+            clock,
+        )
+}
+
+/// Convert a Simplenote export held in memory, returning the path relative to dest_dir and the
+/// text of each note as it would be written. Nothing is read or written, so `contentFile`
+/// bodies aren't loaded and notes are always rendered one markdown file each; the first note
+/// which can't be converted fails the whole render.
+pub fn render(
+    json: &str,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Vec<(PathBuf, String)>, std::io::Error> {
+    let mut all_notes = deserialize_notes(json.to_string()).map_err(invalid_json)?;
+    if options.order_prefix {
+        number_notes(&mut all_notes);
+    }
+    let mut taken = HashSet::new();
+    let mut rendered = Vec::new();
+    for (notes, trashed) in [
+        (all_notes.active_notes, false),
+        (all_notes.trashed_notes, true),
+    ] {
+        for note in notes.into_iter().flatten() {
+            if !is_selected(&note, trashed, options, clock) {
+                continue;
+            }
+            let md = convert_to_markdown(note, trashed, options, clock)?;
+            let notes = match options.split_on_headings {
+                Some(level) => split_on_headings(md, level),
+                None => vec![md],
+            };
+            for md in notes {
+                rendered.push(render_markdown(&md, &mut taken, options)?);
+            }
+        }
+    }
+    Ok(rendered)
+}
+
+/// Write a converted note, replacing the file a previous run wrote it to when the manifest
+/// knows of one, and record where it went. Returns every file written, as the sink does.
+fn write_converted(
+    markdown: Markdown,
+    key: &str,
+    sink: &mut dyn NoteSink,
+    manifest: &mut Option<Manifest>,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let existing = manifest.as_ref().and_then(|m| m.existing_path(key));
+    let file_paths = match existing {
+        Some(path) => sink.replace(&markdown, path)?,
+        None => sink.write(&markdown)?,
+    };
+    // copies of the note follow its own file, which is the one replaced on later runs
+    if let (Some(manifest), Some(file_path)) = (manifest, file_paths.first()) {
+        manifest.record(key, file_path)?;
+    }
+    Ok(file_paths)
+}
+
+/// A line for each date in the notes which can't be read, naming the note and field. Dates are
+/// read as `parse_timestamp` does, so may be RFC 3339 or lack their offset or time. Missing dates
+/// aren't listed, as conversion can fall back to another date for them.
+fn invalid_dates(all_notes: &SimpleNotes) -> Vec<String> {
+    let mut invalid = Vec::new();
+    for note in [&all_notes.active_notes, &all_notes.trashed_notes]
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        for (field, date) in [
+            ("creationDate", &note.creation_date),
+            ("lastModified", &note.last_modified),
+        ] {
+            if let Some(date) = date {
+                if parse_timestamp(date).is_err() {
+                    invalid.push(format!("    {}: {} '{}'", note.id, field, date));
+                }
+            }
+        }
+    }
+    invalid
+}
+
+/// Groups of note ids whose notes derive the same title, ordered by title. Notes with a title
+/// of their own aren't included.
+fn title_collisions(
+    all_notes: &SimpleNotes,
+    options: &ConversionOptions,
+) -> Vec<(String, Vec<String>)> {
+    let mut by_title: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let notes = all_notes
+        .active_notes
+        .iter()
+        .chain(all_notes.trashed_notes.iter());
+    for note in notes.flatten() {
+        by_title
+            .entry(title_from_content(&note.content, options))
+            .or_default()
+            .push(note.id.clone());
+    }
+    by_title
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .collect()
+}
+
+fn report_title_collisions(all_notes: &SimpleNotes, options: &ConversionOptions) {
+    let collisions = title_collisions(all_notes, options);
+    if collisions.is_empty() {
+        println!("No title collisions found.");
+    }
+    for (title, ids) in collisions {
+        println!("{} notes share the title '{}':", ids.len(), title);
+        for id in ids {
+            println!("    {}", id);
+        }
+    }
+}
+
+/// Read the body of a note which keeps it in a separate `content_file`. The path must stay
+/// within `source_dir`, so absolute paths and `..` are rejected.
+fn load_content_file(note: &mut SimpleNote, source_dir: &Path) -> Result<(), std::io::Error> {
+    let content_file = match &note.content_file {
+        Some(f) if note.content.is_empty() => f,
+        _ => return Ok(()),
+    };
+    let relative = Path::new(content_file);
+    let contained = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !contained {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "note: '{}' content_file: '{}' is not within the source directory",
+                note.id, content_file
+            ),
+        ));
+    }
+    match fs::read_to_string(source_dir.join(relative)) {
+        Ok(content) => {
+            note.content = content;
+            Ok(())
+        }
+        Err(e) => Err(std::io::Error::new(
+            e.kind(),
+            format!(
+                "note: '{}' content_file: '{}' could not be read: {}",
+                note.id, content_file, e
+            ),
+        )),
+    }
+}
+
+fn load_file(source_file: &Path, encoding: Option<&str>) -> Result<String, std::io::Error> {
     // this function is well guarded by `verify_source`, so we'll assume that IO is not a problem here
-    let bytes = fs::read(&source_file)?;
+    let bytes = fs::read(source_file)?;
+    if let Some(label) = encoding {
+        return decode_file(source_file, &bytes, label);
+    }
     let text = String::from_utf8(bytes);
     match text {
         Ok(t) => Ok(t),
         Err(f) => {
             eprintln!("Error: {}", f);
-            Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "source_file: '{}' contains data which is not UTF8",
-                    source_file.to_string_lossy()
-                ),
+            Err(Notes2mdError::NotUtf8(format!(
+                "source_file: '{}' contains data which is not UTF8",
+                source_file.to_string_lossy()
+            ))
+            .into_io(ErrorKind::InvalidData))
+        }
+    }
+}
+
+fn decode_file(source_file: &Path, bytes: &[u8], label: &str) -> Result<String, std::io::Error> {
+    let encoding = match Encoding::for_label(label.as_bytes()) {
+        Some(e) => e,
+        None => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("source_encoding: '{}' is not a known encoding", label),
             ))
         }
+    };
+    match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+        Some(text) => Ok(text.into_owned()),
+        None => Err(Notes2mdError::NotUtf8(format!(
+            "source_file: '{}' contains data which is not {}",
+            source_file.to_string_lossy(),
+            encoding.name()
+        ))
+        .into_io(ErrorKind::InvalidData)),
+    }
+}
+
+/// The fields of an export, and of each of its notes, which `SimpleNotes` and `SimpleNote` read.
+const EXPORT_FIELDS: &[&str] = &["activeNotes", "trashedNotes"];
+const NOTE_FIELDS: &[&str] = &[
+    "id",
+    "content",
+    "creationDate",
+    "lastModified",
+    "markdown",
+    "pinned",
+    "tags",
+    "shareURL",
+    "publishURL",
+    "contentFile",
+];
+
+/// A message for each field of the export in `source_text` which isn't converted, and so is
+/// dropped: one for each unknown top level field, and one for each unknown note field giving
+/// how many notes have it. Text which isn't a JSON object has none; deserializing reports it.
+fn unknown_fields(source_file: &Path, source_text: &str) -> Vec<String> {
+    let export = match serde_json::from_str::<serde_json::Value>(source_text) {
+        Ok(serde_json::Value::Object(export)) => export,
+        _ => return Vec::new(),
+    };
+    let source_name = source_file.to_string_lossy();
+    let mut messages: Vec<String> = export
+        .keys()
+        .filter(|key| !EXPORT_FIELDS.contains(&key.as_str()))
+        .map(|key| {
+            format!(
+                "source_file: '{}' has a field '{}' which is not converted",
+                source_name, key
+            )
+        })
+        .collect();
+    let mut note_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let notes = EXPORT_FIELDS
+        .iter()
+        .filter_map(|field| export.get(*field)?.as_array())
+        .flatten()
+        .filter_map(serde_json::Value::as_object);
+    for note in notes {
+        for key in note.keys() {
+            if !NOTE_FIELDS.contains(&key.as_str()) {
+                *note_counts.entry(key).or_default() += 1;
+            }
+        }
     }
+    messages.extend(note_counts.into_iter().map(|(key, count)| {
+        format!(
+            "source_file: '{}' has {} notes with a field '{}' which is not converted",
+            source_name, count, key
+        )
+    }));
+    messages
+}
+
+/// A Simplenote export which can't be deserialized, as the error a processor returns.
+fn invalid_json(e: serde_json::Error) -> std::io::Error {
+    Notes2mdError::InvalidJson(e.to_string()).into_io(ErrorKind::InvalidData)
 }
 
 fn deserialize_notes(source_text: String) -> Result<SimpleNotes, serde_json::Error> {
-    match serde_json::from_str(&source_text) {
-        Ok(notes) => Ok(notes),
-        Err(e) => Err(e),
+    serde_json::from_str(&source_text)
+}
+
+/// Characters stripped from titles unless `title_strip_chars` gives others, as the contents of
+/// a regex character class.
+const TITLE_STRIP_CHARS_DEFAULT: &str = r#"'"`#()!~>_\[\]\*"#;
+
+/// Characters a title taken from a note's first line is cut to unless `max_title_len` is set.
+const TITLE_MAX_LEN_DEFAULT: usize = 200;
+
+/// The characters `title_strip_chars` strips from titles, compiled once when the options are
+/// built rather than for each note. Sets given the same way are equal.
+#[derive(Debug, Clone)]
+pub struct TitleStripChars(Regex);
+
+impl PartialEq for TitleStripChars {
+    fn eq(&self, other: &TitleStripChars) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl TitleStripChars {
+    /// The set matching any one of `set`, which is the contents of a regex character class like
+    /// `#*@` or `\[\]a-z`. Brackets must be escaped or balanced, so the set is a single class.
+    pub fn new(set: &str) -> Result<TitleStripChars, std::io::Error> {
+        title_strip_regex(set).map(TitleStripChars)
+    }
+}
+
+/// The regex matching any one of `set`, rejecting a set which isn't a single class.
+fn title_strip_regex(set: &str) -> Result<Regex, std::io::Error> {
+    let invalid = |reason: &str| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "title_strip_chars: '{}' is not a valid character set: {}",
+                set, reason
+            ),
+        )
+    };
+    let mut depth: i32 = 0;
+    let mut chars = set.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => depth += 1,
+            ']' if depth == 0 => return Err(invalid("unescaped ']'")),
+            ']' => depth -= 1,
+            _ => (),
+        }
+    }
+    if depth != 0 {
+        return Err(invalid("unbalanced '['"));
     }
+    Regex::new(&format!("[{}]", set)).map_err(|e| invalid(&e.to_string()))
 }
 
-fn title_from_content(content: &String) -> String {
+pub(crate) fn title_from_content(content: &str, options: &ConversionOptions) -> String {
     lazy_static! {
         static ref RE_MD_URL: Regex = Regex::new(r"\([^)]*\)").unwrap();
-        static ref RE_BOGUS_TITLE_CHARS: Regex = Regex::new(r#"['"`#()!~>_\[\]\*]"#).unwrap();
+        static ref RE_BOGUS_TITLE_CHARS: Regex =
+            title_strip_regex(TITLE_STRIP_CHARS_DEFAULT).unwrap();
     }
 
-    let mut first_line = "";
-    for line in content.lines() {
-        if "" != line.trim() {
-            first_line = line;
-            break;
-        }
+    let mut non_blank_lines = content.lines().filter(|line| "" != line.trim());
+    let mut first_line = non_blank_lines.next().unwrap_or("");
+    let short_url = match options.shorten_url_titles {
+        true => title_url(content).and_then(shorten_url),
+        false => None,
+    };
+
+    if let Some(short_url) = &short_url {
+        first_line = short_url;
+    } else if options.title_sentence && non_blank_lines.next().is_none() {
+        // a note which is one long line would otherwise repeat the whole of it as the title
+        first_line = first_sentence(first_line);
     }
 
     // nuke any markdown style URL definitions
-    let line_no_url: String = RE_MD_URL.replace_all(&first_line, "").to_string();
+    let line_no_url: String = RE_MD_URL.replace_all(first_line, "").to_string();
 
     // nuke some bogus characters
-    let line_no_bogos: String = RE_BOGUS_TITLE_CHARS
-        .replace_all(&line_no_url, "")
-        .to_string();
+    let bogus_chars = match &options.title_strip_chars {
+        Some(TitleStripChars(set)) => set,
+        None => &RE_BOGUS_TITLE_CHARS,
+    };
+    let line_no_bogos: String = bogus_chars.replace_all(&line_no_url, "").to_string();
+
+    // leading dots/whitespace stripped and trimmed
+    let line_trim = line_no_bogos
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim();
+
+    // ensure not longer than the limit
+    let max_len = options.max_title_len.unwrap_or(TITLE_MAX_LEN_DEFAULT);
+    truncate_chars(line_trim, max_len).to_string()
+}
+
+/// The note's first line, when it is nothing but an http or https URL.
+fn title_url(content: &str) -> Option<&str> {
+    lazy_static! {
+        static ref RE_BARE_URL: Regex = Regex::new(r"^https?://[^/\s]+\S*$").unwrap();
+    }
+
+    let first_line = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    match RE_BARE_URL.is_match(first_line) {
+        true => Some(first_line),
+        false => None,
+    }
+}
+
+/// A URL as `host - last-path-segment`, or just `host` when the path is empty, joined so the
+/// title still makes a file name. The query and fragment are dropped.
+fn shorten_url(url: &str) -> Option<String> {
+    let rest = url.split_once("://")?.1;
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    // credentials and ports aren't worth keeping in a title
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = host.split(':').next().unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    match path.rsplit('/').find(|segment| !segment.is_empty()) {
+        Some(segment) => Some(format!("{} - {}", host, segment)),
+        None => Some(host.to_string()),
+    }
+}
+
+/// The text before the first sentence-ending `.`, `!` or `?` which is followed by whitespace,
+/// or all of `line` if there isn't one.
+fn first_sentence(line: &str) -> &str {
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if ['.', '!', '?'].contains(&c) {
+            match chars.peek() {
+                Some((_, next)) if next.is_whitespace() => return &line[..i],
+                None => return &line[..i],
+                _ => (),
+            }
+        }
+    }
+    line
+}
+
+/// The note's creation and modification dates. When one is missing the other is used for both,
+/// or the current time when neither is present; with `strict` a missing date is an error, and
+/// a date which can't be read always is.
+fn resolve_dates(
+    source: &SimpleNote,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), std::io::Error> {
+    let parse = |field: &str, date: &Option<String>| match date.as_deref().map(parse_timestamp) {
+        Some(Err(e)) => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "note: '{}' has an invalid {} '{}', {}",
+                source.id,
+                field,
+                date.as_deref().unwrap_or_default(),
+                e
+            ),
+        )),
+        Some(Ok(date)) => Ok(Some(date)),
+        None => Ok(None),
+    };
+    let creation_date = parse("creationDate", &source.creation_date)?;
+    let last_modified = parse("lastModified", &source.last_modified)?;
+    let (created, modified) = match (creation_date, last_modified) {
+        (Some(c), Some(m)) => return Ok((c, m)),
+        _ if options.strict => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "note: '{}' is missing its creation or modification date",
+                    source.id
+                ),
+            ))
+        }
+        (Some(c), None) => (c, c),
+        (None, Some(m)) => (m, m),
+        (None, None) => (clock.now(), clock.now()),
+    };
+    diagnostics::warning(
+        options,
+        clock,
+        None,
+        &format!(
+            "note: '{}' is missing a date, using created: '{}' modified: '{}'",
+            source.id,
+            format_timestamp(created, options),
+            format_timestamp(modified, options)
+        ),
+    );
+    Ok((created, modified))
+}
+
+/// The note body after applying the content options, or exactly as exported for `raw_content`.
+fn convert_content(source: &SimpleNote, options: &ConversionOptions) -> String {
+    if options.raw_content {
+        return source.content.clone();
+    }
+
+    let mut content = source.content.replace("\r\n", "\n");
+    if options.strip_title_line {
+        content = strip_title_line(&content, options);
+    }
+    if options.inline_links {
+        content = inline_reference_links(&content);
+    }
+    if let Some(max_length) = options.content_max_length {
+        content = truncate_content(&content, max_length);
+    }
+    if options.escape_plaintext && Some(false) == is_markdown(source, options) {
+        content = escape_markdown(&content);
+    }
+    if let Some(width) = options.wrap {
+        content = wrap_content(&content, width);
+    }
+    content
+}
 
-    // leading dots/spaces stripped and trimmed
-    let line_trim = line_no_bogos.trim_start_matches([' ', '.']).trim();
+/// Drop the line a note's title is taken from, with the blank lines around it, so the title
+/// isn't repeated in the body. A body of only blank lines is left empty. A one line note titled
+/// by its first sentence is kept whole, as the rest of the line would be lost.
+fn strip_title_line(content: &str, options: &ConversionOptions) -> String {
+    let content = content.trim_start();
+    let (_title_line, rest) = match content.split_once('\n') {
+        Some(parts) => parts,
+        None if options.title_sentence => return content.to_string(),
+        None => return String::new(),
+    };
+    match rest.trim().is_empty() {
+        true => String::new(),
+        false => rest.trim_start_matches('\n').to_string(),
+    }
+}
 
-    // ensure not longer than 200 chars
-    if 200 < line_trim.chars().count() {
-        line_trim[0..200].to_string()
-    } else {
-        line_trim.to_string()
+/// Run `content` through `transform_cmd`, if set. A failing command is an error under `strict`,
+/// otherwise a warning which keeps the content as it was.
+fn transform_content(
+    id: &str,
+    content: String,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<String, std::io::Error> {
+    let cmd = match &options.transform_cmd {
+        Some(cmd) if !options.raw_content => cmd,
+        _ => return Ok(content),
+    };
+    match run_transform_cmd(&content, cmd) {
+        Ok(transformed) => Ok(transformed),
+        Err(e) if options.strict => Err(e),
+        Err(e) => {
+            diagnostics::warning(
+                options,
+                clock,
+                None,
+                &format!("note: '{}' not transformed, {}", id, e),
+            );
+            Ok(content)
+        }
     }
 }
 
-fn convert_to_markdown(source: SimpleNote, trashed: bool) -> Markdown {
-    Markdown {
+fn convert_to_markdown(
+    source: SimpleNote,
+    trashed: bool,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<Markdown, std::io::Error> {
+    let (created, modified) = resolve_dates(&source, options, clock)?;
+    let content = transform_content(
+        &source.id,
+        convert_content(&source, options),
+        options,
+        clock,
+    )?;
+    let age = match options.emit_age {
+        true => age_between(&created.to_rfc3339(), clock.now()),
+        false => None,
+    };
+    let (created, modified) = (
+        format_timestamp(created, options),
+        format_timestamp(modified, options),
+    );
+    let id = source.source_id.unwrap_or(source.id);
+    let provenance = match options.provenance_comment {
+        true => Some(format!(
+            "converted by notes2md v{} from simplenote id={}",
+            env!("CARGO_PKG_VERSION"),
+            id
+        )),
+        false => None,
+    };
+    let aliases = match options.shorten_url_titles {
+        true => title_url(&source.content).map(|url| vec![url.to_string()]),
+        false => None,
+    };
+    Ok(Markdown {
         meta: MarkdownMeta {
-            title: title_from_content(&source.content),
-            created: source.creation_date,
-            modified: source.last_modified,
+            title: with_title_affixes(title_from_content(&source.content, options), options),
+            created,
+            modified,
             deleted: if trashed { Some(true) } else { None },
             favorited: None,
             pinned: source.pinned,
-            tags: source.tags,
+            tags: with_default_tag(
+                match options.tag_separator {
+                    Some(separator) => source.tags.map(|tags| split_tags(tags, separator)),
+                    None => source.tags,
+                }
+                .map(|tags| normalize_tag_case(tags, options.tag_case)),
+                options,
+            ),
+            type_: options.emit_type.clone(),
+            age,
+            share_url: source.share_url,
+            publish_url: source.publish_url,
+            aliases,
+            markdown: options.assume_markdown,
+            id: Some(id),
+            extra: serde_yaml::Mapping::new(),
         },
-        content: source.content.replace("\r\n", "\n"),
-    }
+        content,
+        provenance,
+        file_prefix: source.file_prefix,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::{FixedClock, SystemClock};
+    use crate::processor::markdown::write_markdown;
+    use crate::processor::sink::FileSink;
+    use crate::space::FreeSpace;
+    use proptest::prelude::*;
+
+    /// Note content likely to trip up titling: anything at all, control characters, multibyte
+    /// text around the length cap, and markdown punctuation.
+    fn awkward_content() -> impl Strategy<Value = String> {
+        prop_oneof![
+            any::<String>(),
+            "(\\PC|[\\x00-\\x1f]){0,400}",
+            "[é. !?]{190,420}",
+            "[#()!~>_*\\[\\]`. \n]{0,40}",
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn title_from_content_is_a_clean_line(content in awkward_content(), title_sentence in any::<bool>()) {
+            let options = ConversionOptions {
+                title_sentence,
+                ..Default::default()
+            };
+            let title = title_from_content(&content, &options);
+            prop_assert!(title.chars().count() <= 200);
+            prop_assert!(!title.contains('\n'));
+            prop_assert!(!title.starts_with(['.', ' ']));
+        }
+    }
 
     #[test]
     fn load_file_success_for_text() {
         let path = PathBuf::from("test_data/happy.txt");
-        let text = load_file(&path).unwrap();
+        let text = load_file(&path, None).unwrap();
         assert_eq!("this is a happy string", format!("{}", text));
     }
 
+    #[test]
+    fn load_file_decodes_latin1() {
+        let path = PathBuf::from("test_data/latin1.txt");
+        // strict UTF8 rejects it, but decoding from latin1 gives the accented characters
+        assert!(load_file(&path, None).is_err());
+        let text = load_file(&path, Some("latin1")).unwrap();
+        assert_eq!("café crème brûlée", text);
+    }
+
+    #[test]
+    fn load_file_fails_for_unknown_encoding() {
+        let path = PathBuf::from("test_data/latin1.txt");
+        let error = load_file(&path, Some("klingon")).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+        assert_eq!(
+            "source_encoding: 'klingon' is not a known encoding",
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn load_file_fails_for_malformed_encoded_text() {
+        let path = PathBuf::from("test_data/latin1.txt");
+        let error = load_file(&path, Some("utf-16le")).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(format!("{}", error).ends_with("contains data which is not UTF-16LE"));
+    }
+
     #[test]
     fn load_file_fails_for_non_text() {
         let path = PathBuf::from("test_data/not_text.bin");
-        let error = load_file(&path).unwrap_err();
+        let error = load_file(&path, None).unwrap_err();
         assert_eq!(ErrorKind::InvalidData, error.kind());
         assert_eq!(
             format!(
@@ -216,11 +1089,16 @@ mod tests {
         let single = SimpleNote {
             id: String::from(""),
             content: String::from("this is a note"),
-            creation_date: String::from("2022-01-13T22:36:18.906Z"),
-            last_modified: String::from("2022-01-14T07:36:50.656Z"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
             markdown: None,
             pinned: None,
             tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
         };
         let _expected = SimpleNotes {
             active_notes: Some(vec![single]),
@@ -245,11 +1123,16 @@ mod tests {
         let single = SimpleNote {
             id: String::from("someid"),
             content: String::from(""),
-            creation_date: String::from("2022-01-13T22:36:18.906Z"),
-            last_modified: String::from("2022-01-14T07:36:50.656Z"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
             markdown: None,
             pinned: None,
             tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
         };
         let _expected = SimpleNotes {
             active_notes: Some(vec![single]),
@@ -276,11 +1159,16 @@ mod tests {
         let single = SimpleNote {
             id: String::from("someid"),
             content: String::from("this is a note"),
-            creation_date: String::from("2022-01-13T22:36:18.906Z"),
-            last_modified: String::from("2022-01-14T07:36:50.656Z"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
             markdown: None,
             pinned: None,
             tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
         };
         let expected = SimpleNotes {
             active_notes: Some(vec![single]),
@@ -309,11 +1197,16 @@ mod tests {
         let single = SimpleNote {
             id: String::from("someid"),
             content: String::from("this is a note"),
-            creation_date: String::from("2022-01-13T22:36:18.906Z"),
-            last_modified: String::from("2022-01-14T07:36:50.656Z"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
             markdown: Some(true),
             pinned: Some(true),
             tags: Some(vec![String::from("Personal"), String::from("Business")]),
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
         };
         let expected = SimpleNotes {
             active_notes: Some(vec![single]),
@@ -328,7 +1221,7 @@ mod tests {
         let source = String::from("");
         let expected = String::from("");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
@@ -337,7 +1230,7 @@ mod tests {
         let source = String::from("This is a simple one liner");
         let expected = String::from("This is a simple one liner");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
@@ -346,7 +1239,7 @@ mod tests {
         let source = String::from("Mulitple lines\r\n can comprise\r\na note, too.");
         let expected = String::from("Mulitple lines");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
@@ -355,7 +1248,7 @@ mod tests {
         let source = String::from("\r\n\r\n   \r\n\r\nMulitple lines\r\n can be present with spaces in front for\r\nthe note, too.");
         let expected = String::from("Mulitple lines");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
@@ -364,17 +1257,39 @@ mod tests {
         let source = String::from("CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCXXXXXXXXXX");
         let expected = String::from("CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn title_from_content_max_length_200_multibyte() {
+        let source = "é".repeat(210);
+        let expected = "é".repeat(200);
+
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn title_from_content_max_title_len() {
+        let options = ConversionOptions {
+            max_title_len: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            "A long fir",
+            title_from_content("A long first line\nbody", &options)
+        );
+        assert_eq!("Short", title_from_content("Short\nbody", &options));
+    }
+
     #[test]
     fn title_from_content_strip_markdown() {
         let source =
             String::from("# ~ _ * ![`Test Code Markdown Document`](http://google.com) * _ ~ ");
         let expected = String::from("Test Code Markdown Document");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
@@ -383,61 +1298,218 @@ mod tests {
         let source = String::from("https://www.rust-lang.org/learn/get-started");
         let expected = String::from("https://www.rust-lang.org/learn/get-started");
 
-        let actual = title_from_content(&source);
+        let actual = title_from_content(&source, &ConversionOptions::default());
         assert_eq!(expected, actual);
     }
 
     #[test]
-    fn title_from_content_with_leading_dots() {
-        let source = String::from(". .. Some Title");
-        let expected = String::from("Some Title");
-
-        let actual = title_from_content(&source);
-        assert_eq!(expected, actual);
+    fn title_from_content_shortens_urls() {
+        let options = ConversionOptions {
+            shorten_url_titles: true,
+            ..Default::default()
+        };
+        let source = "https://www.example.com/blog/2022/01/some-long-article-title/?utm_source=feed#comments\n\nworth reading";
+        assert_eq!(
+            "www.example.com - some-long-article-title",
+            title_from_content(source, &options)
+        );
+        assert_eq!(
+            "example.com",
+            title_from_content("http://user@example.com:8080/", &options)
+        );
+        // a URL which isn't the whole line is left alone
+        assert_eq!(
+            "see https://example.com/a",
+            title_from_content("see https://example.com/a", &options)
+        );
     }
 
     #[test]
-    fn convert_active_simplenote_to_markdown_minimal_fields() {
+    fn convert_shortened_url_title_keeps_url_as_alias() {
+        let url = "https://www.example.com/blog/2022/01/some-long-article-title";
         let source = SimpleNote {
             id: String::from("someid"),
-            content: String::from("this is a note\nand stuff"),
-            creation_date: String::from("2022-01-13T22:36:18.906Z"),
-            last_modified: String::from("2022-01-14T07:36:50.656Z"),
+            content: format!("{}\nnotes", url),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
             markdown: None,
             pinned: None,
             tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
         };
-        let expected = Markdown {
-            meta: MarkdownMeta {
-                title: String::from("this is a note"),
-                created: String::from("2022-01-13T22:36:18.906Z"),
-                modified: String::from("2022-01-14T07:36:50.656Z"),
-                deleted: None,
-                favorited: None,
-                pinned: None,
-                tags: None,
-            },
-            content: String::from("this is a note\nand stuff"),
+        let options = ConversionOptions {
+            shorten_url_titles: true,
+            ..Default::default()
         };
+        let md = convert_to_markdown(source, false, &options, &SystemClock).unwrap();
+        assert_eq!("www.example.com - some-long-article-title", md.meta.title);
+        assert_eq!(Some(vec![String::from(url)]), md.meta.aliases);
 
-        let actual: Markdown = convert_to_markdown(source, false);
-        println!("{}", expected);
-        println!("{}", actual);
-        assert_eq!(expected, actual);
+        let dest_dir = tempfile::tempdir().unwrap();
+        let file_path = write_markdown(md, dest_dir.path(), &options).unwrap();
+        assert_eq!(
+            dest_dir
+                .path()
+                .join("www.example.com - some-long-article-title.md"),
+            file_path
+        );
+        let host_only = Markdown::new(&title_from_content("https://example.com/", &options), "");
+        let file_path = write_markdown(host_only, dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("example.com.md"), file_path);
     }
 
     #[test]
-    fn simplenote_converted_and_written_to_expected_file() {
-        // this demonstrates how a fully populated Simplenote will render into a Markdown file
-        let expected: String = String::from_utf8_lossy(
-            &fs::read("test_data/expected_2-simplenote-single.md").unwrap(),
-        )
-        .parse()
-        .unwrap();
+    fn title_from_content_with_custom_strip_chars() {
+        let source = "**user_name** @home #1";
+        assert_eq!(
+            "username @home 1",
+            title_from_content(source, &ConversionOptions::default())
+        );
+        // keeping underscores
+        let options = ConversionOptions {
+            title_strip_chars: Some(TitleStripChars::new(r#"'"`#()!~>\[\]\*"#).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!("user_name @home 1", title_from_content(source, &options));
+        // also stripping @
+        let options = ConversionOptions {
+            title_strip_chars: Some(TitleStripChars::new(r#"'"`#()!~>_\[\]\*@"#).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!("username home 1", title_from_content(source, &options));
+    }
 
-        let dest_dir = PathBuf::from("test_data/out");
+    #[test]
+    fn title_strip_chars_rejects_invalid_sets() {
+        assert!(TitleStripChars::new(r"\[\]a-z").is_ok());
+        assert!(TitleStripChars::new("[:alpha:]").is_ok());
+        assert_eq!(
+            TitleStripChars::new("#@").unwrap(),
+            TitleStripChars::new("#@").unwrap()
+        );
+        for set in ["", "a]b", "[a", "z-a"] {
+            let error = TitleStripChars::new(set).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInput, error.kind(), "{}", set);
+        }
+    }
+
+    #[test]
+    fn title_from_content_with_leading_dots() {
+        let source = String::from(". .. Some Title");
+        let expected = String::from("Some Title");
+
+        let actual = title_from_content(&source, &ConversionOptions::default());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn title_from_content_first_sentence_of_single_line() {
+        let options = ConversionOptions {
+            title_sentence: true,
+            ..Default::default()
+        };
+        let source = format!(
+            "Meeting notes from www.example.com today. {}",
+            "Lots more words follow. ".repeat(20)
+        );
+        let expected = String::from("Meeting notes from www.example.com today");
+        assert_eq!(expected, title_from_content(&source, &options));
+
+        let question = String::from("Did it work? Yes it did!");
+        assert_eq!("Did it work", title_from_content(&question, &options));
+    }
+
+    #[test]
+    fn title_from_content_first_sentence_falls_back_to_cap() {
+        let options = ConversionOptions {
+            title_sentence: true,
+            ..Default::default()
+        };
+        let source = "Words without end ".repeat(20);
+        let actual = title_from_content(&source, &options);
+        assert_eq!(truncate_chars(&source, 200), actual);
+    }
+
+    #[test]
+    fn title_from_content_first_sentence_ignores_multiline() {
+        let options = ConversionOptions {
+            title_sentence: true,
+            ..Default::default()
+        };
+        let source = String::from("First line. Still first line\nsecond line");
+        let expected = String::from("First line. Still first line");
+        assert_eq!(expected, title_from_content(&source, &options));
+    }
+
+    #[test]
+    fn convert_active_simplenote_to_markdown_minimal_fields() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("this is a note\nand stuff"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let expected = Markdown {
+            meta: MarkdownMeta {
+                title: String::from("this is a note"),
+                created: String::from("2022-01-13T22:36:18.906Z"),
+                modified: String::from("2022-01-14T07:36:50.656Z"),
+                deleted: None,
+                favorited: None,
+                pinned: None,
+                tags: None,
+                type_: None,
+                age: None,
+                share_url: None,
+                publish_url: None,
+                aliases: None,
+                markdown: None,
+                id: Some(String::from("someid")),
+                extra: serde_yaml::Mapping::new(),
+            },
+            content: String::from("this is a note\nand stuff"),
+            provenance: None,
+            file_prefix: None,
+        };
+
+        let actual: Markdown =
+            convert_to_markdown(source, false, &ConversionOptions::default(), &SystemClock)
+                .unwrap();
+        println!("{}", expected);
+        println!("{}", actual);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn simplenote_converted_and_written_to_expected_file() {
+        // this demonstrates how a fully populated Simplenote will render into a Markdown file
+        let expected: String = String::from_utf8_lossy(
+            &fs::read("test_data/expected_2-simplenote-single.md").unwrap(),
+        )
+        .parse()
+        .unwrap();
+
+        let dest_dir = PathBuf::from("test_data/out");
         let source_file = PathBuf::from("test_data/simplenote-single.json");
-        process(source_file, dest_dir).unwrap();
+        process(
+            source_file,
+            dest_dir,
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
 
         let actual: String =
             String::from_utf8_lossy(&fs::read("test_data/out/Sample Document.md").unwrap())
@@ -455,6 +1527,7 @@ mod tests {
 title: process-active
 created: "2022-01-13T22:36:18.906Z"
 modified: "2022-01-14T07:36:50.656Z"
+id: abc123
 ---
 process-active
 "#;
@@ -463,13 +1536,1446 @@ title: process-trashed
 created: "2022-01-10T12:16:17.906Z"
 modified: "2022-01-11T03:34:55.656Z"
 deleted: true
+id: def456
 ---
 process-trashed
 "#;
-        let _r = process(PathBuf::from("test_data/process-active-trashed.json"), PathBuf::from("test_data/out"));
+        let _r = process(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            PathBuf::from("test_data/out"),
+            &ConversionOptions::default(),
+            &SystemClock,
+        );
         let actual_active = fs::read_to_string("test_data/out/process-active.md").unwrap();
         let actual_trashed = fs::read_to_string("test_data/out/process-trashed.md").unwrap();
         assert_eq!(expected_active, actual_active);
         assert_eq!(expected_trashed, actual_trashed);
     }
+
+    #[test]
+    fn strip_title_line_leaves_title_only_note_empty() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            strip_title_line: true,
+            ..Default::default()
+        };
+        let note = |content: &str| SimpleNote {
+            id: String::from("someid"),
+            content: String::from(content),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+
+        let md =
+            convert_to_markdown(note("My Title\n\n\n"), false, &options, &SystemClock).unwrap();
+        let file_path = write_markdown(md, dest_dir.path(), &options).unwrap();
+        assert_eq!(
+            "---\ntitle: My Title\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-14T07:36:50.656Z\"\nid: someid\n---\n",
+            fs::read_to_string(file_path).unwrap()
+        );
+
+        let md = convert_to_markdown(note("\nMy Title\n\nbody\n"), false, &options, &SystemClock)
+            .unwrap();
+        assert_eq!("body\n", md.content);
+    }
+
+    #[test]
+    fn title_prefix_and_suffix_mark_title_and_file_name() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            title_prefix: Some(String::from("[SN] ")),
+            title_suffix: Some(String::from(" (imported)")),
+            ..Default::default()
+        };
+        let note = |content: &str| SimpleNote {
+            id: String::from("someid"),
+            content: String::from(content),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+
+        let md = convert_to_markdown(note("Original Title\nbody"), false, &options, &SystemClock)
+            .unwrap();
+        assert_eq!("[SN] Original Title (imported)", md.meta.title);
+        let file_path = write_markdown(md, dest_dir.path(), &options).unwrap();
+        assert_eq!(
+            dest_dir.path().join("[SN] Original Title (imported).md"),
+            file_path
+        );
+
+        // the file name is still made safe once the marker is added
+        let options = ConversionOptions {
+            title_prefix: Some(String::from("SN: ")),
+            ..Default::default()
+        };
+        let md = convert_to_markdown(note("Why?\nbody"), false, &options, &SystemClock).unwrap();
+        assert_eq!("SN: Why?", md.meta.title);
+        let file_path = write_markdown(md, dest_dir.path(), &options).unwrap();
+        assert_eq!(dest_dir.path().join("SN_ Why_.md"), file_path);
+
+        // a note with no title isn't named after the marker alone
+        let md = convert_to_markdown(note("   \n"), false, &options, &SystemClock).unwrap();
+        assert_eq!("", md.meta.title);
+    }
+
+    #[test]
+    fn process_marks_trashed_notes_deleted() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(2, stats.converted);
+
+        let front_matter = |name: &str| {
+            let text = fs::read_to_string(dest_dir.path().join(name)).unwrap();
+            text.split("\n---\n").next().unwrap().to_string()
+        };
+        assert!(front_matter("process-trashed.md").contains("\ndeleted: true"));
+        assert!(!front_matter("process-active.md").contains("deleted:"));
+    }
+
+    #[test]
+    fn convert_plaintext_simplenote_escapes_when_requested() {
+        let note = |markdown| SimpleNote {
+            id: String::from("someid"),
+            content: String::from("* not a list\r\n# not a heading"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            escape_plaintext: true,
+            ..Default::default()
+        };
+
+        let plain = convert_to_markdown(note(Some(false)), false, &options, &SystemClock).unwrap();
+        assert_eq!("\\* not a list\n\\# not a heading", plain.content);
+
+        // notes flagged as markdown, or without the flag, are left alone
+        let markdown =
+            convert_to_markdown(note(Some(true)), false, &options, &SystemClock).unwrap();
+        assert_eq!("* not a list\n# not a heading", markdown.content);
+        let unflagged = convert_to_markdown(note(None), false, &options, &SystemClock).unwrap();
+        assert_eq!("* not a list\n# not a heading", unflagged.content);
+
+        // and nothing is escaped unless asked
+        let default = convert_to_markdown(
+            note(Some(false)),
+            false,
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!("* not a list\n# not a heading", default.content);
+    }
+
+    #[test]
+    fn process_with_verify_output() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            verify_output: true,
+            ..Default::default()
+        };
+        process(
+            PathBuf::from("test_data/simplenote-single.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+        assert!(dest_dir.path().join("Sample Document.md").exists());
+    }
+
+    #[test]
+    fn convert_simplenote_with_inline_links() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("Links\r\nRead [this][1].\r\n\r\n[1]: https://example.com\r\n"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: Some(true),
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            inline_links: true,
+            ..Default::default()
+        };
+        let actual = convert_to_markdown(source, false, &options, &SystemClock).unwrap();
+        assert_eq!("Links\nRead [this](https://example.com).\n", actual.content);
+    }
+
+    #[test]
+    fn convert_simplenote_with_assumed_markdown() {
+        let note = |markdown| SimpleNote {
+            id: String::from("someid"),
+            content: String::from("Some *stars*"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = |assume_markdown| ConversionOptions {
+            escape_plaintext: true,
+            assume_markdown,
+            ..Default::default()
+        };
+
+        let actual =
+            convert_to_markdown(note(Some(false)), false, &options(Some(true)), &SystemClock)
+                .unwrap();
+        assert_eq!(Some(true), actual.meta.markdown);
+        assert_eq!("Some *stars*", actual.content);
+
+        let actual =
+            convert_to_markdown(note(Some(true)), false, &options(Some(false)), &SystemClock)
+                .unwrap();
+        assert_eq!(Some(false), actual.meta.markdown);
+        assert_eq!("Some \\*stars\\*", actual.content);
+
+        // the source flag is left out of the front matter unless forced
+        let actual =
+            convert_to_markdown(note(Some(false)), false, &options(None), &SystemClock).unwrap();
+        assert_eq!(None, actual.meta.markdown);
+        assert_eq!("Some \\*stars\\*", actual.content);
+    }
+
+    #[test]
+    fn convert_simplenote_with_tag_separator() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("tagged"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: Some(vec![String::from("a,b,c"), String::from("d")]),
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            tag_separator: Some(','),
+            ..Default::default()
+        };
+        let actual = convert_to_markdown(source, false, &options, &SystemClock).unwrap();
+        assert_eq!(
+            Some(vec![
+                String::from("a"),
+                String::from("b"),
+                String::from("c"),
+                String::from("d")
+            ]),
+            actual.meta.tags
+        );
+    }
+
+    #[test]
+    fn convert_simplenote_with_content_max_length() {
+        let note = |content: &str| SimpleNote {
+            id: String::from("someid"),
+            content: String::from(content),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            content_max_length: Some(10),
+            ..Default::default()
+        };
+
+        let short = convert_to_markdown(note("Short note"), false, &options, &SystemClock).unwrap();
+        assert_eq!("Short note", short.content);
+
+        let long =
+            convert_to_markdown(note("A much longer note"), false, &options, &SystemClock).unwrap();
+        assert_eq!("A much lon…", long.content);
+        // the title comes from the untruncated content
+        assert_eq!("A much longer note", long.meta.title);
+    }
+
+    #[test]
+    fn convert_simplenote_with_emit_type() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("typed"),
+            creation_date: Some(String::from("2022-01-13T22:36:18.906Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            emit_type: Some(String::from("note")),
+            ..Default::default()
+        };
+        let actual = convert_to_markdown(source, false, &options, &SystemClock).unwrap();
+        assert_eq!(Some(String::from("note")), actual.meta.type_);
+        assert!(format!("{}", actual).contains("\ntype: note\n"));
+    }
+
+    #[test]
+    fn process_with_fixed_clock_emits_deterministic_age() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            emit_age: true,
+            ..Default::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        process(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &FixedClock(now),
+        )
+        .unwrap();
+        let actual = fs::read_to_string(dest_dir.path().join("process-active.md")).unwrap();
+        assert!(actual.contains("\nage: 2y 3mo\n"));
+    }
+
+    #[test]
+    fn title_collisions_groups_ids_by_title() {
+        let source = r##"
+            {
+                "activeNotes": [
+                    {
+                        "id": "one",
+                        "content": "Shopping\nmilk",
+                        "creationDate": "2022-01-13T22:36:18.906Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    },
+                    {
+                        "id": "two",
+                        "content": "Something else",
+                        "creationDate": "2022-01-13T22:36:18.906Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    }
+                ],
+                "trashedNotes": [
+                    {
+                        "id": "three",
+                        "content": "# Shopping\neggs",
+                        "creationDate": "2022-01-13T22:36:18.906Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    }
+                ]
+            }"##;
+        let notes = deserialize_notes(String::from(source)).unwrap();
+        let expected = vec![(
+            String::from("Shopping"),
+            vec![String::from("one"), String::from("three")],
+        )];
+        assert_eq!(
+            expected,
+            title_collisions(&notes, &ConversionOptions::default())
+        );
+    }
+
+    #[test]
+    fn process_reporting_title_collisions_writes_nothing() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            report_title_collisions: true,
+            ..Default::default()
+        };
+        process(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(0, fs::read_dir(dest_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn process_with_raw_content_preserves_bytes() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            raw_content: true,
+            content_max_length: Some(5),
+            ..Default::default()
+        };
+        process(
+            PathBuf::from("test_data/simplenote-single.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+        let actual = fs::read(dest_dir.path().join("Sample Document.md")).unwrap();
+        let expected_body =
+            "# Sample Document\r\n\r\n## This is a sample \r\n\r\n* list\r\n* item\r\n* \r\n";
+        assert!(String::from_utf8(actual)
+            .unwrap()
+            .ends_with(&format!("---\n{}", expected_body)));
+    }
+
+    #[test]
+    fn convert_simplenote_normalizes_dates() {
+        let note = |created: &str, modified: &str| SimpleNote {
+            id: String::from("someid"),
+            content: String::from("a note"),
+            creation_date: Some(String::from(created)),
+            last_modified: Some(String::from(modified)),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let source = || note("2022-01-13T23:36:18.906+01:00", "2022-01-14 07:36:50");
+        let md = convert_to_markdown(source(), false, &ConversionOptions::default(), &SystemClock)
+            .unwrap();
+        assert_eq!("2022-01-13T22:36:18.906Z", md.meta.created);
+        assert_eq!("2022-01-14T07:36:50.000Z", md.meta.modified);
+
+        let options = ConversionOptions {
+            date_format: Some(String::from("%Y-%m-%d")),
+            ..Default::default()
+        };
+        let md = convert_to_markdown(source(), false, &options, &SystemClock).unwrap();
+        assert_eq!("2022-01-13", md.meta.created);
+        assert_eq!("2022-01-14", md.meta.modified);
+
+        let bad = note("yesterday", "2022-01-14T07:36:50.656Z");
+        let error = convert_to_markdown(bad, false, &options, &SystemClock).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(error
+            .to_string()
+            .starts_with("note: 'someid' has an invalid creationDate 'yesterday', "));
+    }
+
+    #[test]
+    fn convert_simplenote_with_missing_dates() {
+        let source = r#"
+            {
+                "activeNotes": [
+                    {
+                        "id": "no-created",
+                        "content": "missing creationDate",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    },
+                    {
+                        "id": "no-dates",
+                        "content": "missing both dates"
+                    }
+                ]
+            }"#;
+        let mut notes = deserialize_notes(String::from(source))
+            .unwrap()
+            .active_notes
+            .unwrap();
+        let now = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = FixedClock(now);
+        let options = ConversionOptions::default();
+
+        let no_dates = convert_to_markdown(notes.pop().unwrap(), false, &options, &clock).unwrap();
+        assert_eq!("2024-05-01T00:00:00.000Z", no_dates.meta.created);
+        assert_eq!("2024-05-01T00:00:00.000Z", no_dates.meta.modified);
+
+        let no_created =
+            convert_to_markdown(notes.pop().unwrap(), false, &options, &clock).unwrap();
+        assert_eq!("2022-01-14T07:36:50.656Z", no_created.meta.created);
+        assert_eq!("2022-01-14T07:36:50.656Z", no_created.meta.modified);
+    }
+
+    #[test]
+    fn convert_simplenote_with_missing_date_fails_when_strict() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("no created date"),
+            creation_date: None,
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = convert_to_markdown(source, false, &options, &SystemClock).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "note: 'someid' is missing its creation or modification date",
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn convert_simplenote_with_publish_url() {
+        let source = r#"
+            {
+                "activeNotes": [
+                    {
+                        "id": "published",
+                        "content": "A published note",
+                        "creationDate": "2022-01-14T07:36:50.656Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z",
+                        "publishURL": "https://app.simplenote.com/p/abc123"
+                    }
+                ]
+            }"#;
+        let note = deserialize_notes(String::from(source))
+            .unwrap()
+            .active_notes
+            .unwrap()
+            .pop()
+            .unwrap();
+        let options = ConversionOptions::default();
+        let actual = convert_to_markdown(note, false, &options, &SystemClock).unwrap();
+        assert_eq!(
+            Some(String::from("https://app.simplenote.com/p/abc123")),
+            actual.meta.publish_url
+        );
+        assert_eq!(None, actual.meta.share_url);
+        assert!(format!("{}", actual)
+            .contains("publish_url: \"https://app.simplenote.com/p/abc123\"\n"));
+    }
+
+    #[test]
+    fn process_reuses_cached_notes_until_modified() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let export = |content: &str, modified: &str| {
+            let notes = format!(
+                r#"{{"activeNotes": [{{"id": "abc123", "content": "{}",
+                    "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "{}"}}]}}"#,
+                content, modified
+            );
+            fs::write(&source_file, notes).unwrap();
+        };
+        let options = ConversionOptions {
+            transform_cmd: Some(String::from("tr a-z A-Z")),
+            cache_dir: Some(source_dir.path().join("cache")),
+            ..Default::default()
+        };
+        let convert = || {
+            let dest_dir = tempfile::tempdir().unwrap();
+            process(
+                source_file.clone(),
+                dest_dir.path().to_path_buf(),
+                &options,
+                &SystemClock,
+            )
+            .unwrap();
+            fs::read_to_string(dest_dir.path().join("first.md")).unwrap()
+        };
+
+        export("first\\nversion", "2022-01-14T07:36:50.656Z");
+        assert!(convert().ends_with("---\nFIRST\nVERSION\n"));
+
+        // the note is taken from the cache, so an edit which kept the date isn't seen
+        export("first\\nedited", "2022-01-14T07:36:50.656Z");
+        assert!(convert().ends_with("---\nFIRST\nVERSION\n"));
+
+        // a new modified date converts the note again
+        export("first\\nedited", "2022-02-01T09:00:00.000Z");
+        assert!(convert().ends_with("---\nFIRST\nEDITED\n"));
+    }
+
+    #[test]
+    fn convert_simplenote_with_transform_cmd() {
+        let note = || SimpleNote {
+            id: String::from("someid"),
+            content: String::from("Shout this\nand this"),
+            creation_date: Some(String::from("2022-01-14T07:36:50.656Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            transform_cmd: Some(String::from("tr a-z A-Z")),
+            ..Default::default()
+        };
+        let actual = convert_to_markdown(note(), false, &options, &SystemClock).unwrap();
+        assert_eq!("SHOUT THIS\nAND THIS", actual.content);
+
+        let options = ConversionOptions {
+            transform_cmd: Some(String::from("exit 3")),
+            ..Default::default()
+        };
+        let actual = convert_to_markdown(note(), false, &options, &SystemClock).unwrap();
+        assert_eq!("Shout this\nand this", actual.content);
+
+        let options = ConversionOptions {
+            transform_cmd: Some(String::from("exit 3")),
+            strict: true,
+            ..Default::default()
+        };
+        let error = convert_to_markdown(note(), false, &options, &SystemClock).unwrap_err();
+        assert_eq!(
+            "transform-cmd: 'exit 3' failed, exit status: 3",
+            format!("{}", error)
+        );
+    }
+
+    #[test]
+    fn convert_simplenote_with_provenance_comment() {
+        let source = SimpleNote {
+            id: String::from("someid"),
+            content: String::from("A note"),
+            creation_date: Some(String::from("2022-01-14T07:36:50.656Z")),
+            last_modified: Some(String::from("2022-01-14T07:36:50.656Z")),
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: None,
+            file_prefix: None,
+            source_id: None,
+        };
+        let options = ConversionOptions {
+            provenance_comment: true,
+            ..Default::default()
+        };
+        let actual = format!(
+            "{}",
+            convert_to_markdown(source, false, &options, &SystemClock).unwrap()
+        );
+        let expected_start = format!(
+            "---\n# converted by notes2md v{} from simplenote id=someid\ntitle: A note\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(actual.starts_with(&expected_start));
+    }
+
+    #[test]
+    fn process_simplenote_with_content_files() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(source_dir.path().join("bodies")).unwrap();
+        fs::write(
+            source_dir.path().join("bodies/external.md"),
+            "External body\nkept in its own file",
+        )
+        .unwrap();
+        fs::write(source_dir.path().join("secret.md"), "Not for export").unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"
+            {
+                "activeNotes": [
+                    {
+                        "id": "external",
+                        "content": "",
+                        "contentFile": "bodies/external.md",
+                        "creationDate": "2022-01-14T07:36:50.656Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    },
+                    {
+                        "id": "traversal",
+                        "content": "",
+                        "contentFile": "bodies/../../secret.md",
+                        "creationDate": "2022-01-14T07:36:50.656Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let options = ConversionOptions::default();
+        process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        let written = fs::read_to_string(dest_dir.path().join("External body.md")).unwrap();
+        assert!(written.ends_with("---\nExternal body\nkept in its own file\n"));
+        assert_eq!(1, fs::read_dir(dest_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn load_content_file_rejects_paths_outside_source_dir() {
+        let note = |content_file: &str| SimpleNote {
+            id: String::from("someid"),
+            content: String::new(),
+            creation_date: None,
+            last_modified: None,
+            markdown: None,
+            pinned: None,
+            tags: None,
+            share_url: None,
+            publish_url: None,
+            content_file: Some(String::from(content_file)),
+            file_prefix: None,
+            source_id: None,
+        };
+        for path in ["../secret.md", "bodies/../../secret.md", "/etc/passwd"] {
+            let error = load_content_file(&mut note(path), Path::new("test_data")).unwrap_err();
+            assert_eq!(
+                format!(
+                    "note: 'someid' content_file: '{}' is not within the source directory",
+                    path
+                ),
+                format!("{}", error)
+            );
+        }
+    }
+
+    #[test]
+    fn process_simplenote_with_split_on_headings() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r###"
+            {
+                "activeNotes": [
+                    {
+                        "id": "sections",
+                        "content": "## Groceries\nmilk\n## Errands\npost office\n## Calls\ndentist",
+                        "creationDate": "2022-01-14T07:36:50.656Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z",
+                        "tags": ["todo"]
+                    }
+                ]
+            }"###,
+        )
+        .unwrap();
+        let options = ConversionOptions {
+            split_on_headings: Some(2),
+            ..Default::default()
+        };
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(3, stats.converted);
+        for (title, body) in [
+            ("Groceries", "milk"),
+            ("Errands", "post office"),
+            ("Calls", "dentist"),
+        ] {
+            let written =
+                fs::read_to_string(dest_dir.path().join(format!("{}.md", title))).unwrap();
+            assert!(written.contains("tags:\n  - todo\n"));
+            assert!(written.ends_with(&format!("---\n{}\n", body)));
+        }
+    }
+
+    #[test]
+    fn process_simplenote_updates_in_place_from_manifest() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let note = |id: &str, content: &str| {
+            format!(
+                r#"{{"id": "{}", "content": "{}", "creationDate": "2022-01-14T07:36:50.656Z", "lastModified": "2022-01-14T07:36:50.656Z"}}"#,
+                id, content
+            )
+        };
+        let options = ConversionOptions {
+            update_from_manifest: Some(source_dir.path().join("manifest.json")),
+            ..Default::default()
+        };
+
+        fs::write(
+            &source_file,
+            format!(r#"{{"activeNotes": [{}]}}"#, note("one", "Shopping\\nmilk")),
+        )
+        .unwrap();
+        process(
+            source_file.clone(),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        // the second export changes the first note and adds another
+        fs::write(
+            &source_file,
+            format!(
+                r#"{{"activeNotes": [{}, {}]}}"#,
+                note("one", "Shopping\\nmilk and eggs"),
+                note("two", "Shopping\\nfor a new note")
+            ),
+        )
+        .unwrap();
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        // the changed note replaces its own file, so only the new one is renamed
+        assert_eq!((2, 1), (stats.converted, stats.collisions));
+        let updated = fs::read_to_string(dest_dir.path().join("Shopping.md")).unwrap();
+        assert!(updated.ends_with("---\nShopping\nmilk and eggs\n"));
+        let created = fs::read_to_string(dest_dir.path().join("Shopping (1).md")).unwrap();
+        assert!(created.ends_with("---\nShopping\nfor a new note\n"));
+        assert_eq!(2, fs::read_dir(dest_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn process_simplenote_prefers_active_note_for_shared_id() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let manifest_file = source_dir.path().join("manifest.json");
+        let error_log = source_dir.path().join("errors.log");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Active\nnote", "creationDate": "2022-01-14T07:36:50.656Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ], "trashedNotes": [
+                {"id": "one", "content": "Trashed\nnote", "creationDate": "2022-01-14T07:36:50.656Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let options = ConversionOptions {
+            update_from_manifest: Some(manifest_file.clone()),
+            error_log: Some(error_log.clone()),
+            ..Default::default()
+        };
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(2, stats.converted);
+        let manifest = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(
+            Some(dest_dir.path().join("Active.md").as_path()),
+            manifest.existing_path("one")
+        );
+        assert_eq!(
+            Some(dest_dir.path().join("Trashed.md").as_path()),
+            manifest.existing_path("one (trashed)")
+        );
+        let logged = fs::read_to_string(&error_log).unwrap();
+        assert!(logged.contains(
+            "WARNING: note: 'one' is both active and trashed, the trashed note is recorded as 'one (trashed)'"
+        ));
+    }
+
+    #[test]
+    fn process_simplenote_keeps_source_id_of_trashed_note_sharing_id() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let manifest_file = source_dir.path().join("manifest.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "a1", "content": "Active\nnote", "creationDate": "2022-01-14T07:36:50.656Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ], "trashedNotes": [
+                {"id": "a1", "content": "Trashed\nnote", "creationDate": "2022-01-14T07:36:50.656Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let options = ConversionOptions {
+            update_from_manifest: Some(manifest_file.clone()),
+            provenance_comment: true,
+            ..Default::default()
+        };
+        process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        let trashed = fs::read_to_string(dest_dir.path().join("Trashed.md")).unwrap();
+        assert!(trashed.contains("\nid: a1\n"));
+        assert!(trashed.contains("from simplenote id=a1\n"));
+        assert!(!trashed.contains("(trashed)"));
+        let manifest = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(
+            Some(dest_dir.path().join("Trashed.md").as_path()),
+            manifest.existing_path("a1 (trashed)")
+        );
+    }
+
+    #[test]
+    fn process_simplenote_validate_dates_lists_bad_dates() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"
+            {
+                "activeNotes": [
+                    {
+                        "id": "good",
+                        "content": "Fine",
+                        "creationDate": "2022-01-14T07:36:50.656Z",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    },
+                    {
+                        "id": "bad",
+                        "content": "Not fine",
+                        "creationDate": "14/01/2022",
+                        "lastModified": "2022-01-14T07:36:50.656Z"
+                    }
+                ],
+                "trashedNotes": [
+                    {
+                        "id": "worse",
+                        "content": "Gone",
+                        "creationDate": "yesterday",
+                        "lastModified": "today"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let options = ConversionOptions {
+            validate_dates: true,
+            ..Default::default()
+        };
+
+        let error = process(
+            source_file.clone(),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            format!(
+                "source_file: '{}' has notes with invalid dates:\n    bad: creationDate '14/01/2022'\n    worse: creationDate 'yesterday'\n    worse: lastModified 'today'",
+                source_file.to_string_lossy()
+            ),
+            format!("{}", error)
+        );
+        // nothing is written when the pre-flight check fails
+        assert_eq!(0, fs::read_dir(dest_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn process_simplenote_with_filter() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            filter: Some(crate::filter::FilterExpr::parse("trashed").unwrap()),
+            ..Default::default()
+        };
+        let stats = process(
+            PathBuf::from("test_data/process-active-trashed.json"),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(1, stats.converted);
+        assert_eq!(1, stats.skipped);
+        assert!(dest_dir.path().join("process-trashed.md").exists());
+        assert!(!dest_dir.path().join("process-active.md").exists());
+    }
+
+    #[test]
+    fn process_simplenote_counts_tags() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let note = |id: &str, tags: &str| {
+            format!(
+                r#"{{"id": "{}", "content": "{}", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z", "tags": [{}]}}"#,
+                id, id, tags
+            )
+        };
+        let notes = format!(
+            r#"{{"activeNotes": [{}, {}], "trashedNotes": [{}]}}"#,
+            note("one", r#""work", "ideas""#),
+            note("two", r#""work""#),
+            note("three", r#""ideas", "work""#)
+        );
+        fs::write(&source_file, notes).unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(vec![("work", 3), ("ideas", 2)], stats.tag_frequencies());
+    }
+
+    #[test]
+    fn process_simplenote_recent_notes_only() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let note = |id: &str, modified: &str| {
+            format!(
+                r#"{{"id": "{}", "content": "{}", "creationDate": "2020-01-01T00:00:00.000Z", "lastModified": "{}"}}"#,
+                id, id, modified
+            )
+        };
+        let notes = format!(
+            r#"{{"activeNotes": [{}, {}, {}, {}]}}"#,
+            note("today", "2024-05-10T08:00:00.000Z"),
+            note("last week", "2024-05-04T08:00:00.000Z"),
+            note("last year", "2023-05-10T08:00:00.000Z"),
+            note("garbled", "not a date")
+        );
+        fs::write(&source_file, notes).unwrap();
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let options = ConversionOptions {
+            recent: Some(7),
+            ..Default::default()
+        };
+        let dest_dir = tempfile::tempdir().unwrap();
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &FixedClock(now),
+        )
+        .unwrap();
+        // the garbled date is kept by the filter, but can't be written
+        assert_eq!((2, 1, 1), (stats.converted, stats.skipped, stats.failed));
+        for title in ["today", "last week"] {
+            assert!(dest_dir.path().join(format!("{}.md", title)).exists());
+        }
+        assert!(!dest_dir.path().join("last year.md").exists());
+        assert_eq!(
+            "note: 'garbled' has an invalid lastModified 'not a date', premature end of input",
+            stats.failures[0].message
+        );
+    }
+
+    #[test]
+    fn process_simplenote_summarizes_failures() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let notes = r#"{"activeNotes": [
+            {"id": "dots", "content": "...", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+            {"id": "fine", "content": "Fine", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+            {"id": "lost", "content": "", "contentFile": "missing.md", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+        ]}"#;
+        fs::write(&source_file, notes).unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            summary_failures: true,
+            ..Default::default()
+        };
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(1, stats.converted);
+        assert_eq!(2, stats.failed);
+        let summary = stats.failure_summary();
+        assert!(summary.starts_with("2 notes failed:\n"));
+        assert!(summary.contains("entity not found (1):\n    lost: "));
+        assert!(summary.contains("invalid data (1):\n    dots: title: '' is not valid"));
+    }
+
+    #[test]
+    fn render_simplenote_in_memory() {
+        let notes = r#"{"activeNotes": [
+            {"id": "one", "content": "Shopping\nmilk", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+            {"id": "two", "content": "Shopping\neggs", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+        ]}"#;
+        let rendered = render(notes, &ConversionOptions::default(), &SystemClock).unwrap();
+        let paths: Vec<&Path> = rendered.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(
+            vec![Path::new("Shopping.md"), Path::new("Shopping (1).md")],
+            paths
+        );
+        assert!(rendered[0].1.starts_with("---\ntitle: Shopping\n"));
+        assert!(rendered[1].1.ends_with("---\nShopping\neggs\n"));
+    }
+
+    /// Keeps notes in memory, as a library user's own sink might.
+    struct VecSink(Vec<Markdown>);
+
+    impl NoteSink for VecSink {
+        fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError> {
+            self.0.push(md.clone());
+            Ok(vec![PathBuf::from(&md.meta.title)])
+        }
+    }
+
+    #[test]
+    fn process_into_hands_notes_to_sink() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Shopping\nmilk", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Garden\nweeds", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut sink = VecSink(Vec::new());
+        let stats = process_into(
+            source_file,
+            &mut sink,
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(2, stats.converted);
+        assert_eq!(
+            vec![PathBuf::from("Shopping"), PathBuf::from("Garden")],
+            stats.written
+        );
+        let titles: Vec<&str> = sink.0.iter().map(|md| md.meta.title.as_str()).collect();
+        assert_eq!(vec!["Shopping", "Garden"], titles);
+        assert_eq!("Garden\nweeds", sink.0[1].content);
+    }
+
+    #[test]
+    fn process_records_every_tag_copy() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Plans\nbody", "tags": ["work", "ideas"], "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Loose\nbody", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            duplicate_into_tag_dirs: true,
+            verify_output: true,
+            ..Default::default()
+        };
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        let expected: Vec<PathBuf> = ["work/Plans.md", "ideas/Plans.md", "untagged/Loose.md"]
+            .into_iter()
+            .map(|file| dest_dir.path().join(file))
+            .collect();
+        assert_eq!(expected, stats.written);
+        assert_eq!(3, stats.converted);
+    }
+
+    /// Free space which drops by a fixed amount each time it is looked at, as if every note
+    /// written took that much.
+    struct ShrinkingSpace {
+        free: std::cell::Cell<u64>,
+        per_note: u64,
+    }
+
+    impl FreeSpace for ShrinkingSpace {
+        fn available(&self, _dir: &Path) -> Result<u64, std::io::Error> {
+            let free = self.free.get();
+            self.free.set(free.saturating_sub(self.per_note));
+            Ok(free)
+        }
+    }
+
+    #[test]
+    fn process_stops_before_breaching_reserve_space() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Shopping\nmilk", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Garden\nweeds", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            reserve_space: Some(1000),
+            ..Default::default()
+        };
+        let space = ShrinkingSpace {
+            free: std::cell::Cell::new(1100),
+            per_note: 100,
+        };
+        let mut sink = FileSink::with_free_space(
+            dest_dir.path().to_path_buf(),
+            &options,
+            &space,
+            &SystemClock,
+        );
+
+        let error = process_into(source_file, &mut sink, &options, &SystemClock).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("less than the 1000 bytes reserved"));
+        assert!(dest_dir.path().join("Shopping.md").exists());
+        assert!(!dest_dir.path().join("Garden.md").exists());
+    }
+
+    #[test]
+    fn process_require_tags_strict_or_default_tag() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Shopping\nmilk", "tags": ["home"], "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Garden\nweeds", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let dest_dir = tempfile::tempdir().unwrap();
+        let strict = ConversionOptions {
+            require_tags: true,
+            strict: true,
+            ..Default::default()
+        };
+        let error = process(
+            source_file.clone(),
+            dest_dir.path().to_path_buf(),
+            &strict,
+            &SystemClock,
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("note: 'two' has no tags"));
+        assert!(!dest_dir.path().join("Garden.md").exists());
+
+        let dest_dir = tempfile::tempdir().unwrap();
+        let default_tag = ConversionOptions {
+            default_tag: Some(String::from("inbox")),
+            ..strict
+        };
+        let stats = process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &default_tag,
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(2, stats.converted);
+        let garden = fs::read_to_string(dest_dir.path().join("Garden.md")).unwrap();
+        assert!(garden.contains("tags:\n  - inbox\n"));
+        let shopping = fs::read_to_string(dest_dir.path().join("Shopping.md")).unwrap();
+        assert!(shopping.contains("tags:\n  - home\n"));
+    }
+
+    #[test]
+    fn process_warns_about_unknown_fields() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Shopping\nmilk", "extraField": 1, "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Garden\nweeds", "extraField": 2, "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ], "exportVersion": 3}"#,
+        )
+        .unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let error_log = source_dir.path().join("errors.log");
+        let options = ConversionOptions {
+            warn_unknown_fields: true,
+            error_log: Some(error_log.clone()),
+            ..Default::default()
+        };
+
+        let stats = process(
+            source_file.clone(),
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        // the notes are still converted, without the fields
+        assert_eq!(2, stats.converted);
+        let log = fs::read_to_string(&error_log).unwrap();
+        let warnings: Vec<&str> = log
+            .lines()
+            .map(|line| line.split_once(" WARNING: ").unwrap().1)
+            .collect();
+        let source_name = source_file.to_string_lossy();
+        assert_eq!(
+            vec![
+                format!(
+                    "source_file: '{}' has a field 'exportVersion' which is not converted",
+                    source_name
+                ),
+                format!(
+                    "source_file: '{}' has 2 notes with a field 'extraField' which is not converted",
+                    source_name
+                ),
+            ],
+            warnings
+        );
+    }
+
+    #[test]
+    fn process_simplenote_with_order_prefix() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        fs::write(
+            &source_file,
+            r#"{"activeNotes": [
+                {"id": "one", "content": "Shopping\nmilk", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"},
+                {"id": "two", "content": "Shopping\neggs", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ], "trashedNotes": [
+                {"id": "three", "content": "Old", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}
+            ]}"#,
+        )
+        .unwrap();
+        let dest_dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            order_prefix: true,
+            ..Default::default()
+        };
+        process(
+            source_file,
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemClock,
+        )
+        .unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(dest_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            vec!["0001 Shopping.md", "0002 Shopping.md", "0003 Old.md"],
+            names
+        );
+        let first = fs::read_to_string(dest_dir.path().join("0001 Shopping.md")).unwrap();
+        assert!(first.starts_with("---\ntitle: Shopping\n"));
+    }
+
+    #[test]
+    fn process_simplenote_skips_notes_seen_in_earlier_runs() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_file = source_dir.path().join("notes.json");
+        let note = |id: &str| {
+            format!(
+                r#"{{"id": "{}", "content": "{}", "creationDate": "2022-01-13T22:36:18.906Z", "lastModified": "2022-01-14T07:36:50.656Z"}}"#,
+                id, id
+            )
+        };
+        let options = ConversionOptions {
+            seen_file: Some(source_dir.path().join("seen.txt")),
+            ..Default::default()
+        };
+        let dest_dir = tempfile::tempdir().unwrap();
+        let run = |notes: &[&str]| {
+            let notes: Vec<String> = notes.iter().map(|id| note(id)).collect();
+            fs::write(
+                &source_file,
+                format!(r#"{{"activeNotes": [{}]}}"#, notes.join(", ")),
+            )
+            .unwrap();
+            process(
+                source_file.clone(),
+                dest_dir.path().to_path_buf(),
+                &options,
+                &SystemClock,
+            )
+            .unwrap()
+        };
+
+        let first = run(&["first"]);
+        assert_eq!(1, first.converted);
+        let second = run(&["first", "second"]);
+        assert_eq!(1, second.converted);
+        assert_eq!(1, second.skipped);
+        assert!(dest_dir.path().join("second.md").exists());
+        assert!(!dest_dir.path().join("first (1).md").exists());
+    }
 }
@@ -0,0 +1,258 @@
+use super::markdown::{
+    overwrite_markdown, verify_markdown_file, write_into_tag_dirs, write_journal, write_json_array,
+    write_note, Markdown, RunWrites,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::error::Notes2mdError;
+use crate::space::{check_reserve, FreeSpace, SystemFreeSpace};
+use crate::{ConversionOptions, OutputFormat};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// Where converted notes go. A processor hands each note to its sink, which returns the paths
+/// the note can be found at; implement it to keep notes somewhere other than in files.
+pub trait NoteSink {
+    /// Keep `md`, returning every path it went to: its own first, then any copies of it. An
+    /// error fails only this note, which the run counts in its stats before going on to the next.
+    fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError>;
+
+    /// Keep `md` in place of the note an earlier run kept at `existing`, as a manifest records,
+    /// returning where it went as `write` does. Sinks which can't replace a note keep it as a
+    /// new one.
+    fn replace(&mut self, md: &Markdown, _existing: &Path) -> Result<Vec<PathBuf>, Notes2mdError> {
+        self.write(md)
+    }
+
+    /// Fail when the note kept at `file_path` doesn't read back, which means serialization is
+    /// broken, so the run stops. Sinks which don't write files have nothing to check.
+    fn verify(&self, _file_path: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Fail when a note of about `bytes` can't be written without going below
+    /// `reserve_space`, which stops the run. Sinks which don't write to disk have nothing to
+    /// check.
+    fn check_reserve(&self, _bytes: u64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// How many notes were written under another name than their own because it was taken.
+    /// Sinks which don't name notes after their titles have none.
+    fn collisions(&self) -> usize {
+        0
+    }
+}
+
+/// The sink for writing notes into `dest_dir` as `options` asks: into day files with
+/// `journal`, into one JSON array with that format, and otherwise into a file each. Free space
+/// is taken from `space`, and warnings are logged at the time of `clock`.
+pub fn dest_sink<'a>(
+    dest_dir: PathBuf,
+    options: &'a ConversionOptions,
+    space: &'a dyn FreeSpace,
+    clock: &'a dyn Clock,
+) -> Box<dyn NoteSink + 'a> {
+    let files = DestFiles::new(dest_dir, options, space, clock);
+    match (options.journal, options.format) {
+        (true, _) => Box::new(JournalSink(files)),
+        (false, OutputFormat::JsonArray) => Box::new(JsonArraySink(files)),
+        (false, _) => Box::new(FileSink(files)),
+    }
+}
+
+/// What every sink writing into dest_dir keeps: the options it writes with, where it checks
+/// for free space, the clock its warnings are logged by, and the files its run has written so
+/// far, none of which it replaces.
+struct DestFiles<'a> {
+    dest_dir: PathBuf,
+    options: &'a ConversionOptions,
+    space: &'a dyn FreeSpace,
+    clock: &'a dyn Clock,
+    run: RunWrites,
+}
+
+impl<'a> DestFiles<'a> {
+    fn new(
+        dest_dir: PathBuf,
+        options: &'a ConversionOptions,
+        space: &'a dyn FreeSpace,
+        clock: &'a dyn Clock,
+    ) -> DestFiles<'a> {
+        DestFiles {
+            dest_dir,
+            options,
+            space,
+            clock,
+            run: RunWrites::default(),
+        }
+    }
+
+    fn verify(&self, file_path: &Path) -> Result<(), Error> {
+        match self.options.verify_output {
+            true => verify_markdown_file(file_path, self.options),
+            false => Ok(()),
+        }
+    }
+
+    fn check_reserve(&self, bytes: u64) -> Result<(), Error> {
+        check_reserve(&self.dest_dir, bytes, self.options, self.space)
+    }
+}
+
+/// The usual sink, writing each note into its own file in `dest_dir`, or its own textbundle,
+/// copied into a directory for each tag with `duplicate_into_tag_dirs`. The notes written
+/// through one sink are one run, so none of them replaces another.
+pub struct FileSink<'a>(DestFiles<'a>);
+
+impl<'a> FileSink<'a> {
+    pub fn new(dest_dir: PathBuf, options: &'a ConversionOptions) -> FileSink<'a> {
+        FileSink::with_free_space(dest_dir, options, &SystemFreeSpace, &SystemClock)
+    }
+
+    /// A sink which takes the free space in `dest_dir` from `space`, and logs its warnings at
+    /// the time of `clock`.
+    pub fn with_free_space(
+        dest_dir: PathBuf,
+        options: &'a ConversionOptions,
+        space: &'a dyn FreeSpace,
+        clock: &'a dyn Clock,
+    ) -> FileSink<'a> {
+        FileSink(DestFiles::new(dest_dir, options, space, clock))
+    }
+}
+
+impl NoteSink for FileSink<'_> {
+    fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError> {
+        let DestFiles {
+            dest_dir,
+            options,
+            clock,
+            run,
+            ..
+        } = &mut self.0;
+        match options.duplicate_into_tag_dirs {
+            true => write_into_tag_dirs(md.clone(), dest_dir, options, run, *clock),
+            false => {
+                write_note(md.clone(), dest_dir, dest_dir, options, run, *clock).map(|p| vec![p])
+            }
+        }
+        .map_err(Notes2mdError::from)
+    }
+
+    fn replace(&mut self, md: &Markdown, existing: &Path) -> Result<Vec<PathBuf>, Notes2mdError> {
+        overwrite_markdown(md.clone(), existing, self.0.options, self.0.clock)
+            .map(|p| vec![p])
+            .map_err(Notes2mdError::from)
+    }
+
+    fn verify(&self, file_path: &Path) -> Result<(), Error> {
+        self.0.verify(file_path)
+    }
+
+    fn check_reserve(&self, bytes: u64) -> Result<(), Error> {
+        self.0.check_reserve(bytes)
+    }
+
+    fn collisions(&self) -> usize {
+        self.0.run.collisions
+    }
+}
+
+/// Writes each note as a section of the day file for its creation date, see `write_journal`.
+/// A note an earlier run kept is added again rather than replacing its whole day.
+pub struct JournalSink<'a>(DestFiles<'a>);
+
+impl NoteSink for JournalSink<'_> {
+    fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError> {
+        let files = &mut self.0;
+        write_journal(
+            md.clone(),
+            &files.dest_dir,
+            files.options,
+            &mut files.run,
+            files.clock,
+        )
+        .map(|p| vec![p])
+        .map_err(Notes2mdError::from)
+    }
+
+    fn verify(&self, file_path: &Path) -> Result<(), Error> {
+        self.0.verify(file_path)
+    }
+
+    fn check_reserve(&self, bytes: u64) -> Result<(), Error> {
+        self.0.check_reserve(bytes)
+    }
+
+    fn collisions(&self) -> usize {
+        self.0.run.collisions
+    }
+}
+
+/// Writes every note as an object of one JSON array, see `write_json_array`. All notes share
+/// the file, so none can replace the one an earlier run kept.
+pub struct JsonArraySink<'a>(DestFiles<'a>);
+
+impl NoteSink for JsonArraySink<'_> {
+    fn write(&mut self, md: &Markdown) -> Result<Vec<PathBuf>, Notes2mdError> {
+        let files = &mut self.0;
+        write_json_array(md.clone(), &files.dest_dir, files.options, &mut files.run)
+            .map(|p| vec![p])
+            .map_err(Notes2mdError::from)
+    }
+
+    fn verify(&self, file_path: &Path) -> Result<(), Error> {
+        self.0.verify(file_path)
+    }
+
+    fn check_reserve(&self, bytes: u64) -> Result<(), Error> {
+        self.0.check_reserve(bytes)
+    }
+
+    fn collisions(&self) -> usize {
+        self.0.run.collisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn replace_keeps_what_each_sink_can() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let note =
+            |content: &str| Markdown::new("Note", content).with_created("2022-01-14T07:36:50.656Z");
+
+        let options = ConversionOptions::default();
+        let mut sink = dest_sink(
+            dest_dir.path().to_path_buf(),
+            &options,
+            &SystemFreeSpace,
+            &SystemClock,
+        );
+        let written = sink.write(&note("first")).unwrap();
+        let replaced = sink.replace(&note("second"), &written[0]).unwrap();
+        assert_eq!(written, replaced);
+        assert!(fs::read_to_string(&written[0])
+            .unwrap()
+            .ends_with("---\nsecond\n"));
+
+        // a note's section can't be told from the rest of its day, so it is added again
+        let journal = ConversionOptions {
+            journal: true,
+            ..Default::default()
+        };
+        let mut sink = dest_sink(
+            dest_dir.path().to_path_buf(),
+            &journal,
+            &SystemFreeSpace,
+            &SystemClock,
+        );
+        let day = sink.write(&note("first")).unwrap();
+        assert_eq!(day, sink.replace(&note("second"), &day[0]).unwrap());
+        let text = fs::read_to_string(&day[0]).unwrap();
+        assert!(text.contains("first") && text.contains("second"));
+    }
+}
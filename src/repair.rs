@@ -0,0 +1,163 @@
+use crate::clock::Clock;
+use crate::diagnostics;
+use crate::processor::markdown::{parse_markdown, serialize_markdown, write_file};
+use crate::stats::ConversionStats;
+use crate::ConversionOptions;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Rewrite the notes beneath `notes_dir` as the current version would write them, fixing files
+/// from earlier versions: front matter is re-serialized, so doubled `---` lines are dropped and
+/// ambiguous titles quoted, and tags are sorted. Notes which change are counted as converted
+/// and the rest as skipped; markdown files without front matter aren't notes and are left.
+pub fn repair_notes(
+    notes_dir: &Path,
+    options: &ConversionOptions,
+    clock: &dyn Clock,
+) -> Result<ConversionStats, Error> {
+    let mut stats = ConversionStats::default();
+    let mut note_files = Vec::new();
+    find_markdown(notes_dir, &mut note_files)?;
+    note_files.sort();
+
+    for note_file in note_files {
+        let text = fs::read_to_string(&note_file)?;
+        if !text.starts_with("---\n") {
+            continue;
+        }
+        let name = note_file.to_string_lossy().to_string();
+        match repair_text(&text, options) {
+            Ok(repaired) if repaired == text => stats.skipped += 1,
+            Ok(repaired) => {
+                write_file(&note_file, repaired.as_bytes(), options)?;
+                stats.record_converted(&note_file);
+            }
+            Err(e) => {
+                stats.record_failure(&name, &e);
+                diagnostics::error(options, clock, Some(&name), &e.to_string());
+            }
+        }
+    }
+    println!(
+        "repair rewrote {} of {} notes in '{}'",
+        stats.converted,
+        stats.converted + stats.skipped + stats.failed,
+        notes_dir.to_string_lossy()
+    );
+    Ok(stats)
+}
+
+/// The text of a note as it is written now.
+fn repair_text(text: &str, options: &ConversionOptions) -> Result<String, Error> {
+    let mut markdown =
+        parse_markdown(text, options).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if let Some(tags) = &mut markdown.meta.tags {
+        tags.sort();
+    }
+    serialize_markdown(&markdown, options)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("YAML ERROR: {}", e)))
+}
+
+/// Collect the markdown files beneath `dir`, including those in textbundles.
+pub(crate) fn find_markdown(dir: &Path, note_files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_markdown(&path, note_files)?;
+        } else if path.extension().is_some_and(|e| e == "md") {
+            note_files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+
+    #[test]
+    fn repair_quotes_titles_and_sorts_tags() {
+        let notes_dir = tempfile::tempdir().unwrap();
+        let broken = notes_dir.path().join("2021.md");
+        fs::write(
+            &broken,
+            "---\n---\ntitle: 2021\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\ntags:\n  - work\n  - home\n---\nbody\n",
+        )
+        .unwrap();
+        let plain = notes_dir.path().join("README.md");
+        fs::write(&plain, "# Not a note\n").unwrap();
+
+        let stats = repair_notes(
+            notes_dir.path(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(1, stats.converted);
+        assert_eq!(
+            "---\ntitle: \"2021\"\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\ntags:\n  - home\n  - work\n---\nbody\n",
+            fs::read_to_string(&broken).unwrap()
+        );
+        assert_eq!("# Not a note\n", fs::read_to_string(&plain).unwrap());
+
+        // a repaired note is already as it would be written
+        let stats = repair_notes(
+            notes_dir.path(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!((0, 1), (stats.converted, stats.skipped));
+    }
+
+    #[test]
+    fn repair_keeps_front_matter_it_does_not_write() {
+        let notes_dir = tempfile::tempdir().unwrap();
+        let note = notes_dir.path().join("Draft.md");
+        fs::write(
+            &note,
+            "---\nstatus: draft\ntitle: Draft\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\ntags:\n  - work\n  - home\ncover: img.png\nweight: 3\nseries:\n  - one\n---\nbody\n",
+        )
+        .unwrap();
+
+        let stats = repair_notes(
+            notes_dir.path(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(1, stats.converted);
+        assert_eq!(
+            "---\ntitle: Draft\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\ntags:\n  - home\n  - work\nstatus: draft\ncover: img.png\nweight: 3\nseries:\n  - one\n---\nbody\n",
+            fs::read_to_string(&note).unwrap()
+        );
+    }
+
+    #[test]
+    fn repair_keeps_provenance_and_reports_bad_notes() {
+        let notes_dir = tempfile::tempdir().unwrap();
+        let note = notes_dir.path().join("Kept.md");
+        let text = "---\n# converted by notes2md v0.1.0 from simplenote id=one\ntitle: Kept\ncreated: \"2022-01-13T22:36:18.906Z\"\nmodified: \"2022-01-13T22:36:18.906Z\"\n---\nbody\n";
+        fs::write(&note, text).unwrap();
+        fs::write(
+            notes_dir.path().join("Bad.md"),
+            "---\ntitle: [\n---\nbody\n",
+        )
+        .unwrap();
+
+        let stats = repair_notes(
+            notes_dir.path(),
+            &ConversionOptions::default(),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!((0, 1, 1), (stats.converted, stats.skipped, stats.failed));
+        assert_eq!(text, fs::read_to_string(&note).unwrap());
+        assert!(stats.failures[0].message.starts_with("YAML ERROR"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// An editor whose vault layout can be set up in dest_dir.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Editor {
+    Obsidian,
+    Notable,
+}
+
+/// The directories and files, with their contents, which make dest_dir usable by `editor`.
+fn scaffold_entries(editor: Editor) -> (Vec<&'static str>, Vec<(&'static str, &'static str)>) {
+    match editor {
+        Editor::Obsidian => (
+            vec![".obsidian"],
+            vec![
+                (".obsidian/app.json", "{}\n"),
+                (".gitignore", ".obsidian/workspace*.json\n.trash/\n"),
+            ],
+        ),
+        Editor::Notable => (vec!["attachments", "notes"], vec![]),
+    }
+}
+
+/// Write the minimal configuration `editor` expects into `dest_dir`, returning what was created.
+/// Anything which already exists is left as it is.
+pub fn write_scaffold(dest_dir: &Path, editor: Editor) -> Result<Vec<PathBuf>, Error> {
+    let (dirs, files) = scaffold_entries(editor);
+    let mut created = Vec::new();
+    for dir in dirs {
+        let path = dest_dir.join(dir);
+        if !path.exists() {
+            fs::create_dir_all(&path)?;
+            created.push(path);
+        }
+    }
+    for (file, contents) in files {
+        let path = dest_dir.join(file);
+        if !path.exists() {
+            fs::write(&path, contents)?;
+            created.push(path);
+        }
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obsidian_scaffold_is_created_once() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        fs::write(dest_dir.path().join(".gitignore"), "mine\n").unwrap();
+
+        let created = write_scaffold(dest_dir.path(), Editor::Obsidian).unwrap();
+        assert_eq!(
+            vec![
+                dest_dir.path().join(".obsidian"),
+                dest_dir.path().join(".obsidian/app.json"),
+            ],
+            created
+        );
+        assert_eq!(
+            "{}\n",
+            fs::read_to_string(dest_dir.path().join(".obsidian/app.json")).unwrap()
+        );
+        // an existing file is never replaced
+        assert_eq!(
+            "mine\n",
+            fs::read_to_string(dest_dir.path().join(".gitignore")).unwrap()
+        );
+
+        let again = write_scaffold(dest_dir.path(), Editor::Obsidian).unwrap();
+        assert!(again.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Hashes of the content of every note converted so far, kept between runs so a repeated
+/// import skips notes it has already converted. It is stored as one hex hash per line, and
+/// each new hash is appended as soon as its note is written.
+#[derive(Debug, PartialEq)]
+pub struct SeenHashes {
+    seen_file: PathBuf,
+    hashes: HashSet<u64>,
+}
+
+impl SeenHashes {
+    /// Load the hashes from `seen_file`, or start with none if it doesn't exist yet.
+    pub fn load(seen_file: &Path) -> Result<SeenHashes, Error> {
+        let text = match fs::read_to_string(seen_file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut hashes = HashSet::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match u64::from_str_radix(line, 16) {
+                Ok(hash) => hashes.insert(hash),
+                Err(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "seen_file: '{}' line {} is not a content hash",
+                            seen_file.to_string_lossy(),
+                            i + 1
+                        ),
+                    ))
+                }
+            };
+        }
+        Ok(SeenHashes {
+            seen_file: seen_file.to_path_buf(),
+            hashes,
+        })
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.hashes.contains(&hash)
+    }
+
+    /// Record that a note with content hash `hash` was converted.
+    pub fn record(&mut self, hash: u64) -> Result<(), Error> {
+        if self.hashes.insert(hash) {
+            let mut seen_file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.seen_file)?;
+            writeln!(seen_file, "{:016x}", hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// A 64 bit FNV-1a hash of a note's source content. It is spelled out rather than taken from
+/// std, whose hashers may change between releases and so can't be stored.
+pub fn content_hash(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen_hashes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let seen_file = dir.path().join("seen.txt");
+
+        let mut seen = SeenHashes::load(&seen_file).unwrap();
+        let hash = content_hash(b"a note");
+        assert!(!seen.contains(hash));
+        seen.record(hash).unwrap();
+        seen.record(hash).unwrap();
+        assert_eq!(1, fs::read_to_string(&seen_file).unwrap().lines().count());
+
+        let reloaded = SeenHashes::load(&seen_file).unwrap();
+        assert!(reloaded.contains(hash));
+        assert_eq!(seen, reloaded);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(0xcbf29ce484222325, content_hash(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, content_hash(b"a"));
+    }
+
+    #[test]
+    fn load_fails_for_malformed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let seen_file = dir.path().join("seen.txt");
+        fs::write(&seen_file, "00000000000000ff\nnot a hash\n").unwrap();
+        let error = SeenHashes::load(&seen_file).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert!(error.to_string().ends_with("line 2 is not a content hash"));
+    }
+}
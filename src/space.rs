@@ -0,0 +1,80 @@
+use crate::error::Notes2mdError;
+use crate::ConversionOptions;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Source of the free space on the disk holding a directory, so the `reserve_space` check can
+/// be tested without filling a disk.
+pub trait FreeSpace {
+    fn available(&self, dir: &Path) -> Result<u64, Error>;
+}
+
+/// The free space the filesystem reports, used for conversions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemFreeSpace;
+
+impl FreeSpace for SystemFreeSpace {
+    fn available(&self, dir: &Path) -> Result<u64, Error> {
+        fs2::available_space(dir)
+    }
+}
+
+/// Fail when writing about `bytes` more into `dest_dir` would leave less free than
+/// `reserve_space`; without a reserve nothing is checked. Checked before each note is written,
+/// as the estimate made before starting can't see other writers filling the disk.
+pub fn check_reserve(
+    dest_dir: &Path,
+    bytes: u64,
+    options: &ConversionOptions,
+    space: &dyn FreeSpace,
+) -> Result<(), Error> {
+    let reserve = match options.reserve_space {
+        Some(reserve) => reserve,
+        None => return Ok(()),
+    };
+    let available = space.available(dest_dir)?;
+    match available.checked_sub(bytes) {
+        Some(left) if left >= reserve => Ok(()),
+        _ => Err(Notes2mdError::DestNotWritable(format!(
+            "dest_dir: '{}' has {} bytes free, writing the next note would leave less than the {} bytes reserved",
+            dest_dir.to_string_lossy(),
+            available,
+            reserve
+        ))
+        .into_io(ErrorKind::Other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedFreeSpace(u64);
+
+    impl FreeSpace for FixedFreeSpace {
+        fn available(&self, _dir: &Path) -> Result<u64, Error> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn check_reserve_fails_only_below_reserve() {
+        let dest_dir = Path::new("/tmp");
+        let options = ConversionOptions {
+            reserve_space: Some(1000),
+            ..Default::default()
+        };
+        assert!(check_reserve(dest_dir, 100, &options, &FixedFreeSpace(1100)).is_ok());
+
+        let error = check_reserve(dest_dir, 101, &options, &FixedFreeSpace(1100)).unwrap_err();
+        assert_eq!(
+            "dest_dir: '/tmp' has 1100 bytes free, writing the next note would leave less than the 1000 bytes reserved",
+            error.to_string()
+        );
+        assert!(check_reserve(dest_dir, 5000, &options, &FixedFreeSpace(1100)).is_err());
+
+        // without a reserve the space isn't even looked at
+        let no_reserve = ConversionOptions::default();
+        assert!(check_reserve(dest_dir, 5000, &no_reserve, &FixedFreeSpace(0)).is_ok());
+    }
+}
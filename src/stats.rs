@@ -0,0 +1,243 @@
+use crate::ConversionOptions;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind, Write};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A note which couldn't be converted or written, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteFailure {
+    /// the note's id, or its file for sources without ids
+    pub note: String,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+/// Counts of what happened to the notes in a run.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ConversionStats {
+    /// note files written to dest_dir, counting each copy of a note in a tag directory
+    pub converted: usize,
+    /// notes deliberately not written, e.g. when only reporting title collisions
+    pub skipped: usize,
+    /// notes which couldn't be converted or written
+    pub failed: usize,
+    /// note files written under another name because the one for their note was taken
+    pub collisions: usize,
+    /// how many converted notes had each tag
+    #[serde(skip)]
+    pub tags: BTreeMap<String, usize>,
+    /// each note counted in `failed`, in the order they failed
+    #[serde(skip)]
+    pub failures: Vec<NoteFailure>,
+    /// every file a converted note was written to, in the order they were written
+    #[serde(skip)]
+    pub written: Vec<PathBuf>,
+}
+
+impl AddAssign for ConversionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.converted += other.converted;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.collisions += other.collisions;
+        for (tag, count) in other.tags {
+            *self.tags.entry(tag).or_default() += count;
+        }
+        self.failures.extend(other.failures);
+        self.written.extend(other.written);
+    }
+}
+
+impl ConversionStats {
+    /// The stats `run` counts into. A run stopped by an error returns no stats, so when failures
+    /// are summarized at the end of the run, the ones before the error are printed here instead.
+    pub fn collect(
+        options: &ConversionOptions,
+        run: impl FnOnce(&mut ConversionStats) -> Result<(), Error>,
+    ) -> Result<ConversionStats, Error> {
+        collect_summarizing_to(options, &mut io::stdout(), run)
+    }
+
+    /// Count a note which failed, keeping the error for the end of run summary.
+    pub fn record_failure(&mut self, note: &str, error: &Error) {
+        self.failed += 1;
+        self.failures.push(NoteFailure {
+            note: note.to_string(),
+            kind: error.kind(),
+            message: error.to_string(),
+        });
+    }
+
+    /// Every failed note with its error, grouped by the kind of error, or an empty string when
+    /// nothing failed.
+    pub fn failure_summary(&self) -> String {
+        if self.failures.is_empty() {
+            return String::new();
+        }
+        let mut by_kind: BTreeMap<String, Vec<&NoteFailure>> = BTreeMap::new();
+        for failure in &self.failures {
+            by_kind
+                .entry(failure.kind.to_string())
+                .or_default()
+                .push(failure);
+        }
+        let mut summary = format!("{} notes failed:\n", self.failures.len());
+        for (kind, failures) in by_kind {
+            summary.push_str(&format!("{} ({}):\n", kind, failures.len()));
+            for failure in failures {
+                summary.push_str(&format!("    {}: {}\n", failure.note, failure.message));
+            }
+        }
+        summary
+    }
+
+    /// Count a note converted into `file_path`.
+    pub fn record_converted(&mut self, file_path: &Path) {
+        self.converted += 1;
+        self.written.push(file_path.to_path_buf());
+    }
+
+    /// Count the tags of a converted note.
+    pub fn record_tags(&mut self, tags: &[String]) {
+        for tag in tags {
+            *self.tags.entry(tag.clone()).or_default() += 1;
+        }
+    }
+
+    /// Each tag with the number of notes which had it, most common first and then by name.
+    pub fn tag_frequencies(&self) -> Vec<(&str, usize)> {
+        let mut frequencies: Vec<(&str, usize)> = self
+            .tags
+            .iter()
+            .map(|(tag, count)| (tag.as_str(), *count))
+            .collect();
+        frequencies.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        frequencies
+    }
+
+    /// The tag frequencies as a table of counts and tags, one tag per line.
+    pub fn tag_table(&self) -> String {
+        let frequencies = self.tag_frequencies();
+        let width = frequencies
+            .first()
+            .map_or(1, |(_, count)| count.to_string().len());
+        frequencies
+            .iter()
+            .map(|(tag, count)| format!("{:>width$}  {}\n", count, tag, width = width))
+            .collect()
+    }
+
+    /// A single line JSON summary of the run for tooling, with its duration and destination.
+    pub fn to_json(&self, duration: Duration, dest_dir: &Path) -> String {
+        serde_json::json!({
+            "converted": self.converted,
+            "skipped": self.skipped,
+            "failed": self.failed,
+            "collisions": self.collisions,
+            "duration_ms": duration.as_millis() as u64,
+            "dest_dir": dest_dir.to_string_lossy(),
+        })
+        .to_string()
+    }
+}
+
+/// `ConversionStats::collect`, printing the summary of a stopped run to `out`.
+fn collect_summarizing_to(
+    options: &ConversionOptions,
+    out: &mut dyn Write,
+    run: impl FnOnce(&mut ConversionStats) -> Result<(), Error>,
+) -> Result<ConversionStats, Error> {
+    let mut stats = ConversionStats::default();
+    match run(&mut stats) {
+        Ok(_) => Ok(stats),
+        Err(e) => {
+            if options.summary_failures {
+                // the run's error is already failing it, so this one isn't reported over it
+                let _ = write!(out, "{}", stats.failure_summary());
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn tag_frequencies_most_common_first() {
+        let mut stats = ConversionStats::default();
+        stats.record_tags(&tags(&["work", "home"]));
+        stats.record_tags(&tags(&["work"]));
+        let mut other = ConversionStats::default();
+        other.record_tags(&tags(&["work", "garden", "home"]));
+        stats += other;
+
+        assert_eq!(
+            vec![("work", 3), ("home", 2), ("garden", 1)],
+            stats.tag_frequencies()
+        );
+        assert_eq!("3  work\n2  home\n1  garden\n", stats.tag_table());
+    }
+
+    #[test]
+    fn failure_summary_groups_by_kind() {
+        let mut stats = ConversionStats::default();
+        assert_eq!("", stats.failure_summary());
+        stats.record_failure(
+            "one",
+            &Error::new(ErrorKind::InvalidData, "title: '' is bad"),
+        );
+        stats.record_failure("two", &Error::new(ErrorKind::NotFound, "no such file"));
+        let mut other = ConversionStats::default();
+        other.record_failure("three", &Error::new(ErrorKind::InvalidData, "YAML ERROR"));
+        stats += other;
+
+        assert_eq!(3, stats.failed);
+        let expected = "3 notes failed:
+entity not found (1):
+    two: no such file
+invalid data (2):
+    one: title: '' is bad
+    three: YAML ERROR
+";
+        assert_eq!(expected, stats.failure_summary());
+    }
+
+    #[test]
+    fn collect_summarizes_failures_of_a_stopped_run() {
+        let run = |stats: &mut ConversionStats| {
+            stats.record_failure(
+                "one",
+                &Error::new(ErrorKind::InvalidData, "title: '' is bad"),
+            );
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                "note: 'two' has no tags",
+            ))
+        };
+        let options = ConversionOptions {
+            summary_failures: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let result = collect_summarizing_to(&options, &mut out, run);
+        assert_eq!("note: 'two' has no tags", result.unwrap_err().to_string());
+        assert_eq!(
+            "1 notes failed:\ninvalid data (1):\n    one: title: '' is bad\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        let result = collect_summarizing_to(&ConversionOptions::default(), &mut out, run);
+        assert!(result.is_err());
+        assert!(out.is_empty());
+    }
+}